@@ -0,0 +1,42 @@
+use std::collections::hash_map::DefaultHasher;
+use std::env;
+use std::fs;
+use std::hash::Hasher;
+use std::path::{Path, PathBuf};
+
+/// Hashes the files in `dir` and its subdirectories, sorted by their paths.
+fn hash_dir(dir: &Path, hasher: &mut DefaultHasher) {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)
+        .expect("couldn't read the source directory")
+        .map(|entry| entry.expect("couldn't read the source directory").path())
+        .collect();
+    entries.sort();
+
+    for path in entries {
+        if path.is_dir() {
+            hash_dir(&path, hasher);
+        } else {
+            hasher.write(path.to_string_lossy().as_bytes());
+            hasher.write(&fs::read(&path).expect("couldn't read a source file"));
+        }
+    }
+}
+
+/// Sets `RIP8_BUILD_ID` to a hash of the sources and the locked dependencies. The block cache is
+/// only used by builds with the same id because other code might be generated.
+fn main() {
+    let crate_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-changed=Cargo.lock");
+
+    let mut hasher = DefaultHasher::new();
+    hash_dir(&crate_dir.join("src"), &mut hasher);
+    // the lock file is missing if rip8 is a dependency, its version is part of the path then
+    match fs::read(crate_dir.join("Cargo.lock")) {
+        Ok(lock) => hasher.write(&lock),
+        Err(_) => hasher.write(crate_dir.to_string_lossy().as_bytes()),
+    }
+
+    println!("cargo:rustc-env=RIP8_BUILD_ID={:016x}", hasher.finish());
+}
@@ -0,0 +1,131 @@
+#ifndef RIP8_H
+#define RIP8_H
+
+/* Generated by cbindgen from src/ffi.rs, don't edit it by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * The function succeeded.
+ */
+#define RIP8_OK 0
+
+/**
+ * The emulated program exited, for example with `00FD`.
+ */
+#define RIP8_EXITED 1
+
+/**
+ * The function failed or panicked, see `rip8_last_error`. A panic also unloads the ROM.
+ */
+#define RIP8_ERROR -1
+
+/**
+ * An emulator which is used through the C ABI.
+ */
+typedef struct Rip8 Rip8;
+
+/**
+ * What happened in the frame which got executed by `rip8_step_frame`.
+ */
+typedef struct Rip8Frame {
+  /**
+   * The amount of executed CHIP-8 instructions.
+   */
+  uint64_t instructions;
+  /**
+   * Set while the sound timer is active and the buzzer should be audible.
+   */
+  bool sound;
+} Rip8Frame;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Creates an emulator without a ROM, it has to be freed with `rip8_destroy`. Returns null if it
+ * couldn't be created.
+ */
+struct Rip8 *rip8_create(void);
+
+/**
+ * Frees an emulator of `rip8_create`.
+ *
+ * # Safety
+ * `rip8` has to be returned by `rip8_create` and can't be used afterwards. It may be null.
+ */
+void rip8_destroy(struct Rip8 *rip8);
+
+/**
+ * Resets the emulator and loads the `size` bytes of `rom`. The quirks and the speed are taken
+ * from the ROM database if the ROM is known.
+ *
+ * # Safety
+ * `rip8` has to be a valid emulator and `rom` has to point to `size` readable bytes.
+ */
+int rip8_load_rom(struct Rip8 *rip8, const uint8_t *rom, size_t size);
+
+/**
+ * Sets the keys which are held in the following frames, bit `n` is set if key `n` is pressed.
+ *
+ * # Safety
+ * `rip8` has to be a valid emulator.
+ */
+void rip8_set_keys(struct Rip8 *rip8, uint16_t keys);
+
+/**
+ * Executes one frame and decrements the timers. Returns `RIP8_EXITED` if the program exited and
+ * `RIP8_ERROR` if it did something invalid. `frame` may be null.
+ *
+ * # Safety
+ * `rip8` has to be a valid emulator and `frame` has to be null or writable.
+ */
+int rip8_step_frame(struct Rip8 *rip8, struct Rip8Frame *frame);
+
+/**
+ * Returns the pixels of the screen row by row and stores the current resolution in `width` and
+ * `height`. Every pixel is one byte which stores one bit per XO-CHIP plane. The pointer is valid
+ * until the next call which changes the emulator, it's null if no ROM is loaded.
+ *
+ * # Safety
+ * `rip8` has to be a valid emulator, `width` and `height` have to be null or writable.
+ */
+const uint8_t *rip8_get_framebuffer(struct Rip8 *rip8, size_t *width, size_t *height);
+
+/**
+ * Serializes the emulated machine into `buffer` if it has at least `size` bytes and returns the
+ * size of the state. Call it with a null `buffer` to get the needed size. Returns `0` on errors.
+ *
+ * # Safety
+ * `rip8` has to be a valid emulator and `buffer` has to be null or point to `size` writable
+ * bytes.
+ */
+size_t rip8_save_state(struct Rip8 *rip8, uint8_t *buffer, size_t size);
+
+/**
+ * Restores a state of `rip8_save_state`, the ROM has to be loaded before.
+ *
+ * # Safety
+ * `rip8` has to be a valid emulator and `data` has to point to `size` readable bytes.
+ */
+int rip8_load_state(struct Rip8 *rip8, const uint8_t *data, size_t size);
+
+/**
+ * Returns the message of the last error or null if there wasn't one. The string is valid until
+ * the next error happens.
+ *
+ * # Safety
+ * `rip8` has to be a valid emulator.
+ */
+const char *rip8_last_error(const struct Rip8 *rip8);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* RIP8_H */
@@ -0,0 +1,466 @@
+//! Ahead-of-time compilation of a ROM into a standalone executable.
+//!
+//! The reachable blocks are found by the [control flow analysis](crate::cfg) and compiled by the
+//! x86 backend into one ELF object, together with the ROM, its settings and a table of the
+//! blocks. The object is linked with the static rip8 library, which provides the display, the
+//! input and the timers. The `main` of the object passes the game to [rip8_aot_main]. Blocks
+//! which are only reached through `BNNN` can't be found statically, the threaded backend
+//! interprets them, so the executable never maps executable memory.
+
+use log::debug;
+use memoffset::offset_of;
+use object::write::{Object, Relocation, StandardSection, Symbol, SymbolSection};
+use object::{
+    Architecture, BinaryFormat, Endianness, RelocationEncoding, RelocationFlags, RelocationKind,
+    SymbolFlags, SymbolKind, SymbolScope,
+};
+
+use crate::backend::Backend;
+use crate::block_cache::{self, BlockCache, StoredBlock};
+use crate::cache::CompileBlock;
+use crate::cfg::Cfg;
+use crate::chip8::Chip8;
+use crate::display::TerminalMode;
+use crate::quirks::Quirks;
+use crate::{Error, Options, Result};
+
+use std::env;
+use std::fs;
+use std::mem::size_of;
+use std::os::raw::c_int;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::slice;
+
+/// The system libraries which the static rip8 library needs, see `--print native-static-libs`.
+const NATIVE_LIBS: [&str; 7] = [
+    "-ldl",
+    "-lgcc_s",
+    "-lutil",
+    "-lrt",
+    "-lpthread",
+    "-lm",
+    "-lc",
+];
+
+/// The `main` of the executable, `lea rdi, [rip + game]` and `jmp rip8_aot_main`. The
+/// displacements are filled in by the linker.
+const MAIN: [u8; 12] = [0x48, 0x8d, 0x3d, 0, 0, 0, 0, 0xe9, 0, 0, 0, 0];
+/// The offsets of the displacements of the game and of [rip8_aot_main] in [MAIN].
+const MAIN_GAME_OFFSET: u64 = 3;
+const MAIN_ENTRY_OFFSET: u64 = 8;
+
+/// A block in the executable which was compiled ahead of time.
+#[derive(Debug)]
+#[repr(C)]
+pub struct LinkedBlock {
+    pub start: u64,
+    pub end: u64,
+    pub instructions: u64,
+    pub code: *const u8,
+}
+
+/// The game in the executable, its fields are written by [Game::to_object].
+#[derive(Debug)]
+#[repr(C)]
+pub struct LinkedGame {
+    /// The [build hash](block_cache::build_hash) of the compiler, the code only works with the
+    /// same build.
+    pub build_hash: *const u8,
+    /// The quirks in the format of [Quirks::to_bytes].
+    pub quirks: *const u8,
+    pub speed: u64,
+    pub rom: *const u8,
+    pub rom_len: u64,
+    pub blocks: *const LinkedBlock,
+    pub block_count: u64,
+}
+
+impl LinkedGame {
+    /// Returns an error if the blocks were compiled by another build of rip8.
+    ///
+    /// # Safety
+    /// The game has to be written by [Game::to_object].
+    pub unsafe fn check_build(&self) -> Result<()> {
+        let build_hash = slice::from_raw_parts(self.build_hash, block_cache::BUILD_HASH_SIZE);
+        if build_hash != block_cache::build_hash() {
+            return Err(Error::Aot(
+                "the executable was linked with another build of rip8 than it got compiled with"
+                    .to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// # Safety
+    /// The game has to be written by [Game::to_object].
+    pub unsafe fn quirks(&self) -> Quirks {
+        let bytes = slice::from_raw_parts(self.quirks, Quirks::ENCODED_SIZE);
+        Quirks::from_bytes(bytes.try_into().expect("the quirks have the encoded size"))
+    }
+
+    /// # Safety
+    /// The game has to be written by [Game::to_object].
+    pub unsafe fn rom(&self) -> &[u8] {
+        slice::from_raw_parts(self.rom, self.rom_len as usize)
+    }
+
+    /// # Safety
+    /// The game has to be written by [Game::to_object] and [LinkedGame::check_build] has to
+    /// succeed.
+    pub unsafe fn blocks(&self) -> Vec<CompileBlock> {
+        slice::from_raw_parts(self.blocks, self.block_count as usize)
+            .iter()
+            .map(|block| {
+                CompileBlock::linked(block.code, block.start..block.end, block.instructions)
+            })
+            .collect()
+    }
+}
+
+/// The usage of the executables of `rip8 aot`.
+const USAGE: &str =
+    "usage: [--terminal [MODE]] [--frames FRAMES] [--screenshot-at-frame FRAME PATH]";
+
+/// Parses the few options which the executables of `rip8 aot` accept, the other settings are
+/// taken from the game and the config file.
+fn parse_args(args: impl IntoIterator<Item = String>) -> std::result::Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = args.into_iter().peekable();
+    let number = |value: Option<String>| -> std::result::Result<u64, String> {
+        value
+            .and_then(|value| value.parse().ok())
+            .ok_or_else(|| USAGE.to_string())
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--terminal" => {
+                let mode = match args.next_if(|mode| !mode.starts_with("--")) {
+                    Some(mode) => mode.parse()?,
+                    None => TerminalMode::default(),
+                };
+                options.terminal = Some(mode);
+            }
+            "--frames" => options.frames = Some(number(args.next())?),
+            "--screenshot-at-frame" => {
+                let frame = number(args.next())?;
+                let path = args.next().ok_or_else(|| USAGE.to_string())?;
+                options.screenshot_at_frame = Some((frame, path.into()));
+            }
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+
+    Ok(options)
+}
+
+/// The entry point of the executables of `rip8 aot`, called by their `main`. Returns the exit
+/// code of the process.
+///
+/// # Safety
+/// `game` has to be written by [Game::to_object].
+#[no_mangle]
+pub unsafe extern "C" fn rip8_aot_main(game: *const LinkedGame) -> c_int {
+    let _ = env_logger::try_init();
+
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}", err);
+            return 2;
+        }
+    };
+
+    match panic::catch_unwind(AssertUnwindSafe(|| crate::run_linked(&*game, options))) {
+        Ok(Ok(())) => 0,
+        Ok(Err(err)) => {
+            eprintln!("error: {}", err);
+            1
+        }
+        // the panic message got printed already
+        Err(_) => 101,
+    }
+}
+
+/// A ROM with its settings and compiled blocks, ready to be linked into an executable.
+#[derive(Debug, Clone)]
+pub struct Game {
+    pub rom: Vec<u8>,
+    pub quirks: Quirks,
+    pub speed: u64,
+    pub blocks: Vec<StoredBlock>,
+}
+
+impl Game {
+    /// Compiles all blocks of `rom` which are reachable from the start address.
+    pub fn compile(rom: Vec<u8>, quirks: Quirks, speed: u64) -> Result<Self> {
+        if !cfg!(all(target_arch = "x86_64", target_os = "linux")) {
+            return Err(Error::Aot(
+                "executables can only be compiled on x86-64 Linux".to_string(),
+            ));
+        }
+
+        let mut chip8 = Chip8::builder()
+            .rom(rom.clone())
+            .quirks(quirks)
+            .backend(Backend::X86)
+            .build()?;
+        chip8.set_block_cache(BlockCache::default());
+
+        let cfg = Cfg::analyze(chip8.memory(), rom.len(), &quirks);
+        for (start, _) in cfg.compiled_blocks() {
+            chip8.precompile(start)?;
+        }
+
+        let mut blocks: Vec<StoredBlock> = chip8
+            .block_cache()
+            .map(|block_cache| block_cache.blocks().cloned().collect())
+            .unwrap_or_default();
+        blocks.sort_by_key(|block| block.addrs.start);
+
+        Ok(Self {
+            rom,
+            quirks,
+            speed,
+            blocks,
+        })
+    }
+
+    /// Returns an x86-64 ELF object with the compiled blocks, a [LinkedGame] which refers to
+    /// them and a `main` which runs it.
+    pub fn to_object(&self) -> Result<Vec<u8>> {
+        let mut object = Object::new(BinaryFormat::Elf, Architecture::X86_64, Endianness::Little);
+        let text = object.section_id(StandardSection::Text);
+        let rodata = object.section_id(StandardSection::ReadOnlyData);
+        let tables = object.section_id(StandardSection::ReadOnlyDataWithRel);
+        let text_symbol = object.section_symbol(text);
+        let rodata_symbol = object.section_symbol(rodata);
+        let tables_symbol = object.section_symbol(tables);
+
+        let pointer = |offset: usize, symbol, addend: u64| Relocation {
+            offset: offset as u64,
+            symbol,
+            addend: addend as i64,
+            flags: RelocationFlags::Generic {
+                kind: RelocationKind::Absolute,
+                encoding: RelocationEncoding::Generic,
+                size: 64,
+            },
+        };
+        let mut relocations = Vec::new();
+
+        let mut block_table = Vec::new();
+        for block in &self.blocks {
+            let code = object.append_section_data(text, &block.code, 16);
+            let entry = block_table.len();
+            for value in [block.addrs.start, block.addrs.end, block.instructions, 0] {
+                block_table.extend_from_slice(&value.to_le_bytes());
+            }
+            relocations.push(pointer(
+                entry + offset_of!(LinkedBlock, code),
+                text_symbol,
+                code,
+            ));
+        }
+        let block_table_offset = object.append_section_data(tables, &block_table, 8);
+        for relocation in &mut relocations {
+            relocation.offset += block_table_offset;
+        }
+
+        let build_hash = object.append_section_data(rodata, &block_cache::build_hash(), 1);
+        let quirks = object.append_section_data(rodata, &self.quirks.to_bytes(), 1);
+        let rom = object.append_section_data(rodata, &self.rom, 1);
+
+        let mut game = vec![0; size_of::<LinkedGame>()];
+        let mut set = |offset: usize, value: u64| {
+            game[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+        };
+        set(offset_of!(LinkedGame, speed), self.speed);
+        set(offset_of!(LinkedGame, rom_len), self.rom.len() as u64);
+        set(
+            offset_of!(LinkedGame, block_count),
+            self.blocks.len() as u64,
+        );
+        let game_offset = object.append_section_data(tables, &game, 8);
+        let game_pointers = [
+            (
+                offset_of!(LinkedGame, build_hash),
+                rodata_symbol,
+                build_hash,
+            ),
+            (offset_of!(LinkedGame, quirks), rodata_symbol, quirks),
+            (offset_of!(LinkedGame, rom), rodata_symbol, rom),
+            (
+                offset_of!(LinkedGame, blocks),
+                tables_symbol,
+                block_table_offset,
+            ),
+        ];
+        for (offset, symbol, addend) in game_pointers {
+            let mut relocation = pointer(offset, symbol, addend);
+            relocation.offset += game_offset;
+            relocations.push(relocation);
+        }
+        for relocation in relocations {
+            object
+                .add_relocation(tables, relocation)
+                .map_err(|err| Error::Aot(err.to_string()))?;
+        }
+
+        let entry = object.add_symbol(Symbol {
+            name: b"rip8_aot_main".to_vec(),
+            value: 0,
+            size: 0,
+            kind: SymbolKind::Text,
+            scope: SymbolScope::Dynamic,
+            weak: false,
+            section: SymbolSection::Undefined,
+            flags: SymbolFlags::None,
+        });
+        let main = object.add_symbol(Symbol {
+            name: b"main".to_vec(),
+            value: 0,
+            size: 0,
+            kind: SymbolKind::Text,
+            scope: SymbolScope::Linkage,
+            weak: false,
+            section: SymbolSection::Undefined,
+            flags: SymbolFlags::None,
+        });
+        let main_offset = object.add_symbol_data(main, text, &MAIN, 16);
+        // the displacements are relative to the end of the instructions
+        let main_relocations = [
+            (
+                MAIN_GAME_OFFSET,
+                tables_symbol,
+                game_offset as i64 - 4,
+                RelocationKind::Relative,
+            ),
+            (MAIN_ENTRY_OFFSET, entry, -4, RelocationKind::PltRelative),
+        ];
+        for (offset, symbol, addend, kind) in main_relocations {
+            let relocation = Relocation {
+                offset: main_offset + offset,
+                symbol,
+                addend,
+                flags: RelocationFlags::Generic {
+                    kind,
+                    encoding: RelocationEncoding::Generic,
+                    size: 32,
+                },
+            };
+            object
+                .add_relocation(text, relocation)
+                .map_err(|err| Error::Aot(err.to_string()))?;
+        }
+
+        object.write().map_err(|err| Error::Aot(err.to_string()))
+    }
+
+    /// Writes the object of the game next to `output` and links it with the static rip8 library
+    /// `runtime` into the executable `output`. The C compiler is taken from `$CC`, `cc` by
+    /// default.
+    pub fn link<P: AsRef<Path>, R: AsRef<Path>>(&self, output: P, runtime: R) -> Result<()> {
+        let output = output.as_ref();
+        let runtime = runtime.as_ref();
+        if !runtime.is_file() {
+            return Err(Error::Aot(format!(
+                "the runtime '{}' doesn't exist, it's built as librip8.a together with rip8",
+                runtime.display()
+            )));
+        }
+
+        let object_path = output.with_extension("o");
+        fs::write(&object_path, self.to_object()?).map_err(|source| Error::Write {
+            path: object_path.clone(),
+            source,
+        })?;
+
+        let compiler = env::var_os("CC").unwrap_or_else(|| "cc".into());
+        let result = Command::new(&compiler)
+            .arg("-o")
+            .arg(output)
+            .arg(&object_path)
+            .arg(runtime)
+            .arg("-Wl,--gc-sections")
+            .args(NATIVE_LIBS)
+            .output();
+        let _ = fs::remove_file(&object_path);
+
+        let compiler = compiler.to_string_lossy();
+        let result =
+            result.map_err(|err| Error::Aot(format!("couldn't run '{}': {}", compiler, err)))?;
+        if !result.status.success() {
+            return Err(Error::Aot(format!(
+                "'{}' couldn't link the executable: {}",
+                compiler,
+                String::from_utf8_lossy(&result.stderr).trim()
+            )));
+        }
+        debug!(
+            "Linked '{}' with {} compiled blocks",
+            output.display(),
+            self.blocks.len()
+        );
+
+        Ok(())
+    }
+
+    /// The static rip8 library next to the running executable, where cargo puts it.
+    pub fn default_runtime() -> Option<PathBuf> {
+        Some(env::current_exe().ok()?.with_file_name("librip8.a"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> std::result::Result<Options, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parses_the_arguments() {
+        let options = args(&["--terminal", "--frames", "10"]).unwrap();
+        assert_eq!(options.terminal, Some(TerminalMode::HalfBlocks));
+        assert_eq!(options.frames, Some(10));
+
+        let options = args(&[
+            "--screenshot-at-frame",
+            "5",
+            "out.png",
+            "--terminal",
+            "braille",
+        ]);
+        let options = options.unwrap();
+        assert_eq!(options.terminal, Some(TerminalMode::Braille));
+        assert_eq!(
+            options.screenshot_at_frame,
+            Some((5, PathBuf::from("out.png")))
+        );
+
+        assert!(args(&["--frames"]).is_err());
+        assert!(args(&["--frames", "many"]).is_err());
+        assert!(args(&["--screenshot-at-frame", "5"]).is_err());
+        assert!(args(&["--scale", "4"]).is_err());
+    }
+
+    #[test]
+    fn writes_a_relocatable_object() {
+        let game = Game::compile(vec![0x12, 0x00], Quirks::VIP, 10).unwrap();
+        assert_eq!(game.blocks.len(), 1);
+
+        let object = game.to_object().unwrap();
+        assert_eq!(&object[..4], b"\x7fELF");
+        // ET_REL
+        assert_eq!(u16::from_le_bytes([object[16], object[17]]), 1);
+        let contains = |name: &[u8]| object.windows(name.len()).any(|window| window == name);
+        assert!(contains(b"rip8_aot_main\0"));
+        assert!(contains(b"main\0"));
+        assert!(contains(&game.blocks[0].code));
+    }
+}
@@ -0,0 +1,291 @@
+//! Allocates the machine code of compiled blocks from large regions instead of mapping a page for
+//! every block. The regions are switched between writable and executable as a whole.
+
+use log::debug;
+use memmap2::MmapMut;
+
+use crate::{Error, Result};
+
+use std::collections::BTreeMap;
+use std::io;
+
+/// The size of a region, blocks which are bigger get a region of their own.
+pub const REGION_SIZE: usize = 256 * 1024;
+/// The alignment of the start of every block.
+const ALIGNMENT: usize = 16;
+
+/// The location of the code of a block in a [CodeArena].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CodeSpan {
+    region: usize,
+    offset: usize,
+    len: usize,
+    /// The address of the first byte, stored as integer to keep the blocks `Send`.
+    addr: usize,
+}
+
+impl CodeSpan {
+    pub fn as_ptr(&self) -> *const u8 {
+        self.addr as *const u8
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// The usage of the regions of a [CodeArena], in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct ArenaStats {
+    pub regions: usize,
+    pub capacity: usize,
+    pub used: usize,
+    pub free: usize,
+    /// The amount of separate free chunks, a fresh region has one.
+    pub free_chunks: usize,
+    pub largest_free_chunk: usize,
+}
+
+impl ArenaStats {
+    /// Returns how much of the free space isn't part of the largest free chunk, from `0.0` for
+    /// none to almost `1.0` if it's scattered into tiny pieces.
+    pub fn fragmentation(&self) -> f64 {
+        if self.free == 0 {
+            0.0
+        } else {
+            1.0 - self.largest_free_chunk as f64 / self.free as f64
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Region {
+    map: MmapMut,
+    executable: bool,
+    /// The offsets and lengths of the free chunks, adjacent chunks are merged.
+    free: BTreeMap<usize, usize>,
+}
+
+impl Region {
+    fn new(size: usize) -> Result<Self> {
+        let map = MmapMut::map_anon(size).map_err(Error::ExecutableMemory)?;
+        debug!("Mapped a code region of {} bytes", size);
+
+        Ok(Self {
+            map,
+            executable: false,
+            free: BTreeMap::from([(0, size)]),
+        })
+    }
+
+    /// Takes `len` bytes from the first free chunk which is big enough.
+    fn take(&mut self, len: usize) -> Option<usize> {
+        let (&offset, &chunk) = self.free.iter().find(|(_, &chunk)| chunk >= len)?;
+
+        self.free.remove(&offset);
+        if chunk > len {
+            self.free.insert(offset + len, chunk - len);
+        }
+
+        Some(offset)
+    }
+
+    fn give_back(&mut self, offset: usize, len: usize) {
+        let (mut start, mut end) = (offset, offset + len);
+
+        if let Some((&prev, &prev_len)) = self.free.range(..offset).next_back() {
+            if prev + prev_len == start {
+                self.free.remove(&prev);
+                start = prev;
+            }
+        }
+        if let Some(next_len) = self.free.remove(&end) {
+            end += next_len;
+        }
+
+        self.free.insert(start, end - start);
+    }
+
+    fn protect(&mut self, executable: bool) -> Result<()> {
+        if self.executable == executable {
+            return Ok(());
+        }
+
+        let protection = if executable {
+            libc::PROT_READ | libc::PROT_EXEC
+        } else {
+            libc::PROT_READ | libc::PROT_WRITE
+        };
+        // the mapping stays valid if this fails, unlike with `MmapMut::make_exec`
+        let result =
+            unsafe { libc::mprotect(self.map.as_mut_ptr().cast(), self.map.len(), protection) };
+        if result != 0 {
+            return Err(Error::ExecutableMemory(io::Error::last_os_error()));
+        }
+
+        self.executable = executable;
+        Ok(())
+    }
+}
+
+/// Executable memory for the compiled blocks. Blocks are written with [CodeArena::alloc] and can
+/// be executed after [CodeArena::seal].
+#[derive(Debug, Default)]
+pub struct CodeArena {
+    regions: Vec<Region>,
+}
+
+impl CodeArena {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Copies `code` into a writable region. The regions which are written to can't be executed
+    /// until [CodeArena::seal] is called.
+    pub fn alloc(&mut self, code: &[u8]) -> Result<CodeSpan> {
+        let len = code.len().max(1).next_multiple_of(ALIGNMENT);
+
+        let found = self
+            .regions
+            .iter_mut()
+            .enumerate()
+            .find_map(|(index, region)| region.take(len).map(|offset| (index, offset)));
+        let (index, offset) = match found {
+            Some(found) => found,
+            None => {
+                let mut region = Region::new(len.max(REGION_SIZE))?;
+                let offset = region.take(len).expect("a new region is big enough");
+                self.regions.push(region);
+                (self.regions.len() - 1, offset)
+            }
+        };
+
+        let region = &mut self.regions[index];
+        if let Err(err) = region.protect(false) {
+            region.give_back(offset, len);
+            return Err(err);
+        }
+        region.map[offset..offset + code.len()].copy_from_slice(code);
+
+        Ok(CodeSpan {
+            region: index,
+            offset,
+            len,
+            addr: region.map.as_ptr() as usize + offset,
+        })
+    }
+
+    /// Returns the space of `span` for new blocks, the code mustn't be executed anymore.
+    pub fn free(&mut self, span: CodeSpan) {
+        self.regions[span.region].give_back(span.offset, span.len);
+    }
+
+    /// Returns the code of `span`, including the padding up to the alignment.
+    pub fn code(&self, span: CodeSpan) -> &[u8] {
+        &self.regions[span.region].map[span.offset..span.offset + span.len]
+    }
+
+    /// Returns `true` if the region of `span` is executable, i.e. sealed.
+    pub fn is_executable(&self, span: CodeSpan) -> bool {
+        self.regions[span.region].executable
+    }
+
+    /// Makes all regions which were written to executable again.
+    pub fn seal(&mut self) -> Result<()> {
+        self.regions
+            .iter_mut()
+            .try_for_each(|region| region.protect(true))
+    }
+
+    pub fn stats(&self) -> ArenaStats {
+        let mut stats = ArenaStats {
+            regions: self.regions.len(),
+            ..ArenaStats::default()
+        };
+
+        for region in &self.regions {
+            stats.capacity += region.map.len();
+            for &chunk in region.free.values() {
+                stats.free += chunk;
+                stats.free_chunks += 1;
+                stats.largest_free_chunk = stats.largest_free_chunk.max(chunk);
+            }
+        }
+        stats.used = stats.capacity - stats.free;
+
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocates_aligned_blocks() {
+        let mut arena = CodeArena::new();
+        let first = arena.alloc(&[0xc3]).unwrap();
+        let second = arena.alloc(&[0x90; 17]).unwrap();
+
+        assert_eq!(first.len(), ALIGNMENT);
+        assert_eq!(second.len(), 2 * ALIGNMENT);
+        assert_eq!(second.as_ptr() as usize % ALIGNMENT, 0);
+        assert_eq!(&arena.code(second)[..17], &[0x90; 17]);
+
+        let stats = arena.stats();
+        assert_eq!(stats.regions, 1);
+        assert_eq!(stats.used, 3 * ALIGNMENT);
+        assert_eq!(stats.free_chunks, 1);
+    }
+
+    #[test]
+    fn merges_freed_chunks() {
+        let mut arena = CodeArena::new();
+        let spans: Vec<CodeSpan> = (0..4).map(|_| arena.alloc(&[0; 16]).unwrap()).collect();
+
+        arena.free(spans[0]);
+        arena.free(spans[2]);
+        assert_eq!(arena.stats().free_chunks, 3);
+
+        // fills the gap between the two freed chunks
+        arena.free(spans[1]);
+        assert_eq!(arena.stats().free_chunks, 2);
+        arena.free(spans[3]);
+        let stats = arena.stats();
+        assert_eq!(stats.free_chunks, 1);
+        assert_eq!(stats.free, REGION_SIZE);
+        assert_eq!(stats.fragmentation(), 0.0);
+
+        // the freed space is reused from the start
+        assert_eq!(arena.alloc(&[0; 48]).unwrap().as_ptr(), spans[0].as_ptr());
+    }
+
+    #[test]
+    fn big_blocks_get_their_own_region() {
+        let mut arena = CodeArena::new();
+        arena.alloc(&[0; 16]).unwrap();
+        let big = arena.alloc(&vec![0; REGION_SIZE + 1]).unwrap();
+
+        let stats = arena.stats();
+        assert_eq!(stats.regions, 2);
+        assert_eq!(big.len(), REGION_SIZE + ALIGNMENT);
+        assert_eq!(stats.capacity, 2 * REGION_SIZE + ALIGNMENT);
+    }
+
+    #[test]
+    fn alloc_makes_the_region_writable() {
+        let mut arena = CodeArena::new();
+        let first = arena.alloc(&[0xc3]).unwrap();
+        assert!(!arena.is_executable(first));
+
+        arena.seal().unwrap();
+        assert!(arena.is_executable(first));
+
+        arena.alloc(&[0xc3]).unwrap();
+        assert!(!arena.is_executable(first));
+    }
+}
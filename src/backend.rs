@@ -0,0 +1,55 @@
+use serde::Deserialize;
+
+use std::fmt;
+use std::str::FromStr;
+
+/// The way in which the CHIP-8 instructions are executed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// Compiles every block into x86-64 machine code.
+    #[default]
+    X86,
+    /// Lowers every block to Cranelift IR, which optimizes it and generates the code for the
+    /// host. Only available with the `cranelift` feature.
+    Cranelift,
+    /// Pre-decodes every block into closures, which doesn't need executable memory. It's used
+    /// automatically if the other backends can't map any.
+    Threaded,
+}
+
+impl Backend {
+    pub const NAMES: [&'static str; 3] = ["x86", "cranelift", "threaded"];
+
+    /// Returns `false` if the backend was left out of this build.
+    pub fn is_available(&self) -> bool {
+        match self {
+            Self::X86 => true,
+            Self::Cranelift => cfg!(feature = "cranelift"),
+            Self::Threaded => true,
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::X86 => write!(f, "x86"),
+            Self::Cranelift => write!(f, "cranelift"),
+            Self::Threaded => write!(f, "threaded"),
+        }
+    }
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_lowercase().as_str() {
+            "x86" | "x86-64" | "x86_64" | "jit" => Ok(Self::X86),
+            "cranelift" | "clif" => Ok(Self::Cranelift),
+            "threaded" | "closures" | "interpreter" => Ok(Self::Threaded),
+            _ => Err(format!("unknown backend '{}'", name)),
+        }
+    }
+}
@@ -0,0 +1,343 @@
+//! Keeps the machine code of compiled blocks on disk, so the next run of the same ROM doesn't
+//! have to compile them again. The code is position independent, it only accesses the state
+//! pointer and calls the helpers through [crate::chip8::Chip8State::helpers].
+
+use fnv::FnvHashMap;
+use log::{debug, warn};
+use sha1::{Digest, Sha1};
+
+use crate::backend::Backend;
+use crate::chip8::{Chip8, Chip8Field, Chip8State, INSTRUCTION_SIZE_BYTES};
+use crate::jit::Helper;
+use crate::quirks::Quirks;
+use crate::rom_db;
+use crate::{Addr, Error, Result};
+
+use std::env;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8; 8] = b"RIP8BLKS";
+/// The size of [build_hash].
+pub(crate) const BUILD_HASH_SIZE: usize = 20;
+
+/// A compiled block which can be loaded instead of compiling it again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredBlock {
+    pub addrs: Range<Addr>,
+    pub instructions: u64,
+    /// The SHA-1 hash of the instructions the block got compiled from, see [source_hash].
+    pub source_hash: [u8; 20],
+    pub quirks: Quirks,
+    pub code: Vec<u8>,
+}
+
+impl StoredBlock {
+    /// Returns `true` if the block was compiled from the instructions which are in the memory of
+    /// `state` now and with the same quirks.
+    fn matches(&self, state: &Chip8State) -> bool {
+        self.quirks == state.quirks && self.source_hash == source_hash(&state.mem, &self.addrs)
+    }
+}
+
+/// Returns the hash of the bytes in `addrs` and of the instruction after them, which a skip at
+/// the end of the block reads to know its size. The addresses wrap around at the end of the
+/// memory.
+pub fn source_hash(mem: &[u8], addrs: &Range<Addr>) -> [u8; 20] {
+    let mut hasher = Sha1::new();
+    for addr in addrs.start..addrs.end + INSTRUCTION_SIZE_BYTES {
+        hasher.update([mem[(addr & Chip8::ADDR_MASK) as usize]]);
+    }
+
+    hasher.finalize().into()
+}
+
+/// Identifies the build and the layout of [Chip8State] which the code was compiled for. Files of
+/// other builds are ignored because their code might access other offsets or be generated
+/// differently.
+pub(crate) fn build_hash() -> [u8; BUILD_HASH_SIZE] {
+    let fields = [
+        Chip8Field::I,
+        Chip8Field::PC,
+        Chip8Field::SP,
+        Chip8Field::Stack,
+        Chip8Field::Reg(0),
+        Chip8Field::Delay,
+        Chip8Field::Sound,
+        Chip8Field::Planes,
+        Chip8Field::Pitch,
+        Chip8Field::Fault,
+        Chip8Field::Helper(Helper::Cls),
+    ];
+
+    let mut hasher = Sha1::new();
+    hasher.update(env!("RIP8_BUILD_ID"));
+    for field in fields {
+        hasher.update((field.offset() as u64).to_le_bytes());
+    }
+    hasher.update((std::mem::size_of::<Chip8State>() as u64).to_le_bytes());
+    hasher.update((Helper::COUNT as u64).to_le_bytes());
+
+    hasher.finalize().into()
+}
+
+/// The compiled blocks of one ROM and backend.
+#[derive(Debug, Clone, Default)]
+pub struct BlockCache {
+    /// The file the blocks are saved to, `None` if they're only kept in memory.
+    path: Option<PathBuf>,
+    blocks: FnvHashMap<Addr, Vec<StoredBlock>>,
+    /// Set if there are blocks which aren't on disk yet.
+    changed: bool,
+}
+
+impl BlockCache {
+    /// `$XDG_CACHE_HOME/rip8/blocks` or `~/.cache/rip8/blocks`.
+    pub fn default_dir() -> Option<PathBuf> {
+        let cache_dir = env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+
+        Some(cache_dir.join("rip8").join("blocks"))
+    }
+
+    /// Loads the blocks of `rom` from `dir`. Files which are missing, damaged or were written by
+    /// another build result in an empty cache, which replaces them when it's saved.
+    pub fn open<P: AsRef<Path>>(dir: P, rom: &[u8], backend: Backend) -> Self {
+        let path = dir
+            .as_ref()
+            .join(format!("{}-{}.bin", rom_db::hash(rom), backend));
+
+        let mut cache = match fs::read(&path) {
+            Ok(data) => Self::from_bytes(&data).unwrap_or_else(|| {
+                warn!("Ignoring the outdated block cache '{}'", path.display());
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        };
+        debug!("Loaded {} blocks from '{}'", cache.len(), path.display());
+
+        cache.path = Some(path);
+        cache
+    }
+
+    /// Reads the blocks of [BlockCache::to_bytes] into a cache which isn't saved to disk. Returns
+    /// `None` if `data` is damaged or of another build.
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        let mut cache = Self::default();
+        for block in parse(data)? {
+            cache.insert(block);
+        }
+        cache.changed = false;
+
+        Some(cache)
+    }
+
+    /// Returns the file the blocks are saved to, `None` if they're only kept in memory.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Returns the block which starts at the pc of `state`, if it was compiled from the same
+    /// instructions with the same quirks.
+    pub fn lookup(&self, state: &Chip8State) -> Option<&StoredBlock> {
+        self.blocks
+            .get(&state.pc)?
+            .iter()
+            .find(|block| block.matches(state))
+    }
+
+    /// Adds a block, replacing one which was compiled from the same instructions and quirks.
+    pub fn insert(&mut self, block: StoredBlock) {
+        let variants = self.blocks.entry(block.addrs.start).or_default();
+        variants.retain(|variant| {
+            variant.quirks != block.quirks || variant.source_hash != block.source_hash
+        });
+        variants.push(block);
+        self.changed = true;
+    }
+
+    pub fn blocks(&self) -> impl Iterator<Item = &StoredBlock> {
+        self.blocks.values().flatten()
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Writes the blocks to disk if new ones were added since they were loaded.
+    pub fn save(&self) -> Result<()> {
+        let path = match &self.path {
+            Some(path) if self.changed => path,
+            _ => return Ok(()),
+        };
+
+        let write_error = |source| Error::Write {
+            path: path.clone(),
+            source,
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(write_error)?;
+        }
+        fs::write(path, self.to_bytes()).map_err(write_error)?;
+        debug!("Saved {} blocks to '{}'", self.len(), path.display());
+
+        Ok(())
+    }
+
+    /// Encodes the blocks in the format of the files on disk.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&build_hash());
+        data.extend_from_slice(&(self.len() as u32).to_le_bytes());
+
+        for block in self.blocks.values().flatten() {
+            data.extend_from_slice(&block.addrs.start.to_le_bytes());
+            data.extend_from_slice(&block.addrs.end.to_le_bytes());
+            data.extend_from_slice(&block.instructions.to_le_bytes());
+            data.extend_from_slice(&block.source_hash);
+            data.extend_from_slice(&block.quirks.to_bytes());
+            data.extend_from_slice(&(block.code.len() as u32).to_le_bytes());
+            data.extend_from_slice(&block.code);
+        }
+
+        data
+    }
+}
+
+/// Reads the blocks of [BlockCache::to_bytes], returns `None` if `data` is damaged or of another
+/// build.
+fn parse(data: &[u8]) -> Option<Vec<StoredBlock>> {
+    let mut reader = Reader { data };
+
+    if reader.take(MAGIC.len())? != MAGIC || reader.take(BUILD_HASH_SIZE)? != build_hash() {
+        return None;
+    }
+
+    let amount = reader.u32()?;
+    let mut blocks = Vec::new();
+    for _ in 0..amount {
+        let start = reader.u64()?;
+        let end = reader.u64()?;
+        let instructions = reader.u64()?;
+        let source_hash = reader.take(20)?.try_into().ok()?;
+        let quirks = Quirks::from_bytes(reader.take(Quirks::ENCODED_SIZE)?.try_into().ok()?);
+        let code_len = reader.u32()? as usize;
+        let code = reader.take(code_len)?.to_vec();
+
+        blocks.push(StoredBlock {
+            addrs: start..end,
+            instructions,
+            source_hash,
+            quirks,
+            code,
+        });
+    }
+
+    reader.data.is_empty().then_some(blocks)
+}
+
+/// Reads the little endian fields of the cache files, `None` means that `data` is too short.
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, amount: usize) -> Option<&'a [u8]> {
+        if self.data.len() < amount {
+            return None;
+        }
+
+        let (taken, rest) = self.data.split_at(amount);
+        self.data = rest;
+        Some(taken)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache() -> BlockCache {
+        let mut cache = BlockCache::default();
+        for (start, code) in [(0x200, vec![0xc3]), (0x204, vec![0x90, 0xc3])] {
+            let addrs = start..start + 4;
+            cache.insert(StoredBlock {
+                source_hash: source_hash(&[0; Chip8::MEM_SIZE], &addrs),
+                addrs,
+                instructions: 2,
+                quirks: Quirks::VIP,
+                code,
+            });
+        }
+
+        cache
+    }
+
+    #[test]
+    fn round_trip() {
+        let cache = cache();
+        let read = BlockCache::from_bytes(&cache.to_bytes()).unwrap();
+
+        assert_eq!(read.len(), 2);
+        assert_eq!(read.blocks, cache.blocks);
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let data = cache().to_bytes();
+        for len in 0..data.len() {
+            assert!(BlockCache::from_bytes(&data[..len]).is_none(), "{}", len);
+        }
+
+        let mut longer = data.clone();
+        longer.push(0);
+        assert!(BlockCache::from_bytes(&longer).is_none());
+    }
+
+    #[test]
+    fn rejects_foreign_files() {
+        let data = cache().to_bytes();
+
+        let mut magic = data.clone();
+        magic[0] ^= 1;
+        assert!(BlockCache::from_bytes(&magic).is_none());
+
+        // written by another build
+        let mut build = data.clone();
+        build[MAGIC.len()] ^= 1;
+        assert!(BlockCache::from_bytes(&build).is_none());
+
+        assert!(BlockCache::from_bytes(b"{\"blocks\": []}").is_none());
+    }
+
+    #[test]
+    fn hashes_the_instruction_after_the_block() {
+        let mut mem = [0; Chip8::MEM_SIZE];
+        let addrs = 0x200..0x204;
+        let hash = source_hash(&mem, &addrs);
+
+        // the skip at the end of the block would skip an `F000 NNNN` now
+        mem[0x204] = 0xf0;
+        assert_ne!(source_hash(&mem, &addrs), hash);
+        mem[0x204] = 0;
+
+        mem[0x206] = 0xf0;
+        assert_eq!(source_hash(&mem, &addrs), hash);
+    }
+}
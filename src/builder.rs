@@ -0,0 +1,105 @@
+use crate::backend::Backend;
+use crate::block_cache::BlockCache;
+use crate::chip8::{self, Chip8};
+use crate::filter::Filter;
+use crate::palette::Palette;
+use crate::quirks::{Platform, Quirks};
+use crate::{Error, Result};
+
+use std::path::PathBuf;
+
+/// Creates an emulator without a display, see [Chip8::builder].
+///
+/// ```no_run
+/// use rip8::chip8::{Chip8, Keys};
+///
+/// let rom = std::fs::read("game.ch8")?;
+/// let mut chip8 = Chip8::builder().rom(rom).speed(30).build()?;
+/// let frame = chip8.step_frame(Keys::NONE.with(0x5))?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Chip8Builder {
+    rom: Vec<u8>,
+    quirks: Quirks,
+    backend: Backend,
+    speed: Option<u64>,
+    palette: Option<Palette>,
+    scale: Option<usize>,
+    filter: Filter,
+    block_cache: Option<PathBuf>,
+}
+
+impl Chip8Builder {
+    pub fn rom(mut self, rom: impl Into<Vec<u8>>) -> Self {
+        self.rom = rom.into();
+        self
+    }
+
+    pub fn quirks(mut self, quirks: Quirks) -> Self {
+        self.quirks = quirks;
+        self
+    }
+
+    /// Uses the quirks of `platform`.
+    pub fn platform(self, platform: Platform) -> Self {
+        self.quirks(platform.quirks())
+    }
+
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Loads compiled blocks of earlier runs of the ROM from `dir`, see [Chip8::save_block_cache].
+    pub fn block_cache(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.block_cache = Some(dir.into());
+        self
+    }
+
+    /// The amount of instructions which are executed per frame.
+    pub fn speed(mut self, speed: u64) -> Self {
+        self.speed = Some(speed);
+        self
+    }
+
+    pub fn palette(mut self, palette: Palette) -> Self {
+        self.palette = Some(palette);
+        self
+    }
+
+    /// The size of a low resolution pixel in screenshots, recordings and the window.
+    pub fn scale(mut self, scale: usize) -> Self {
+        self.scale = Some(scale);
+        self
+    }
+
+    pub fn filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Loads the ROM, which fails if it doesn't fit into the memory or if the backend isn't part
+    /// of this build.
+    pub fn build(self) -> Result<Chip8> {
+        if !self.backend.is_available() {
+            return Err(Error::BackendUnavailable(self.backend));
+        }
+
+        let block_cache = self
+            .block_cache
+            .map(|dir| BlockCache::open(dir, &self.rom, self.backend));
+        let mut chip8 = Chip8::new(self.rom, self.quirks)?;
+
+        chip8.set_backend(self.backend);
+        if let Some(block_cache) = block_cache {
+            chip8.set_block_cache(block_cache);
+        }
+        chip8.set_speed(self.speed.unwrap_or(Chip8::DEFAULT_SPEED));
+        chip8.set_palette(self.palette.unwrap_or(chip8::PALETTE));
+        chip8.set_scale(self.scale.unwrap_or(Chip8::DEFAULT_SCALE));
+        chip8.set_filter(self.filter);
+
+        Ok(chip8)
+    }
+}
@@ -1,51 +1,315 @@
 use fnv::FnvHashMap;
-use log::debug;
-use memmap2::Mmap;
+use log::{debug, warn};
 
-use crate::chip8::Chip8State;
+use crate::arena::{ArenaStats, CodeArena, CodeSpan};
+use crate::backend::Backend;
+use crate::block_cache::{self, BlockCache, StoredBlock};
+use crate::chip8::{Chip8State, INSTRUCTION_SIZE_BYTES};
+use crate::idle::IdleInfo;
 use crate::jit;
-use crate::Addr;
+use crate::threaded::{self, Operation};
+use crate::{Addr, Error, Result};
 
-use std::cell::RefCell;
-use std::rc::Rc;
+use std::fmt;
+use std::ops::Range;
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct Cache {
     blocks: FnvHashMap<Addr, CompileBlock>,
+    backend: Backend,
+    /// Holds the machine code of the blocks of the native backends.
+    arena: CodeArena,
+    /// Blocks of earlier runs, new blocks are added to it.
+    block_cache: Option<BlockCache>,
+    /// Created by the first block which is compiled with Cranelift.
+    #[cfg(feature = "cranelift")]
+    cranelift: Option<crate::cranelift::Compiler>,
 }
 
 impl Cache {
-    pub fn new() -> Self {
+    pub fn new(backend: Backend) -> Self {
         Self {
             blocks: FnvHashMap::default(),
+            backend,
+            arena: CodeArena::new(),
+            block_cache: None,
+            #[cfg(feature = "cranelift")]
+            cranelift: None,
         }
     }
 
-    pub fn get_or_compile(&mut self, state: Rc<RefCell<Chip8State>>) -> &CompileBlock {
-        let pc = state.borrow().pc;
-        self.blocks.entry(pc).or_insert_with(|| {
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    pub fn get_or_compile(&mut self, state: &Chip8State) -> Result<&CompileBlock> {
+        let pc = state.pc;
+        if !self.blocks.contains_key(&pc) {
             debug!("Cache miss for {:#x}", pc);
-            jit::compile(state)
-        })
+            let mut block = self.compile(state)?;
+            block.idle = IdleInfo::analyze(&state.mem, pc, &state.quirks);
+            self.blocks.insert(pc, block);
+        }
+
+        Ok(&self.blocks[&pc])
+    }
+
+    /// Adds a block which was compiled elsewhere from the memory of `state`, replacing the one at
+    /// the same address.
+    pub fn insert(&mut self, mut block: CompileBlock, state: &Chip8State) {
+        block.idle = IdleInfo::analyze(&state.mem, block.start_addr, &state.quirks);
+        if let Some(old) = self.blocks.insert(block.start_addr, block) {
+            old.release(&mut self.arena);
+        }
+    }
+
+    /// Drops all blocks, their memory is reused for the next ones.
+    pub fn clear(&mut self) {
+        for (_, block) in self.blocks.drain() {
+            block.release(&mut self.arena);
+        }
+    }
+
+    /// Drops the blocks which contain any of the addresses in `addrs`, e.g. because the program
+    /// overwrote them.
+    pub fn invalidate(&mut self, addrs: Range<Addr>) {
+        // a skip at the end of a block depends on the size of the instruction at `end_addr`, which
+        // is read from the whole word there
+        self.drop_blocks(|block, _| {
+            block.start_addr < addrs.end && addrs.start < block.end_addr + INSTRUCTION_SIZE_BYTES
+        });
+    }
+
+    fn drop_blocks(&mut self, mut predicate: impl FnMut(&CompileBlock, &CodeArena) -> bool) {
+        let dropped: Vec<Addr> = self
+            .blocks
+            .values()
+            .filter(|block| predicate(block, &self.arena))
+            .map(|block| block.start_addr)
+            .collect();
+
+        for addr in dropped {
+            debug!("Dropping the block at {:#x}", addr);
+            if let Some(block) = self.blocks.remove(&addr) {
+                block.release(&mut self.arena);
+            }
+        }
+    }
+
+    /// Loads the blocks of the native backends from `block_cache` if they were compiled from the
+    /// same instructions and adds the newly compiled ones to it.
+    pub fn set_block_cache(&mut self, block_cache: BlockCache) {
+        self.block_cache = Some(block_cache);
+    }
+
+    pub fn block_cache(&self) -> Option<&BlockCache> {
+        self.block_cache.as_ref()
+    }
+
+    pub fn arena_stats(&self) -> ArenaStats {
+        self.arena.stats()
+    }
+
+    /// Compiles the block at pc. Switches to [Backend::Threaded] for this and all following
+    /// blocks if no executable memory can be mapped.
+    fn compile(&mut self, state: &Chip8State) -> Result<CompileBlock> {
+        let block = match self.backend {
+            Backend::Threaded => threaded::compile(state),
+            _ => match self
+                .block_cache
+                .as_ref()
+                .and_then(|cache| cache.lookup(state))
+            {
+                Some(stored) => {
+                    debug!("Loading the block at {:#x} from the block cache", state.pc);
+                    CompileBlock::new(
+                        &mut self.arena,
+                        &stored.code,
+                        stored.addrs.clone(),
+                        stored.instructions,
+                    )
+                }
+                None => self.compile_native(state),
+            },
+        };
+
+        // the regions which got the new code are only executable after sealing them
+        let block = match block {
+            Ok(block) => match self.arena.seal() {
+                Ok(()) => Ok(block),
+                Err(err) => {
+                    block.release(&mut self.arena);
+                    // the new code made regions of older blocks writable, they can't run anymore
+                    self.drop_blocks(|block, arena| match block.code {
+                        BlockCode::Native(span) => !arena.is_executable(span),
+                        BlockCode::Threaded(_) | BlockCode::Linked(_) => false,
+                    });
+                    Err(err)
+                }
+            },
+            Err(err) => Err(err),
+        };
+
+        match block {
+            Err(Error::ExecutableMemory(err)) => {
+                warn!(
+                    "{}, falling back to the threaded backend",
+                    Error::ExecutableMemory(err)
+                );
+                self.backend = Backend::Threaded;
+                threaded::compile(state)
+            }
+            block => block,
+        }
+    }
+
+    /// Compiles the block at pc into machine code and adds it to the block cache.
+    fn compile_native(&mut self, state: &Chip8State) -> Result<CompileBlock> {
+        let block = match self.backend {
+            Backend::X86 => jit::compile(state, &mut self.arena)?,
+            #[cfg(feature = "cranelift")]
+            Backend::Cranelift => {
+                let compiler = match &mut self.cranelift {
+                    Some(compiler) => compiler,
+                    None => self.cranelift.insert(crate::cranelift::Compiler::new()?),
+                };
+                compiler.compile(state, &mut self.arena)?
+            }
+            #[cfg(not(feature = "cranelift"))]
+            Backend::Cranelift => return Err(Error::BackendUnavailable(self.backend)),
+            Backend::Threaded => unreachable!("the threaded backend doesn't generate machine code"),
+        };
+
+        if let (Some(block_cache), BlockCode::Native(span)) = (&mut self.block_cache, &block.code) {
+            let addrs = block.start_addr..block.end_addr;
+            block_cache.insert(StoredBlock {
+                source_hash: block_cache::source_hash(&state.mem, &addrs),
+                addrs,
+                instructions: block.instructions,
+                quirks: state.quirks,
+                code: self.arena.code(*span).to_vec(),
+            });
+        }
+
+        Ok(block)
+    }
+}
+
+/// The executable form of a block. It's private to the crate because [CompileBlock::execute]
+/// calls the machine code, blocks with it can only be created by the backends and
+/// [CompileBlock::linked].
+pub(crate) enum BlockCode {
+    /// Machine code for the host which is called with a pointer to the state.
+    Native(CodeSpan),
+    /// Operations which are called one after another.
+    Threaded(Vec<Operation>),
+    /// Machine code which was linked into the executable by `rip8 aot`, stored as integer to keep
+    /// the blocks `Send`.
+    Linked(usize),
+}
+
+impl fmt::Debug for BlockCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Native(code) => f.debug_tuple("Native").field(code).finish(),
+            Self::Threaded(operations) => write!(f, "Threaded({} operations)", operations.len()),
+            Self::Linked(code) => write!(f, "Linked({:#x})", code),
+        }
     }
 }
 
 #[derive(Debug)]
 pub struct CompileBlock {
-    pub code: Mmap,
+    code: BlockCode,
     pub start_addr: Addr,
+    /// The address after the last instruction of the block.
+    pub end_addr: Addr,
+    /// The amount of CHIP-8 instructions in this block.
+    pub instructions: u64,
+    pub idle: IdleInfo,
 }
 
 impl CompileBlock {
-    pub fn execute(&self, state: Rc<RefCell<Chip8State>>) {
-        {
-            let pc = state.borrow().pc;
-            debug!("Executing at address: {:#x}", pc);
+    /// Copies the machine code into `arena`, it can be executed once the arena is sealed.
+    pub(crate) fn new(
+        arena: &mut CodeArena,
+        code: &[u8],
+        addrs: Range<Addr>,
+        instructions: u64,
+    ) -> Result<Self> {
+        Ok(Self {
+            code: BlockCode::Native(arena.alloc(code)?),
+            start_addr: addrs.start,
+            end_addr: addrs.end,
+            instructions,
+            idle: IdleInfo::default(),
+        })
+    }
+
+    pub fn threaded(operations: Vec<Operation>, addrs: Range<Addr>) -> Self {
+        Self {
+            instructions: operations.len() as u64,
+            code: BlockCode::Threaded(operations),
+            start_addr: addrs.start,
+            end_addr: addrs.end,
+            idle: IdleInfo::default(),
         }
-        let state = (&mut *state.borrow_mut()) as *mut Chip8State;
+    }
+
+    /// A block of `rip8 transpile`, `function` executes all of its instructions.
+    pub fn transpiled(
+        function: fn(&mut Chip8State),
+        addrs: Range<Addr>,
+        instructions: u64,
+    ) -> Self {
+        Self {
+            code: BlockCode::Threaded(vec![Box::new(function)]),
+            start_addr: addrs.start,
+            end_addr: addrs.end,
+            instructions,
+            idle: IdleInfo::default(),
+        }
+    }
+
+    /// A block of `rip8 aot` whose machine code is part of the executable.
+    ///
+    /// # Safety
+    /// `code` has to be a function which was compiled by the x86 backend of this build for the
+    /// instructions in `addrs`.
+    pub unsafe fn linked(code: *const u8, addrs: Range<Addr>, instructions: u64) -> Self {
+        Self {
+            code: BlockCode::Linked(code as usize),
+            start_addr: addrs.start,
+            end_addr: addrs.end,
+            instructions,
+            idle: IdleInfo::default(),
+        }
+    }
+
+    /// Returns the machine code of the block to `arena`.
+    fn release(self, arena: &mut CodeArena) {
+        if let BlockCode::Native(span) = self.code {
+            arena.free(span);
+        }
+    }
+
+    pub fn execute(&self, state: &mut Chip8State) {
+        debug!("Executing at address: {:#x}", state.pc);
+
+        let code = match &self.code {
+            BlockCode::Native(span) => span.as_ptr(),
+            BlockCode::Linked(code) => *code as *const u8,
+            BlockCode::Threaded(operations) => {
+                for operation in operations {
+                    operation(state);
+                }
+                return;
+            }
+        };
 
+        let state = state as *mut Chip8State;
         let fnptr: unsafe extern "C" fn(state: *mut Chip8State) =
-            unsafe { std::mem::transmute(self.code.as_ptr()) };
+            unsafe { std::mem::transmute(code) };
         unsafe {
             fnptr(state);
         }
@@ -0,0 +1,779 @@
+//! Static analysis of the control flow of a ROM. It decodes the instructions which are reachable
+//! from [Chip8::START_ADDRESS] by following jumps, calls, skips and returns, splits them into
+//! basic blocks and finds the subroutines. Jumps with `BNNN` are only known at runtime, they're
+//! recorded but not followed. The bytes which sprite and memory instructions read are data.
+
+use crate::chip8::{self, Chip8};
+use crate::instruction::Instruction;
+use crate::quirks::Quirks;
+use crate::Addr;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Write};
+use std::ops::Range;
+
+/// The values `I` may have at some point, `None` if there are too many or they depend on a
+/// register.
+type PossibleI = Option<BTreeSet<Addr>>;
+
+/// The amount of values of `I` which are tracked before it's considered unknown.
+const MAX_POSSIBLE_I: usize = 8;
+
+/// The XO-CHIP planes which may be selected, bit `n` is set if `FN01` may have selected the planes
+/// `n`.
+type PossiblePlanes = u8;
+
+/// The planes which are selected at the start.
+const INITIAL_PLANES: PossiblePlanes = 1 << 1;
+
+/// What's known about the registers which decide the bytes that sprite and memory instructions
+/// read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Known {
+    i: PossibleI,
+    planes: PossiblePlanes,
+}
+
+impl Known {
+    /// Returns the values of both, `None` if they're the ones of `self`.
+    fn merge(&self, other: &Self) -> Option<Self> {
+        let i = match (&self.i, &other.i) {
+            (Some(i), Some(other)) => {
+                let merged: BTreeSet<Addr> = i.union(other).copied().collect();
+                (merged.len() <= MAX_POSSIBLE_I).then_some(merged)
+            }
+            _ => None,
+        };
+        let merged = Self {
+            i,
+            planes: self.planes | other.planes,
+        };
+
+        (merged != *self).then_some(merged)
+    }
+
+    /// The amount of planes a sprite is drawn to at most.
+    fn max_planes(&self) -> u64 {
+        (0..4u32)
+            .filter(|&planes| self.planes & (1 << planes) != 0)
+            .map(|planes| u64::from(planes.count_ones()))
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// How a basic block ends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Terminator {
+    /// The next instruction starts another block because something jumps to it.
+    Fallthrough(Addr),
+    /// The last instruction ends a compiled block although the execution continues at the
+    /// address, like `DXYN` with the `display_wait` quirk.
+    Continue(Addr),
+    Jump(Addr),
+    Call {
+        target: Addr,
+        ret: Addr,
+    },
+    /// The next instruction is executed or skipped.
+    Skip {
+        next: Addr,
+        skipped: Addr,
+    },
+    Return,
+    /// `FX0A` stays at `addr` until a key is pressed.
+    WaitKey {
+        addr: Addr,
+        next: Addr,
+    },
+    /// `BNNN`, its target depends on a register.
+    IndirectJump,
+    /// `00FD` stops the emulation.
+    Exit,
+    /// The block runs into bytes which aren't an instruction at the address.
+    Invalid(Addr),
+}
+
+impl Terminator {
+    /// Returns the blocks which may run after this one.
+    pub fn successors(&self) -> Vec<Addr> {
+        match *self {
+            Self::Fallthrough(addr) | Self::Continue(addr) | Self::Jump(addr) => vec![addr],
+            Self::Call { target, ret } => vec![target, ret],
+            Self::Skip { next, skipped } => vec![next, skipped],
+            Self::WaitKey { addr, next } => vec![addr, next],
+            Self::Return | Self::IndirectJump | Self::Exit | Self::Invalid(_) => Vec::new(),
+        }
+    }
+}
+
+/// Instructions which are always executed one after another.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BasicBlock {
+    pub addrs: Range<Addr>,
+    pub instructions: Vec<(Addr, Instruction)>,
+    pub terminator: Terminator,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Subroutine {
+    pub entry: Addr,
+    /// The start addresses of the blocks which are executed before it returns, without the
+    /// ones of the subroutines it calls.
+    pub blocks: BTreeSet<Addr>,
+    /// The start addresses of the blocks which call it.
+    pub callers: BTreeSet<Addr>,
+}
+
+/// What a byte of the ROM is used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ByteKind {
+    /// The byte belongs to a reachable instruction.
+    Code,
+    /// The byte is read by a sprite or memory instruction with a known `I`.
+    Data,
+    /// Neither of both could be determined statically.
+    #[default]
+    Unknown,
+}
+
+/// The control flow graph of the reachable part of a ROM.
+#[derive(Debug, Clone)]
+pub struct Cfg {
+    /// The basic blocks by their start address.
+    pub blocks: BTreeMap<Addr, BasicBlock>,
+    /// The subroutines by their entry address.
+    pub subroutines: BTreeMap<Addr, Subroutine>,
+    /// The addresses of the `BNNN` instructions.
+    pub indirect_jumps: BTreeSet<Addr>,
+    /// The kind of every byte of the ROM.
+    pub bytes: Vec<ByteKind>,
+}
+
+impl Cfg {
+    /// Analyzes the ROM in `mem`, which is `rom_len` bytes long. The quirks decide where the
+    /// blocks of the backends end.
+    pub fn analyze(mem: &[u8], rom_len: usize, quirks: &Quirks) -> Self {
+        let (instructions, leaders) = decode_reachable(mem, quirks);
+
+        let mut cfg = Self {
+            blocks: BTreeMap::new(),
+            subroutines: BTreeMap::new(),
+            indirect_jumps: BTreeSet::new(),
+            bytes: vec![ByteKind::Unknown; rom_len],
+        };
+
+        for &start in &leaders {
+            let block = basic_block(mem, start, &instructions, &leaders, quirks);
+            if let Some(&(addr, Instruction::JpV0(_))) = block.instructions.last() {
+                cfg.indirect_jumps.insert(addr);
+            }
+            cfg.blocks.insert(start, block);
+        }
+
+        cfg.find_subroutines();
+        cfg.classify_bytes(mem, quirks);
+
+        cfg
+    }
+
+    /// Returns the instructions which the backends compile into the block at `start`, `None` if
+    /// it runs into bytes which aren't an instruction.
+    pub fn compiled_block(&self, start: Addr) -> Option<Vec<(Addr, Instruction)>> {
+        let mut instructions = Vec::new();
+        let mut block = self.blocks.get(&start)?;
+
+        loop {
+            instructions.extend_from_slice(&block.instructions);
+            match block.terminator {
+                Terminator::Fallthrough(next) => block = self.blocks.get(&next)?,
+                Terminator::Invalid(_) => return None,
+                _ => return Some(instructions),
+            }
+        }
+    }
+
+    /// Returns the start addresses and instructions of all blocks which the backends can
+    /// compile, see [Cfg::compiled_block].
+    pub fn compiled_blocks(&self) -> impl Iterator<Item = (Addr, Vec<(Addr, Instruction)>)> + '_ {
+        self.blocks
+            .keys()
+            .filter_map(|&start| Some((start, self.compiled_block(start)?)))
+    }
+
+    /// Returns the kind of the byte at `addr`, addresses outside of the ROM are [ByteKind::Unknown].
+    pub fn byte_kind(&self, addr: Addr) -> ByteKind {
+        addr.checked_sub(Chip8::START_ADDRESS)
+            .and_then(|offset| self.bytes.get(offset as usize))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Formats the graph in the DOT language of Graphviz. The blocks of a subroutine are grouped
+    /// in a cluster and `BNNN` jumps lead to a node with a question mark.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        self.write_dot(&mut dot)
+            .expect("writing to a String can't fail");
+        dot
+    }
+
+    fn write_dot(&self, out: &mut String) -> fmt::Result {
+        let node = |addr: Addr| format!("b{:x}", addr);
+
+        let count = |kind| self.bytes.iter().filter(|&&byte| byte == kind).count();
+        writeln!(
+            out,
+            "// {} bytes of code, {} of data and {} unknown",
+            count(ByteKind::Code),
+            count(ByteKind::Data),
+            count(ByteKind::Unknown)
+        )?;
+        writeln!(out, "digraph rom {{")?;
+        writeln!(out, "    node [shape=box, fontname=\"monospace\"];")?;
+
+        // a block which is shared by subroutines is drawn in the first one
+        let mut drawn = BTreeSet::new();
+        for subroutine in self.subroutines.values() {
+            writeln!(out, "    subgraph cluster_{:x} {{", subroutine.entry)?;
+            writeln!(out, "        label=\"subroutine {:#x}\";", subroutine.entry)?;
+            for &start in &subroutine.blocks {
+                if drawn.insert(start) {
+                    writeln!(out, "        {};", node(start))?;
+                }
+            }
+            writeln!(out, "    }}")?;
+        }
+
+        for (&start, block) in &self.blocks {
+            let mut label = String::new();
+            for (addr, instruction) in &block.instructions {
+                write!(label, "{:#x}: {}\\l", addr, instruction)?;
+            }
+            writeln!(out, "    {} [label=\"{}\"];", node(start), label)?;
+
+            match block.terminator {
+                Terminator::Fallthrough(next)
+                | Terminator::Continue(next)
+                | Terminator::Jump(next) => {
+                    writeln!(out, "    {} -> {};", node(start), node(next))?;
+                }
+                Terminator::Call { target, ret } => {
+                    writeln!(
+                        out,
+                        "    {} -> {} [label=\"call\"];",
+                        node(start),
+                        node(target)
+                    )?;
+                    writeln!(out, "    {} -> {} [style=dashed];", node(start), node(ret))?;
+                }
+                Terminator::Skip { next, skipped } => {
+                    writeln!(out, "    {} -> {};", node(start), node(next))?;
+                    writeln!(
+                        out,
+                        "    {} -> {} [label=\"skip\"];",
+                        node(start),
+                        node(skipped)
+                    )?;
+                }
+                Terminator::WaitKey { addr, next } => {
+                    writeln!(
+                        out,
+                        "    {} -> {} [label=\"no key\"];",
+                        node(start),
+                        node(addr)
+                    )?;
+                    writeln!(out, "    {} -> {};", node(start), node(next))?;
+                }
+                Terminator::IndirectJump => {
+                    writeln!(
+                        out,
+                        "    indirect_{:x} [shape=diamond, label=\"?\"];",
+                        start
+                    )?;
+                    writeln!(
+                        out,
+                        "    {} -> indirect_{:x} [style=dotted];",
+                        node(start),
+                        start
+                    )?;
+                }
+                Terminator::Invalid(addr) => {
+                    writeln!(
+                        out,
+                        "    invalid_{:x} [shape=octagon, label=\"invalid {:#x}\"];",
+                        start, addr
+                    )?;
+                    writeln!(out, "    {} -> invalid_{:x};", node(start), start)?;
+                }
+                Terminator::Return | Terminator::Exit => (),
+            }
+        }
+
+        writeln!(out, "}}")
+    }
+
+    /// Collects the blocks of every called address until it returns, calls are stepped over.
+    fn find_subroutines(&mut self) {
+        let mut subroutines: BTreeMap<Addr, Subroutine> = BTreeMap::new();
+
+        for (&start, block) in &self.blocks {
+            if let Terminator::Call { target, .. } = block.terminator {
+                subroutines
+                    .entry(target)
+                    .or_insert_with(|| Subroutine {
+                        entry: target,
+                        blocks: BTreeSet::new(),
+                        callers: BTreeSet::new(),
+                    })
+                    .callers
+                    .insert(start);
+            }
+        }
+
+        for subroutine in subroutines.values_mut() {
+            let mut pending = vec![subroutine.entry];
+            while let Some(addr) = pending.pop() {
+                let block = match self.blocks.get(&addr) {
+                    Some(block) if subroutine.blocks.insert(addr) => block,
+                    _ => continue,
+                };
+                match block.terminator {
+                    Terminator::Call { ret, .. } => pending.push(ret),
+                    terminator => pending.extend(terminator.successors()),
+                }
+            }
+        }
+
+        self.subroutines = subroutines;
+    }
+
+    /// Marks the bytes of the instructions as code and the ones which are read with a known `I`
+    /// as data.
+    fn classify_bytes(&mut self, mem: &[u8], quirks: &Quirks) {
+        let known = self.known(quirks);
+        let mut code = Vec::new();
+        let mut data = Vec::new();
+
+        for (start, block) in &self.blocks {
+            if let Some(known) = known.get(start) {
+                track_reads(block, known.clone(), quirks, &mut data);
+            }
+
+            for &(addr, _) in &block.instructions {
+                code.push(addr..addr + chip8::instruction_size(mem, addr));
+            }
+        }
+
+        for range in code {
+            self.mark(range, ByteKind::Code);
+        }
+
+        for range in data {
+            for addr in range {
+                if self.byte_kind(addr) == ByteKind::Unknown {
+                    self.mark(addr..addr + 1, ByteKind::Data);
+                }
+            }
+        }
+    }
+
+    /// Returns the values `I` and the planes may have at the start of every block. Subroutines
+    /// may change them, so `I` is unknown after calls and every plane which is selected somewhere
+    /// may be selected.
+    fn known(&self, quirks: &Quirks) -> BTreeMap<Addr, Known> {
+        let selected_planes = self
+            .blocks
+            .values()
+            .flat_map(|block| &block.instructions)
+            .fold(0, |planes, &(_, instruction)| match instruction {
+                Instruction::Plane(n) => planes | 1 << (n & 0x3),
+                _ => planes,
+            });
+
+        let mut known: BTreeMap<Addr, Known> = BTreeMap::new();
+        let initial = Known {
+            i: Some(BTreeSet::from([0])),
+            planes: INITIAL_PLANES,
+        };
+        let mut pending = vec![(Chip8::START_ADDRESS, initial)];
+
+        while let Some((addr, values)) = pending.pop() {
+            let values = match known.get(&addr) {
+                None => values,
+                Some(old) => match old.merge(&values) {
+                    Some(merged) => merged,
+                    None => continue,
+                },
+            };
+            known.insert(addr, values.clone());
+
+            let block = match self.blocks.get(&addr) {
+                Some(block) => block,
+                None => continue,
+            };
+            let values = track_reads(block, values, quirks, &mut Vec::new());
+            match block.terminator {
+                Terminator::Call { target, ret } => {
+                    let after_call = Known {
+                        i: None,
+                        planes: values.planes | selected_planes,
+                    };
+                    pending.extend([(target, values), (ret, after_call)]);
+                }
+                terminator => pending.extend(
+                    terminator
+                        .successors()
+                        .into_iter()
+                        .map(|addr| (addr, values.clone())),
+                ),
+            }
+        }
+
+        known
+    }
+
+    fn mark(&mut self, addrs: Range<Addr>, kind: ByteKind) {
+        for addr in addrs {
+            let offset = addr.wrapping_sub(Chip8::START_ADDRESS) as usize;
+            if let Some(byte) = self.bytes.get_mut(offset) {
+                *byte = kind;
+            }
+        }
+    }
+}
+
+/// Follows the possible values of `I` and the planes through the instructions of `block` and adds
+/// the addresses which are read from to `reads`. Returns the values at the end of the block.
+fn track_reads(
+    block: &BasicBlock,
+    mut known: Known,
+    quirks: &Quirks,
+    reads: &mut Vec<Range<Addr>>,
+) -> Known {
+    for &(_, instruction) in &block.instructions {
+        let len = match instruction {
+            Instruction::LdI(nnn) => {
+                known.i = Some(BTreeSet::from([Addr::from(nnn)]));
+                None
+            }
+            Instruction::Plane(n) => {
+                known.planes = 1 << (n & 0x3);
+                None
+            }
+            // the sprite of every selected plane follows the one of the previous plane
+            Instruction::Drw(_, _, 0) => Some(32 * known.max_planes()),
+            Instruction::Drw(_, _, n) => Some(u64::from(n) * known.max_planes()),
+            Instruction::LdIX(x) | Instruction::LdXI(x) => Some(u64::from(x) + 1),
+            Instruction::SaveRange(x, y) | Instruction::LoadRange(x, y) => {
+                Some(u64::from(x.abs_diff(y)) + 1)
+            }
+            Instruction::LdB(_) => Some(3),
+            Instruction::Audio => Some(chip8::AUDIO_PATTERN_SIZE as u64),
+            // these change `I` to a value which depends on a register
+            Instruction::AddI(_) | Instruction::LdF(_) | Instruction::LdHf(_) => {
+                known.i = None;
+                None
+            }
+            _ => None,
+        };
+        if let (Some(len), Some(values)) = (len, &known.i) {
+            reads.extend(values.iter().map(|&value| value..value + len));
+        }
+
+        if let Instruction::LdIX(x) | Instruction::LdXI(x) = instruction {
+            if quirks.increment_i {
+                known.i = known.i.map(|values| {
+                    values
+                        .iter()
+                        .map(|&value| value + u64::from(x) + 1)
+                        .collect()
+                });
+            }
+        }
+    }
+
+    known
+}
+
+/// Decodes the instructions which are reachable from the start address. Returns them with the
+/// addresses at which the blocks of the backends start.
+fn decode_reachable(mem: &[u8], quirks: &Quirks) -> (BTreeMap<Addr, Instruction>, BTreeSet<Addr>) {
+    let mut instructions = BTreeMap::new();
+    let mut leaders = BTreeSet::new();
+    let mut pending = vec![Chip8::START_ADDRESS];
+
+    while let Some(start) = pending.pop() {
+        if start as usize >= mem.len() || !leaders.insert(start) {
+            continue;
+        }
+
+        let mut addr = start;
+        while !instructions.contains_key(&addr) {
+            let instruction = match Instruction::decode(mem, addr) {
+                Some(instruction) => instruction,
+                None => break,
+            };
+            instructions.insert(addr, instruction);
+
+            let next = addr + chip8::instruction_size(mem, addr);
+            if instruction.ends_block(quirks) {
+                let skipped = next + chip8::instruction_size(mem, next);
+                pending.extend(terminator(instruction, addr, next, skipped).successors());
+                break;
+            }
+            addr = next;
+        }
+    }
+
+    (instructions, leaders)
+}
+
+/// Collects the instructions from `start` up to the next one which ends a block or the start of
+/// the next block.
+fn basic_block(
+    mem: &[u8],
+    start: Addr,
+    instructions: &BTreeMap<Addr, Instruction>,
+    leaders: &BTreeSet<Addr>,
+    quirks: &Quirks,
+) -> BasicBlock {
+    let mut block = BasicBlock {
+        addrs: start..start,
+        instructions: Vec::new(),
+        terminator: Terminator::Invalid(start),
+    };
+    let mut addr = start;
+
+    while let Some(&instruction) = instructions.get(&addr) {
+        let next = addr + chip8::instruction_size(mem, addr);
+        block.instructions.push((addr, instruction));
+        block.addrs.end = next;
+
+        if instruction.ends_block(quirks) {
+            let skipped = next + chip8::instruction_size(mem, next);
+            block.terminator = terminator(instruction, addr, next, skipped);
+            return block;
+        }
+        if leaders.contains(&next) {
+            block.terminator = Terminator::Fallthrough(next);
+            return block;
+        }
+        addr = next;
+    }
+
+    block.terminator = Terminator::Invalid(addr);
+    block
+}
+
+/// Returns how a block which ends with `instruction` at `addr` continues. `next` is the address
+/// of the following instruction and `skipped` the one after it.
+pub(crate) fn terminator(
+    instruction: Instruction,
+    addr: Addr,
+    next: Addr,
+    skipped: Addr,
+) -> Terminator {
+    match instruction {
+        Instruction::Ret => Terminator::Return,
+        Instruction::Exit => Terminator::Exit,
+        Instruction::Jp(nnn) => Terminator::Jump(nnn.into()),
+        Instruction::Call(nnn) => Terminator::Call {
+            target: nnn.into(),
+            ret: next,
+        },
+        Instruction::SeByte(..)
+        | Instruction::SneByte(..)
+        | Instruction::SeReg(..)
+        | Instruction::SneReg(..)
+        | Instruction::Skp(_)
+        | Instruction::Sknp(_) => Terminator::Skip { next, skipped },
+        Instruction::JpV0(_) => Terminator::IndirectJump,
+        Instruction::LdK(_) => Terminator::WaitKey { addr, next },
+        _ => Terminator::Continue(next),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn analyze(rom: &[u8], quirks: &Quirks) -> Cfg {
+        let mut mem = vec![0; Chip8::MEM_SIZE];
+        mem[Chip8::START_ADDRESS as usize..][..rom.len()].copy_from_slice(rom);
+        Cfg::analyze(&mem, rom.len(), quirks)
+    }
+
+    #[rustfmt::skip]
+    const ROM: [u8; 0x25] = [
+        0x60, 0x00, // LD V0, 0
+        0x30, 0x00, // SE V0, 0
+        0x12, 0x08, // JP 0x208
+        0x61, 0x01, // LD V1, 1
+        0x22, 0x10, // CALL 0x210
+        0x12, 0x0a, // JP 0x20a
+        0x00, 0x00, 0x00, 0x00,
+        0xa2, 0x20, // LD I, 0x220
+        0xd0, 0x15, // DRW V0, V1, 5
+        0x00, 0xee, // RET
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0xf0, 0x90, 0x90, 0x90, 0xf0,
+    ];
+
+    #[test]
+    fn splits_blocks_at_skips_and_jumps() {
+        let cfg = analyze(&ROM, &Quirks::SCHIP);
+        let terminators: Vec<(Addr, Terminator)> = cfg
+            .blocks
+            .iter()
+            .map(|(&start, block)| (start, block.terminator))
+            .collect();
+
+        assert_eq!(
+            terminators,
+            [
+                (
+                    0x200,
+                    Terminator::Skip {
+                        next: 0x204,
+                        skipped: 0x206
+                    }
+                ),
+                (0x204, Terminator::Jump(0x208)),
+                (0x206, Terminator::Fallthrough(0x208)),
+                (
+                    0x208,
+                    Terminator::Call {
+                        target: 0x210,
+                        ret: 0x20a
+                    }
+                ),
+                (0x20a, Terminator::Jump(0x20a)),
+                (0x210, Terminator::Return),
+            ]
+        );
+        assert_eq!(cfg.blocks[&0x200].addrs, 0x200..0x204);
+
+        // the backends compile the fallthrough into the next block
+        let instructions = cfg.compiled_block(0x206).unwrap();
+        assert_eq!(
+            instructions,
+            [
+                (0x206, Instruction::LdByte(1, 1)),
+                (0x208, Instruction::Call(0x210))
+            ]
+        );
+
+        // `DXYN` ends the blocks of the backends with the `display_wait` quirk
+        let cfg = analyze(&ROM, &Quirks::VIP);
+        assert_eq!(cfg.blocks[&0x210].terminator, Terminator::Continue(0x214));
+        assert_eq!(cfg.blocks[&0x214].terminator, Terminator::Return);
+    }
+
+    #[test]
+    fn finds_subroutines() {
+        #[rustfmt::skip]
+        let rom = [
+            0x22, 0x06, // CALL 0x206
+            0x22, 0x0a, // CALL 0x20a
+            0x12, 0x04, // JP 0x204
+            0x22, 0x0a, // CALL 0x20a
+            0x00, 0xee, // RET
+            0x00, 0xee, // RET
+        ];
+        let cfg = analyze(&rom, &Quirks::SCHIP);
+
+        assert_eq!(
+            cfg.subroutines.values().cloned().collect::<Vec<_>>(),
+            [
+                Subroutine {
+                    entry: 0x206,
+                    blocks: BTreeSet::from([0x206, 0x208]),
+                    callers: BTreeSet::from([0x200]),
+                },
+                Subroutine {
+                    entry: 0x20a,
+                    blocks: BTreeSet::from([0x20a]),
+                    callers: BTreeSet::from([0x202, 0x206]),
+                },
+            ]
+        );
+
+        let cfg = analyze(&ROM, &Quirks::VIP);
+        assert_eq!(
+            cfg.subroutines[&0x210].blocks,
+            BTreeSet::from([0x210, 0x214])
+        );
+    }
+
+    #[test]
+    fn records_indirect_jumps() {
+        #[rustfmt::skip]
+        let rom = [
+            0x60, 0x02, // LD V0, 2
+            0xb2, 0x08, // JP V0, 0x208
+            0x12, 0x04, // JP 0x204
+            0x00, 0x00,
+            0x12, 0x08, // JP 0x208
+        ];
+        let cfg = analyze(&rom, &Quirks::VIP);
+
+        assert_eq!(cfg.indirect_jumps, BTreeSet::from([0x202]));
+        assert_eq!(cfg.blocks.keys().copied().collect::<Vec<_>>(), [0x200]);
+        assert_eq!(cfg.blocks[&0x200].terminator, Terminator::IndirectJump);
+        assert_eq!(cfg.byte_kind(0x208), ByteKind::Unknown);
+        assert!(cfg.compiled_block(0x208).is_none());
+    }
+
+    #[test]
+    fn classifies_bytes() {
+        let cfg = analyze(&ROM, &Quirks::SCHIP);
+        let kinds = |addrs: Range<Addr>| addrs.map(|addr| cfg.byte_kind(addr)).collect::<Vec<_>>();
+
+        assert_eq!(kinds(0x200..0x20c), [ByteKind::Code; 12]);
+        assert_eq!(kinds(0x20c..0x210), [ByteKind::Unknown; 4]);
+        assert_eq!(kinds(0x210..0x216), [ByteKind::Code; 6]);
+        assert_eq!(kinds(0x216..0x220), [ByteKind::Unknown; 10]);
+        assert_eq!(kinds(0x220..0x225), [ByteKind::Data; 5]);
+        // outside of the ROM
+        assert_eq!(cfg.byte_kind(0x225), ByteKind::Unknown);
+        assert_eq!(cfg.byte_kind(0x100), ByteKind::Unknown);
+    }
+
+    #[test]
+    fn reads_the_sprites_of_every_plane() {
+        #[rustfmt::skip]
+        let mut rom = vec![
+            0xf3, 0x01, // PLANE 3
+            0xa2, 0x0c, // LD I, 0x20c
+            0xd0, 0x10, // DRW V0, V1, 0
+            0xf1, 0x01, // PLANE 1
+            0xd0, 0x10, // DRW V0, V1, 0
+            0x12, 0x0a, // JP 0x20a
+        ];
+        rom.resize(rom.len() + 64 + 1, 0xff);
+        let cfg = analyze(&rom, &Quirks::XOCHIP);
+
+        assert_eq!(cfg.bytes[..12], [ByteKind::Code; 12]);
+        assert_eq!(cfg.bytes[12..12 + 64], [ByteKind::Data; 64]);
+        assert_eq!(cfg.bytes[12 + 64], ByteKind::Unknown);
+    }
+
+    #[test]
+    fn formats_dot() {
+        let dot = analyze(&ROM, &Quirks::SCHIP).to_dot();
+
+        assert!(dot.starts_with("// 18 bytes of code, 5 of data and 14 unknown\ndigraph rom {\n"));
+        assert!(dot.contains(
+            "    subgraph cluster_210 {\n        label=\"subroutine 0x210\";\n        b210;\n    }\n"
+        ));
+        assert!(dot.contains("    b200 -> b204;\n    b200 -> b206 [label=\"skip\"];\n"));
+        assert!(
+            dot.contains("    b208 -> b210 [label=\"call\"];\n    b208 -> b20a [style=dashed];\n")
+        );
+        assert!(dot.contains(
+            "    b210 [label=\"0x210: LD I, 0x220\\l0x212: DRW V0, V1, 0x5\\l0x214: RET\\l\"];\n"
+        ));
+        assert!(dot.ends_with("}\n"));
+    }
+}
@@ -1,16 +1,35 @@
-use minifb::{Key, Window, WindowOptions};
+use log::{debug, info};
+use memoffset::offset_of;
 
-use crate::cache::Cache;
+use crate::arena::ArenaStats;
+use crate::backend::Backend;
+use crate::block_cache::BlockCache;
+use crate::builder::Chip8Builder;
+use crate::cache::{Cache, CompileBlock};
+use crate::filter::{DisplayFilter, Filter};
+use crate::gif::GifRecorder;
+use crate::idle::IdleDetector;
+use crate::image::Image;
+use crate::jit::Helper;
+use crate::palette::Palette;
+use crate::quirks::Quirks;
+use crate::save_state;
+use crate::{Addr, Error, Fault, Result};
 
-use std::cell::RefCell;
-use std::rc::Rc;
-use std::time::{Duration, Instant};
+use std::ops::Range;
+use std::path::Path;
+use std::time::Duration;
 
 pub const INSTRUCTION_SIZE_BYTES: u64 = 2;
+/// Size of the XO-CHIP `F000 NNNN` instruction.
+pub const LONG_INSTRUCTION_SIZE_BYTES: u64 = 4;
 
 pub const PIXEL_DRAW: u32 = u32::MAX;
 pub const PIXEL_CLEAN: u32 = 0;
 
+/// The colors of the four possible pixel values if both XO-CHIP planes are used.
+pub const PALETTE: Palette = [PIXEL_CLEAN, PIXEL_DRAW, 0xaaaaaa, 0x555555];
+
 #[allow(non_upper_case_globals)]
 pub const WINDOW_WIDTHu16: u16 = 64;
 #[allow(non_upper_case_globals)]
@@ -24,6 +43,18 @@ pub const WINDOW_SIZEu16: u16 = WINDOW_WIDTHu16 * WINDOW_HEIGHTu16;
 #[allow(non_upper_case_globals)]
 pub const WINDOW_SIZEusize: usize = WINDOW_SIZEu16 as usize;
 
+#[allow(non_upper_case_globals)]
+pub const HIRES_WIDTHusize: usize = 2 * WINDOW_WIDTHusize;
+#[allow(non_upper_case_globals)]
+pub const HIRES_HEIGHTusize: usize = 2 * WINDOW_HEIGHTusize;
+/// The framebuffer is always big enough for the high resolution mode.
+#[allow(non_upper_case_globals)]
+pub const FB_SIZEusize: usize = HIRES_WIDTHusize * HIRES_HEIGHTusize;
+
+pub const AMOUNT_PLANES: usize = 2;
+pub const AUDIO_PATTERN_SIZE: usize = 16;
+pub const DEFAULT_PITCH: u64 = 64;
+
 pub const AMOUNT_KEYS: usize = 16;
 
 pub const SPRITES: [u8; 16 * 5] = [
@@ -31,20 +62,40 @@ pub const SPRITES: [u8; 16 * 5] = [
     0x20, 0x60, 0x20, 0x20, 0x70, // 1
     0xf0, 0x10, 0xf0, 0x80, 0xf0, // 2
     0xf0, 0x10, 0xf0, 0x10, 0xf0, // 3
-    0xf0, 0x80, 0xf0, 0x90, 0xf0, // 4
+    0x90, 0x90, 0xf0, 0x10, 0x10, // 4
     0xf0, 0x80, 0xf0, 0x10, 0xf0, // 5
     0xf0, 0x80, 0xf0, 0x90, 0xf0, // 6
     0xf0, 0x10, 0x20, 0x40, 0x40, // 7
     0xf0, 0x90, 0xf0, 0x90, 0xf0, // 8
     0xf0, 0x90, 0xf0, 0x10, 0xf0, // 9
     0xf0, 0x90, 0xf0, 0x90, 0x90, // A
-    0xe0, 0xe0, 0xe0, 0x90, 0xe0, // B
+    0xe0, 0x90, 0xe0, 0x90, 0xe0, // B
     0xf0, 0x80, 0x80, 0x80, 0xf0, // C
     0xe0, 0x90, 0x90, 0x90, 0xe0, // D
     0xf0, 0x80, 0xf0, 0x80, 0xf0, // E
     0xf0, 0x80, 0xf0, 0x80, 0x80, // F
 ];
 
+pub const BIG_SPRITES_ADDRESS: u64 = SPRITES.len() as u64;
+pub const BIG_SPRITES: [u8; 16 * 10] = [
+    0xff, 0xff, 0xc3, 0xc3, 0xc3, 0xc3, 0xc3, 0xc3, 0xff, 0xff, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xff, 0xff, // 1
+    0xff, 0xff, 0x03, 0x03, 0xff, 0xff, 0xc0, 0xc0, 0xff, 0xff, // 2
+    0xff, 0xff, 0x03, 0x03, 0xff, 0xff, 0x03, 0x03, 0xff, 0xff, // 3
+    0xc3, 0xc3, 0xc3, 0xc3, 0xff, 0xff, 0x03, 0x03, 0x03, 0x03, // 4
+    0xff, 0xff, 0xc0, 0xc0, 0xff, 0xff, 0x03, 0x03, 0xff, 0xff, // 5
+    0xff, 0xff, 0xc0, 0xc0, 0xff, 0xff, 0xc3, 0xc3, 0xff, 0xff, // 6
+    0xff, 0xff, 0x03, 0x03, 0x06, 0x0c, 0x18, 0x18, 0x18, 0x18, // 7
+    0xff, 0xff, 0xc3, 0xc3, 0xff, 0xff, 0xc3, 0xc3, 0xff, 0xff, // 8
+    0xff, 0xff, 0xc3, 0xc3, 0xff, 0xff, 0x03, 0x03, 0xff, 0xff, // 9
+    0x7e, 0xff, 0xc3, 0xc3, 0xc3, 0xff, 0xff, 0xc3, 0xc3, 0xc3, // A
+    0xfc, 0xfc, 0xc3, 0xc3, 0xfc, 0xfc, 0xc3, 0xc3, 0xfc, 0xfc, // B
+    0x3c, 0xff, 0xc3, 0xc0, 0xc0, 0xc0, 0xc0, 0xc3, 0xff, 0x3c, // C
+    0xfc, 0xfe, 0xc3, 0xc3, 0xc3, 0xc3, 0xc3, 0xc3, 0xfe, 0xfc, // D
+    0xff, 0xff, 0xc0, 0xc0, 0xff, 0xff, 0xc0, 0xc0, 0xff, 0xff, // E
+    0xff, 0xff, 0xc0, 0xc0, 0xff, 0xff, 0xc0, 0xc0, 0xc0, 0xc0, // F
+];
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Chip8Field {
     I,
@@ -54,6 +105,34 @@ pub enum Chip8Field {
     Reg(u8),
     Delay,
     Sound,
+    Planes,
+    Pitch,
+    Fault,
+    Helper(Helper),
+}
+
+impl Chip8Field {
+    /// Returns the offset of the field in [Chip8State], which compiled code uses to access it.
+    pub fn offset(&self) -> usize {
+        match self {
+            Self::I => offset_of!(Chip8State, i),
+            Self::PC => offset_of!(Chip8State, pc),
+            Self::SP => offset_of!(Chip8State, sp),
+            Self::Stack => offset_of!(Chip8State, stack),
+            Self::Reg(index) => {
+                assert!(usize::from(*index) < Chip8::AMOUNT_REGISTERS);
+                offset_of!(Chip8State, regs) + usize::from(*index) * std::mem::size_of::<u64>()
+            }
+            Self::Delay => offset_of!(Chip8State, delay),
+            Self::Sound => offset_of!(Chip8State, sound),
+            Self::Planes => offset_of!(Chip8State, planes),
+            Self::Pitch => offset_of!(Chip8State, pitch),
+            Self::Fault => offset_of!(Chip8State, fault),
+            Self::Helper(helper) => {
+                offset_of!(Chip8State, helpers) + *helper as usize * std::mem::size_of::<u64>()
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -67,51 +146,198 @@ pub struct Chip8State {
     pub pc: u64,
     pub sp: u64,
     pub stack: [u64; Chip8::MAX_AMOUNT_STACK],
-    pub window: Window,
-    pub fb: [bool; WINDOW_SIZEusize],
+    /// Every pixel stores one bit per plane.
+    pub fb: [u8; FB_SIZEusize],
+    /// Bitmask of the planes which are affected by drawing operations.
+    pub planes: u64,
+    pub hires: bool,
+    pub audio_pattern: [u8; AUDIO_PATTERN_SIZE],
+    pub pitch: u64,
+    pub flags: [u64; Chip8::AMOUNT_REGISTERS],
     pub keys: [bool; AMOUNT_KEYS],
-    pub tick: Instant,
+    /// The keys which were held in the previous frame.
+    pub previous_keys: [bool; AMOUNT_KEYS],
+    /// The key which got pressed while `FX0A` waits for it to be released, [Chip8::NO_KEY] if
+    /// there's none.
+    pub pressed_key: u64,
     pub help_regs: [u64; Chip8::AMOUNT_REGISTERS],
-    should_run: bool,
+    pub quirks: Quirks,
+    /// Set if the execution has to wait until the next frame starts.
+    pub wait_for_frame: bool,
+    /// The code of the [Fault] which happened, `0` if everything is fine.
+    pub fault: u64,
+    /// The memory from `written_start` to `written_end` got written by the current block, the
+    /// compiled blocks which contain it are dropped afterwards. Empty if nothing was written.
+    pub written_start: u64,
+    pub written_end: u64,
+    /// The addresses of the functions which compiled code calls, see [Helper].
+    pub helpers: [u64; Helper::COUNT],
+    pub(crate) should_run: bool,
+}
+
+impl Chip8State {
+    /// Returns the current `(width, height)` of the screen.
+    pub fn resolution(&self) -> (usize, usize) {
+        if self.hires {
+            (HIRES_WIDTHusize, HIRES_HEIGHTusize)
+        } else {
+            (WINDOW_WIDTHusize, WINDOW_HEIGHTusize)
+        }
+    }
+
+    /// Remembers that the program wrote to `addr`, the code there might have changed.
+    pub fn mark_written(&mut self, addr: Addr) {
+        if self.written_start >= self.written_end {
+            self.written_start = addr;
+            self.written_end = addr + 1;
+        } else {
+            self.written_start = self.written_start.min(addr);
+            self.written_end = self.written_end.max(addr + 1);
+        }
+    }
+
+    /// Returns the memory which got written since the last call, if any.
+    pub fn take_written(&mut self) -> Option<Range<Addr>> {
+        let written = self.written_start..self.written_end;
+        self.written_start = 0;
+        self.written_end = 0;
+
+        (!written.is_empty()).then_some(written)
+    }
+
+    /// The frequency in Hz in which the audio pattern buffer should be played.
+    pub fn playback_rate(&self) -> f64 {
+        4000.0 * 2f64.powf((self.pitch as f64 - 64.0) / 48.0)
+    }
+}
+
+/// The pressed keys of the hexadecimal keypad, bit `n` is set if key `n` is pressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Keys(pub u16);
+
+impl Keys {
+    pub const NONE: Self = Self(0);
+
+    pub fn is_pressed(&self, key: u8) -> bool {
+        self.0 & (1 << (key & 0xf)) != 0
+    }
+
+    pub fn set(&mut self, key: u8, pressed: bool) {
+        let mask = 1 << (key & 0xf);
+        if pressed {
+            self.0 |= mask;
+        } else {
+            self.0 &= !mask;
+        }
+    }
+
+    /// Returns these keys with `key` being pressed.
+    pub fn with(mut self, key: u8) -> Self {
+        self.set(key, true);
+        self
+    }
+}
+
+impl From<[bool; AMOUNT_KEYS]> for Keys {
+    fn from(pressed: [bool; AMOUNT_KEYS]) -> Self {
+        let mut keys = Self::NONE;
+        for (key, pressed) in pressed.into_iter().enumerate() {
+            keys.set(key as u8, pressed);
+        }
+        keys
+    }
+}
+
+impl From<Keys> for [bool; AMOUNT_KEYS] {
+    fn from(keys: Keys) -> Self {
+        std::array::from_fn(|key| keys.is_pressed(key as u8))
+    }
+}
+
+/// What happened in a frame which got executed by [Chip8::step_frame].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FrameResult {
+    /// The amount of executed CHIP-8 instructions.
+    pub instructions: u64,
+    /// `false` if the program exited, for example with `00FD`.
+    pub running: bool,
+    /// Set while the sound timer is active and the buzzer should be audible.
+    pub sound: bool,
+}
+
+/// A copy of the registers of the CHIP-8.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Registers {
+    pub v: [u8; Chip8::AMOUNT_REGISTERS],
+    pub i: Addr,
+    pub pc: Addr,
+    /// The return addresses of the active subroutine calls, the innermost one is the last one.
+    pub stack: Vec<Addr>,
+    pub delay: u8,
+    pub sound: u8,
 }
 
 #[derive(Debug)]
 pub struct Chip8 {
-    state: Rc<RefCell<Chip8State>>,
+    /// The compiled blocks get a pointer to it, so it's boxed to keep its address.
+    state: Box<Chip8State>,
     cache: Cache,
+    /// The amount of instructions which are executed per frame.
+    speed: u64,
+    /// The amount of instructions which got executed in the current frame.
+    executed: u64,
+    /// Finds the idle loops of the current frame.
+    idle: IdleDetector,
+    palette: Palette,
+    filter: DisplayFilter,
+    /// The colors of the screen after the last frame, including the display filter.
+    pixels: Vec<u32>,
+    /// The size of a low resolution pixel in screenshots and recordings.
+    scale: usize,
+    /// The amount of finished frames.
+    frames: u64,
+    recorder: Option<GifRecorder>,
 }
 
+// the emulator has a single owner and can be moved to another thread
+const _: fn() = || {
+    fn assert_send<T: Send>() {}
+    assert_send::<Chip8>();
+};
+
 impl Chip8 {
-    pub const MEM_SIZE: usize = 4096;
+    pub const MEM_SIZE: usize = 0x10000;
+    pub const ADDR_MASK: u64 = Self::MEM_SIZE as u64 - 1;
     pub const AMOUNT_REGISTERS: usize = 16;
     pub const START_ADDRESS: u64 = 0x200;
     pub const MAX_AMOUNT_STACK: usize = 16;
     pub const FREQUENCY: Duration = Duration::new(0, 16000000);
     pub const REG_MAX_VALUE: i32 = 0xff;
+    pub const DEFAULT_SPEED: u64 = 15;
+    /// The value of [Chip8State::pressed_key] while no key is pressed.
+    pub const NO_KEY: u64 = u64::MAX;
+    /// The size of a low resolution pixel in the window.
+    pub const DEFAULT_SCALE: usize = 10;
 
-    pub fn new(binary_content: Vec<u8>) -> Self {
+    /// Creates an emulator which is driven by [Chip8::step_frame]. A
+    /// [Frontend](crate::frontend::Frontend) shows it in a window.
+    pub fn new(binary_content: Vec<u8>, quirks: Quirks) -> Result<Self> {
         if !binary_is_valid(&binary_content) {
-            panic!("ROM is too big");
+            return Err(Error::RomTooBig {
+                size: binary_content.len(),
+                max_size: MAX_ROM_SIZE,
+            });
         }
 
         let mut mem = [0u8; Chip8::MEM_SIZE];
-        for (index, &value) in SPRITES.iter().enumerate() {
-            mem[index] = value;
-        }
-        for (index, &value) in binary_content.iter().enumerate() {
-            mem[Self::START_ADDRESS as usize + index] = value;
-        }
+        mem[..SPRITES.len()].copy_from_slice(&SPRITES);
+        let big_sprites_start = BIG_SPRITES_ADDRESS as usize;
+        mem[big_sprites_start..big_sprites_start + BIG_SPRITES.len()].copy_from_slice(&BIG_SPRITES);
+        let rom_start = Self::START_ADDRESS as usize;
+        mem[rom_start..rom_start + binary_content.len()].copy_from_slice(&binary_content);
 
-        let window = Window::new(
-            "RIP-8",
-            WINDOW_WIDTHusize,
-            WINDOW_HEIGHTusize,
-            WindowOptions::default(),
-        )
-        .unwrap();
-
-        Self {
-            state: Rc::new(RefCell::new(Chip8State {
+        Ok(Self {
+            state: Box::new(Chip8State {
                 mem,
                 regs: [0; Chip8::AMOUNT_REGISTERS],
                 i: 0,
@@ -121,107 +347,743 @@ impl Chip8 {
                 sp: 0,
                 stack: [0; Chip8::MAX_AMOUNT_STACK],
                 should_run: true,
-                fb: [false; WINDOW_SIZEusize],
+                fb: [0; FB_SIZEusize],
+                planes: 1,
+                hires: false,
+                audio_pattern: [0; AUDIO_PATTERN_SIZE],
+                pitch: DEFAULT_PITCH,
+                flags: [0; Self::AMOUNT_REGISTERS],
                 keys: [false; AMOUNT_KEYS],
+                previous_keys: [false; AMOUNT_KEYS],
+                pressed_key: Self::NO_KEY,
                 help_regs: [0; Self::AMOUNT_REGISTERS],
-                tick: Instant::now(),
-                window,
-            })),
-            cache: Cache::new(),
-        }
+                quirks,
+                wait_for_frame: false,
+                fault: 0,
+                written_start: 0,
+                written_end: 0,
+                helpers: Helper::table(),
+            }),
+            cache: Cache::new(Backend::default()),
+            speed: Self::DEFAULT_SPEED,
+            executed: 0,
+            idle: IdleDetector::default(),
+            palette: PALETTE,
+            filter: DisplayFilter::default(),
+            pixels: vec![PALETTE[0]; WINDOW_SIZEusize],
+            scale: Self::DEFAULT_SCALE,
+            frames: 0,
+            recorder: None,
+        })
+    }
+
+    /// Returns a builder for an emulator which is driven by [Chip8::step_frame].
+    pub fn builder() -> Chip8Builder {
+        Chip8Builder::default()
     }
 
-    pub fn run(&mut self) {
-        while self.state.borrow().should_run {
-            let block = self.cache.get_or_compile(self.state.clone());
-            block.execute(self.state.clone());
+    /// Drops all compiled blocks and the block cache, they're compiled again by `backend`.
+    pub fn set_backend(&mut self, backend: Backend) {
+        self.cache = Cache::new(backend);
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.cache.backend()
+    }
 
-            self.tick();
+    /// Loads the blocks which `block_cache` has for the current backend instead of compiling them
+    /// and adds the compiled ones to it.
+    pub fn set_block_cache(&mut self, block_cache: BlockCache) {
+        self.cache.set_block_cache(block_cache);
+    }
+
+    /// Writes the blocks of the block cache to disk, if there's one and it got new blocks.
+    pub fn save_block_cache(&self) -> Result<()> {
+        match self.cache.block_cache() {
+            Some(block_cache) => block_cache.save(),
+            None => Ok(()),
         }
     }
 
-    pub fn tick(&mut self) {
-        self.refresh_window();
-        self.refresh_keys();
+    pub fn block_cache(&self) -> Option<&BlockCache> {
+        self.cache.block_cache()
+    }
+
+    /// Adds a block which was compiled elsewhere, e.g. by `rip8 transpile`. It's dropped like the
+    /// compiled ones when the state is loaded or the backend changes.
+    pub fn insert_block(&mut self, block: CompileBlock) {
+        self.cache.insert(block, &self.state);
+    }
+
+    /// Compiles the block which starts at `addr` without executing it, so it's in the block cache
+    /// before the program gets there.
+    pub fn precompile(&mut self, addr: Addr) -> Result<()> {
+        let pc = self.state.pc;
+        self.state.pc = addr;
+        let result = self.cache.get_or_compile(&self.state).map(|_| ());
+        self.state.pc = pc;
+
+        result
+    }
+
+    /// Returns how much executable memory the compiled blocks take up.
+    pub fn code_stats(&self) -> ArenaStats {
+        self.cache.arena_stats()
+    }
+
+    pub fn set_speed(&mut self, speed: u64) {
+        self.speed = speed;
+    }
+
+    /// Sets the colors of the pixel values, starting with the background.
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    pub fn palette(&self) -> Palette {
+        self.palette
+    }
+
+    /// Sets the size of a low resolution pixel, it has to be set before the window is opened.
+    pub fn set_scale(&mut self, scale: usize) {
+        self.scale = scale.max(1);
+    }
+
+    pub fn scale(&self) -> usize {
+        self.scale
+    }
+
+    pub fn set_filter(&mut self, filter: Filter) {
+        self.filter = DisplayFilter::new(filter);
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.state.should_run
+    }
+
+    /// Stops the emulation, for example because the window got closed.
+    pub fn stop(&mut self) {
+        self.state.should_run = false;
+    }
 
-        std::thread::sleep(Self::FREQUENCY.saturating_sub(self.state.borrow().tick.elapsed()));
+    pub fn frames(&self) -> u64 {
+        self.frames
     }
 
-    pub fn refresh_window(&mut self) {
-        let mut state = self.state.borrow_mut();
+    /// Executes one frame with the given keys being held, shows it in the recording and
+    /// decrements the timers. It doesn't wait for the frame time like a
+    /// [Frontend](crate::frontend::Frontend).
+    pub fn step_frame(&mut self, input: Keys) -> Result<FrameResult> {
+        self.state.keys = input.into();
+
+        let frame_finished = self.execute_frame()?;
+        let instructions = self.executed;
+        if frame_finished {
+            self.present()?;
+            self.finish_frame();
+        }
+
+        Ok(FrameResult {
+            instructions,
+            running: self.state.should_run,
+            sound: self.state.sound > 0,
+        })
+    }
+
+    /// Executes blocks until the current frame is finished. Returns `false` if the emulation
+    /// stopped before. An idle loop finishes the frame early, see [crate::idle].
+    fn execute_frame(&mut self) -> Result<bool> {
+        while self.is_running() {
+            let block = self.cache.get_or_compile(&self.state)?;
+
+            // the loop would run the same way until the timers or the keys change
+            if block.idle.loop_head && self.idle.visit(&self.state) {
+                debug!(
+                    "Idle loop at {:#x}, skipping to the next frame",
+                    self.state.pc
+                );
+                return Ok(true);
+            }
+
+            block.execute(&mut self.state);
+            self.executed += block.instructions;
+            if !block.idle.side_effect_free {
+                self.idle.reset();
+            }
+            // self-modifying code, the block itself already ran with the old instructions
+            if let Some(written) = self.state.take_written() {
+                self.cache.invalidate(written);
+            }
+            self.check_fault()?;
 
-        let mut buffer: Vec<u32> = [0; WINDOW_SIZEusize].to_vec();
-        for entry in state.fb.clone().into_iter().enumerate() {
-            let (index, should_place) = entry;
+            let frame_finished = self.state.wait_for_frame || self.executed >= self.speed;
 
-            if should_place {
-                buffer[index] = PIXEL_DRAW;
+            if frame_finished {
+                return Ok(true);
             }
         }
 
-        state
-            .window
-            .update_with_buffer(&buffer, WINDOW_WIDTHusize, WINDOW_HEIGHTusize)
-            .unwrap();
+        Ok(false)
+    }
 
-        if let Some(key) = state
-            .window
-            .get_keys_pressed(minifb::KeyRepeat::No)
-            .into_iter()
-            .next()
-        {
-            state.should_run = key == Key::Q;
+    fn check_fault(&self) -> Result<()> {
+        let state = &self.state;
+
+        match Fault::from_code(state.fault) {
+            Some(fault) => Err(Error::Emulation {
+                fault,
+                pc: state.pc,
+                stack: state.stack[..state.sp as usize].to_vec(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Applies the display filter to the finished frame and records it.
+    fn present(&mut self) -> Result<()> {
+        let (width, height) = self.state.resolution();
+        self.pixels = self
+            .filter
+            .apply(&self.state.fb[..width * height], &self.palette);
+
+        if let Some(recorder) = &mut self.recorder {
+            recorder.push_frame(&self.pixels, width, height)?;
+        }
+
+        Ok(())
+    }
+
+    /// Starts the next frame and decrements the timers.
+    fn finish_frame(&mut self) {
+        self.executed = 0;
+        self.idle.reset();
+        self.frames += 1;
+
+        let state = &mut self.state;
+        state.previous_keys = state.keys;
+        state.wait_for_frame = false;
+        state.delay = state.delay.saturating_sub(1);
+        state.sound = state.sound.saturating_sub(1);
+    }
+
+    /// Returns the current `(width, height)` of the screen.
+    pub fn resolution(&self) -> (usize, usize) {
+        self.state.resolution()
+    }
+
+    /// Returns the pixels of the screen row by row in the current resolution. Every pixel stores
+    /// one bit per plane, see [Chip8::set_palette] for the colors of the values.
+    pub fn framebuffer(&self) -> &[u8] {
+        let (width, height) = self.state.resolution();
+        &self.state.fb[..width * height]
+    }
+
+    /// Returns the colors of the last finished frame including the display filter, in the
+    /// resolution which the screen had at the end of it.
+    pub fn pixels(&self) -> &[u32] {
+        &self.pixels
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.state.mem
+    }
+
+    pub fn registers(&self) -> Registers {
+        let state = &self.state;
+
+        Registers {
+            v: state.regs.map(|reg| reg as u8),
+            i: state.i,
+            pc: state.pc,
+            stack: state.stack[..state.sp as usize].to_vec(),
+            delay: state.delay as u8,
+            sound: state.sound as u8,
+        }
+    }
+
+    /// Serializes the state of the emulated machine, see [Chip8::load_state].
+    pub fn save_state(&self) -> Vec<u8> {
+        save_state::save(&self.state)
+    }
+
+    /// Restores a state of [Chip8::save_state]. The compiled blocks are dropped because the
+    /// memory might contain different code now.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<()> {
+        save_state::load(&mut self.state, data)?;
+        self.cache.clear();
+        self.idle.reset();
+
+        Ok(())
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    /// Records every shown frame, including the display filter, into an animated GIF. The frames
+    /// have the size of the window when it gets opened.
+    pub fn start_recording<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        self.stop_recording()?;
+
+        let recorder = GifRecorder::create(
+            path,
+            WINDOW_WIDTHusize * self.scale,
+            WINDOW_HEIGHTusize * self.scale,
+        )?;
+        info!("Recording to '{}'", recorder.path().display());
+        self.recorder = Some(recorder);
+
+        Ok(())
+    }
+
+    pub fn stop_recording(&mut self) -> Result<()> {
+        match self.recorder.take() {
+            Some(recorder) => {
+                let path = recorder.path().to_path_buf();
+                recorder.finish()?;
+                info!("Saved recording '{}'", path.display());
+                Ok(())
+            }
+            None => Ok(()),
         }
     }
 
-    pub fn refresh_keys(&mut self) {
-        let mut state = self.state.borrow_mut();
+    /// Returns the screen in the colors of the palette without the display filter. A low
+    /// resolution pixel is [Chip8::set_scale] pixels big, a high resolution pixel half as big.
+    pub fn screenshot(&self) -> Image {
+        let state = &self.state;
+
+        let (width, height) = state.resolution();
+        let pixels: Vec<u32> = state.fb[..width * height]
+            .iter()
+            .map(|&pixel| self.palette[usize::from(pixel)])
+            .collect();
+        let scale = if state.hires {
+            self.scale / 2
+        } else {
+            self.scale
+        };
+
+        Image::scaled(&pixels, width, height, scale)
+    }
+
+    /// Saves [Chip8::screenshot] as PPM if the extension of `path` is `ppm`, otherwise as PNG.
+    pub fn save_screenshot<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.screenshot().save(path)
+    }
+}
+
+const MAX_ROM_SIZE: usize = Chip8::MEM_SIZE - Chip8::START_ADDRESS as usize;
+
+fn binary_is_valid(binary: &[u8]) -> bool {
+    binary.len() <= MAX_ROM_SIZE
+}
+
+/// Reads the big endian word at `addr`, wrapping around at the end of the memory.
+pub fn read_word(mem: &[u8], addr: Addr) -> u16 {
+    u16::from_be_bytes([
+        mem[(addr & Chip8::ADDR_MASK) as usize],
+        mem[((addr + 1) & Chip8::ADDR_MASK) as usize],
+    ])
+}
+
+/// Returns the amount of bytes of the instruction at `addr`.
+pub fn instruction_size(mem: &[u8], addr: Addr) -> u64 {
+    if read_word(mem, addr) == 0xf000 {
+        LONG_INSTRUCTION_SIZE_BYTES
+    } else {
+        INSTRUCTION_SIZE_BYTES
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BACKENDS: [Backend; 3] = [Backend::X86, Backend::Cranelift, Backend::Threaded];
 
-        state
-            .window
-            .get_keys_pressed(minifb::KeyRepeat::No)
+    /// Runs `rom` for a frame on every backend of this build and returns the faults.
+    fn faults(rom: &[u8], quirks: Quirks) -> Vec<(Backend, Option<Fault>)> {
+        BACKENDS
             .into_iter()
-            .for_each(|key: Key| {
-                if let Some(index) = key_value(key) {
-                    state.keys[index as usize] = true;
-                }
-            });
-        state
-            .window
-            .get_keys_released()
+            .filter(Backend::is_available)
+            .map(|backend| {
+                let mut chip8 = Chip8::builder()
+                    .rom(rom)
+                    .quirks(quirks)
+                    .backend(backend)
+                    .speed(1000)
+                    .build()
+                    .unwrap();
+                let fault = match chip8.step_frame(Keys::NONE) {
+                    Err(Error::Emulation { fault, .. }) => Some(fault),
+                    _ => None,
+                };
+                (backend, fault)
+            })
+            .collect()
+    }
+
+    /// Runs `rom` on every backend of this build for a frame per entry of `frames`, the keys are
+    /// held during it.
+    fn run(rom: &[u8], quirks: Quirks, frames: &[Keys]) -> Vec<(Backend, Chip8)> {
+        BACKENDS
             .into_iter()
-            .for_each(|key: Key| {
-                if let Some(index) = key_value(key) {
-                    state.keys[index as usize] = false;
+            .filter(Backend::is_available)
+            .map(|backend| {
+                let mut chip8 = Chip8::builder()
+                    .rom(rom)
+                    .quirks(quirks)
+                    .backend(backend)
+                    .speed(1000)
+                    .build()
+                    .unwrap();
+                for &keys in frames {
+                    chip8.step_frame(keys).unwrap();
                 }
-            });
+                (backend, chip8)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn steps_frames_until_the_exit() {
+        #[rustfmt::skip]
+        let rom = [
+            0x60, 0x03, // LD V0, 3
+            0xf0, 0x18, // LD ST, V0
+            0x71, 0x01, // ADD V1, 1
+            0x31, 0x28, // SE V1, 40
+            0x12, 0x04, // JP 0x204
+            0x00, 0xfd, // EXIT
+        ];
+        for backend in BACKENDS.into_iter().filter(Backend::is_available) {
+            let mut chip8 = Chip8::builder()
+                .rom(rom)
+                .platform(crate::quirks::Platform::Schip)
+                .backend(backend)
+                .speed(10)
+                .build()
+                .unwrap();
 
-        if let Some(index) = key_value(Key::A) {
-            state.should_run = !state.keys[index as usize];
+            let frame = chip8.step_frame(Keys::NONE).unwrap();
+            assert!(frame.running && frame.sound, "{}", backend);
+            assert!(frame.instructions >= 10, "{}", backend);
+
+            let frames: Vec<FrameResult> = (0..20)
+                .map(|_| chip8.step_frame(Keys::NONE).unwrap())
+                .take_while(|frame| frame.running)
+                .collect();
+            assert!(frames.len() < 19, "{}", backend);
+            assert!(!frames.last().unwrap().sound, "{}", backend);
+            assert_eq!(chip8.registers().v[1], 40, "{}", backend);
         }
     }
-}
 
-fn binary_is_valid(binary: &Vec<u8>) -> bool {
-    binary.len() <= Chip8::MEM_SIZE
-}
+    #[test]
+    fn ends_idle_frames_early() {
+        #[rustfmt::skip]
+        let rom = [
+            0x60, 0x03, // LD V0, 3
+            0xf0, 0x15, // LD DT, V0
+            0xf0, 0x07, // LD V0, DT
+            0x30, 0x00, // SE V0, 0
+            0x12, 0x04, // JP 0x204
+            0x00, 0xfd, // EXIT
+        ];
+        for backend in BACKENDS.into_iter().filter(Backend::is_available) {
+            let mut chip8 = Chip8::builder()
+                .rom(rom)
+                .backend(backend)
+                .speed(1000)
+                .build()
+                .unwrap();
+
+            for _ in 0..3 {
+                let frame = chip8.step_frame(Keys::NONE).unwrap();
+                assert!(frame.running, "{}", backend);
+                assert!(frame.instructions < 20, "{}", backend);
+            }
+            assert!(
+                !chip8.step_frame(Keys::NONE).unwrap().running,
+                "{}",
+                backend
+            );
+        }
+    }
+
+    #[test]
+    fn waits_for_a_key_press() {
+        #[rustfmt::skip]
+        let rom = [
+            0xf0, 0x0a, // LD V0, K
+            0x61, 0x01, // LD V1, 1
+            0x12, 0x04, // JP 0x204
+        ];
+        let pressed = |key: u8| Keys(1 << key);
+        let quirks = Quirks {
+            key_release: false,
+            ..Quirks::VIP
+        };
+
+        for (backend, chip8) in run(&rom, quirks, &[Keys::NONE, Keys::NONE]) {
+            assert_eq!(chip8.registers().pc, 0x200, "{}", backend);
+            assert_eq!(chip8.registers().v[1], 0, "{}", backend);
+        }
+        for (backend, chip8) in run(&rom, quirks, &[Keys::NONE, pressed(7)]) {
+            assert_eq!(chip8.registers().v[0], 7, "{}", backend);
+            assert_eq!(chip8.registers().v[1], 1, "{}", backend);
+        }
+
+        // a key which is held since the previous frame doesn't count, only the new one
+        #[rustfmt::skip]
+        let rom = [
+            0xd0, 0x01, // DRW V0, V0, 1, continues in the next frame
+            0xf0, 0x0a, // LD V0, K
+            0x12, 0x04, // JP 0x204
+        ];
+        for (backend, chip8) in run(&rom, quirks, &[pressed(2), pressed(2)]) {
+            assert_eq!(chip8.registers().pc, 0x202, "{}", backend);
+        }
+        let frames = [pressed(2), Keys(pressed(2).0 | pressed(9).0)];
+        for (backend, chip8) in run(&rom, quirks, &frames) {
+            assert_eq!(chip8.registers().v[0], 9, "{}", backend);
+        }
+    }
+
+    #[test]
+    fn waits_for_a_key_release() {
+        #[rustfmt::skip]
+        let rom = [
+            0xf0, 0x0a, // LD V0, K
+            0x61, 0x01, // LD V1, 1
+            0x12, 0x04, // JP 0x204
+        ];
+        let pressed = |key: u8| Keys(1 << key);
+
+        let frames = [Keys::NONE, pressed(7), pressed(7)];
+        for (backend, chip8) in run(&rom, Quirks::VIP, &frames) {
+            assert_eq!(chip8.registers().pc, 0x200, "{}", backend);
+            assert_eq!(chip8.registers().v[1], 0, "{}", backend);
+        }
+        // other keys which are pressed meanwhile are ignored
+        let frames = [Keys::NONE, pressed(7), pressed(3), Keys::NONE];
+        for (backend, chip8) in run(&rom, Quirks::VIP, &frames) {
+            assert_eq!(chip8.registers().v[0], 7, "{}", backend);
+            assert_eq!(chip8.registers().v[1], 1, "{}", backend);
+        }
+    }
+
+    #[test]
+    fn shifts_and_stores_by_the_quirks() {
+        #[rustfmt::skip]
+        let rom = [
+            0x60, 0x03, // LD V0, 3
+            0x61, 0x05, // LD V1, 5
+            0x80, 0x16, // SHR V0, V1
+            0xa3, 0x00, // LD I, 0x300
+            0xf1, 0x55, // LD [I], V0-V1
+            0x12, 0x0a, // JP 0x20a
+        ];
+        for (shift_vy, increment_i) in [(true, true), (false, false)] {
+            let quirks = Quirks {
+                shift_vy,
+                increment_i,
+                ..Quirks::SCHIP
+            };
+            for (backend, chip8) in run(&rom, quirks, &[Keys::NONE]) {
+                let registers = chip8.registers();
+                assert_eq!(registers.v[0], if shift_vy { 2 } else { 1 }, "{}", backend);
+                assert_eq!(registers.v[0xf], 1, "{}", backend);
+                let i = if increment_i { 0x302 } else { 0x300 };
+                assert_eq!(registers.i, i, "{}", backend);
+            }
+        }
+    }
+
+    #[test]
+    fn jumps_by_the_quirk() {
+        #[rustfmt::skip]
+        let rom = [
+            0x60, 0x04, // LD V0, 4
+            0x62, 0x0a, // LD V2, 10
+            0xb2, 0x04, // JP V0, 0x204 or JP V2, 0x204
+            0x00, 0x00,
+            0x12, 0x08, // JP 0x208
+            0x00, 0x00,
+            0x00, 0x00,
+            0x12, 0x0e, // JP 0x20e
+        ];
+        for jump_vx in [false, true] {
+            let quirks = Quirks {
+                jump_vx,
+                ..Quirks::SCHIP
+            };
+            for (backend, chip8) in run(&rom, quirks, &[Keys::NONE]) {
+                let pc = if jump_vx { 0x20e } else { 0x208 };
+                assert_eq!(chip8.registers().pc, pc, "{}", backend);
+            }
+        }
+    }
+
+    #[test]
+    fn wraps_or_clips_sprites() {
+        #[rustfmt::skip]
+        let rom = [
+            0x60, 0x7e, // LD V0, 126, wraps around to 62
+            0x61, 0x3e, // LD V1, 62, wraps around to 30
+            0xa2, 0x12, // LD I, 0x212
+            0xd0, 0x13, // DRW V0, V1, 3
+            0x82, 0xf0, // LD V2, VF
+            0x63, 0x00, // LD V3, 0
+            0xd3, 0x31, // DRW V3, V3, 1
+            0x12, 0x0e, // JP 0x20e
+            0x00, 0x00,
+            0xff, 0xff, 0xff,
+        ];
+        for clip_sprites in [false, true] {
+            let quirks = Quirks {
+                clip_sprites,
+                ..Quirks::XOCHIP
+            };
+            for (backend, chip8) in run(&rom, quirks, &[Keys::NONE]) {
+                let (width, _) = chip8.resolution();
+                let lit = |x: usize, y: usize| chip8.framebuffer()[x + y * width] != 0;
+                let amount = chip8
+                    .framebuffer()
+                    .iter()
+                    .filter(|&&pixel| pixel != 0)
+                    .count();
+                let registers = chip8.registers();
+
+                assert_eq!(registers.v[2], 0, "{}", backend);
+                assert!(lit(62, 30) && lit(63, 31), "{}", backend);
+                if clip_sprites {
+                    assert_eq!(registers.v[0xf], 0, "{}", backend);
+                    assert_eq!(amount, 4 + 8, "{}", backend);
+                    assert!(lit(0, 0) && !lit(0, 30), "{}", backend);
+                } else {
+                    // the second sprite hits the wrapped part of the first one
+                    assert_eq!(registers.v[0xf], 1, "{}", backend);
+                    assert_eq!(amount, 24 - 6 + 2, "{}", backend);
+                    assert!(!lit(0, 0) && lit(0, 30) && lit(5, 31), "{}", backend);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn draws_and_collides_per_plane() {
+        #[rustfmt::skip]
+        let mut rom = vec![
+            0xf3, 0x01, // PLANE 3
+            0xa2, 0x20, // LD I, 0x220
+            0xd0, 0x01, // DRW V0, V0, 1
+            0x82, 0xf0, // LD V2, VF
+            0xf2, 0x01, // PLANE 2
+            0xa2, 0x22, // LD I, 0x222
+            0xd0, 0x01, // DRW V0, V0, 1, clears the pixel of the first sprite in plane 2
+            0x83, 0xf0, // LD V3, VF
+            0xf1, 0x01, // PLANE 1
+            0xa2, 0x24, // LD I, 0x224
+            0xd0, 0x01, // DRW V0, V0, 1, doesn't hit plane 2
+            0x12, 0x16, // JP 0x216
+        ];
+        rom.resize(0x20, 0);
+        rom.extend([0x80, 0x80, 0xc0, 0x00, 0x40]);
+
+        for (backend, chip8) in run(&rom, Quirks::XOCHIP, &[Keys::NONE]) {
+            let registers = chip8.registers();
+            assert_eq!(&chip8.framebuffer()[..3], [0b01, 0b11, 0b00], "{}", backend);
+            assert_eq!(registers.v[2], 0, "{}", backend);
+            assert_eq!(registers.v[3], 1, "{}", backend);
+            assert_eq!(registers.v[0xf], 0, "{}", backend);
+        }
+    }
+
+    #[test]
+    fn call_overflows_the_stack() {
+        // calls itself forever
+        let rom = [0x22, 0x00];
+        for stack_depth in [12, 16, 17, 1000] {
+            let quirks = Quirks {
+                stack_depth,
+                ..Quirks::VIP
+            };
+            for (backend, fault) in faults(&rom, quirks) {
+                assert_eq!(fault, Some(Fault::StackOverflow), "{}", backend);
+            }
+        }
+    }
 
-fn key_value(key: Key) -> Option<u8> {
-    match key {
-        Key::Key1 => Some(0x1),
-        Key::Key2 => Some(0x2),
-        Key::Key3 => Some(0x3),
-        Key::Key4 => Some(0x4),
-        Key::Key5 => Some(0x5),
-        Key::Key6 => Some(0x6),
-        Key::Key7 => Some(0x7),
-        Key::Key8 => Some(0x8),
-        Key::Key9 => Some(0x9),
-        Key::A => Some(0xA),
-        Key::B => Some(0xB),
-        Key::C => Some(0xC),
-        Key::E => Some(0xE),
-        Key::F => Some(0xF),
-        _ => None,
+    #[test]
+    fn call_stops_at_the_stack_depth() {
+        let rom = [0x22, 0x00];
+        for backend in BACKENDS.into_iter().filter(Backend::is_available) {
+            let mut chip8 = Chip8::builder()
+                .rom(rom)
+                .platform(crate::quirks::Platform::Vip)
+                .backend(backend)
+                .speed(1000)
+                .build()
+                .unwrap();
+            assert!(chip8.step_frame(Keys::NONE).is_err());
+            assert_eq!(chip8.registers().stack.len(), 12, "{}", backend);
+            assert_eq!(chip8.registers().pc, 0x200, "{}", backend);
+        }
+    }
+
+    #[test]
+    fn ret_underflows_the_stack() {
+        let rom = [0x00, 0xee];
+        for (backend, fault) in faults(&rom, Quirks::VIP) {
+            assert_eq!(fault, Some(Fault::StackUnderflow), "{}", backend);
+        }
+    }
+
+    #[test]
+    fn executes_overwritten_code() {
+        #[rustfmt::skip]
+        let rom = [
+            0xa2, 0x10, // LD I, 0x210
+            0x22, 0x10, // CALL 0x210
+            0x60, 0x62, // LD V0, 0x62
+            0x61, 0x05, // LD V1, 0x05
+            0xf1, 0x55, // LD [I], V0-V1, replaces LD V2, 1 with LD V2, 5
+            0x22, 0x10, // CALL 0x210
+            0x12, 0x0c, // JP 0x20c
+            0x00, 0x00,
+            0x62, 0x01, // LD V2, 1
+            0x00, 0xee, // RET
+        ];
+        for backend in BACKENDS.into_iter().filter(Backend::is_available) {
+            let mut chip8 = Chip8::builder().rom(rom).backend(backend).build().unwrap();
+            chip8.step_frame(Keys::NONE).unwrap();
+            assert_eq!(chip8.registers().v[2], 5, "{}", backend);
+        }
+    }
+
+    #[test]
+    fn skips_over_an_overwritten_instruction() {
+        #[rustfmt::skip]
+        let rom = [
+            0xa2, 0x13, // LD I, 0x213
+            0x22, 0x10, // CALL 0x210
+            0xf0, 0x55, // LD [I], V0, turns PLANE 0 into a long LD I
+            0x22, 0x10, // CALL 0x210
+            0x12, 0x08, // JP 0x208
+            0x00, 0x00,
+            0x00, 0x00,
+            0x00, 0x00,
+            0x30, 0x00, // SE V0, 0
+            0xf0, 0x01, // PLANE 0
+            0x72, 0x01, // ADD V2, 1
+            0x00, 0xee, // RET
+        ];
+        for backend in BACKENDS.into_iter().filter(Backend::is_available) {
+            let mut chip8 = Chip8::builder().rom(rom).backend(backend).build().unwrap();
+            chip8.step_frame(Keys::NONE).unwrap();
+            assert_eq!(chip8.registers().v[2], 1, "{}", backend);
+        }
     }
 }
@@ -0,0 +1,68 @@
+use serde::Deserialize;
+
+use crate::filter::Filter;
+use crate::keymap::{HotkeyOverrides, KeyBindings, KeyPreset};
+use crate::palette::PaletteOverrides;
+use crate::{Error, Result};
+
+use std::env;
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+/// The user settings which are shared by all ROMs.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub keys: KeyConfig,
+    pub display: DisplayConfig,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeyConfig {
+    pub preset: Option<KeyPreset>,
+    /// Replaces the keys of CHIP-8 keys, both of the preset and of the ROM database.
+    pub bindings: KeyBindings,
+    pub hotkeys: HotkeyOverrides,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DisplayConfig {
+    /// The size of a low resolution pixel in the window.
+    pub scale: Option<usize>,
+    pub palette: PaletteOverrides,
+    pub filter: Option<Filter>,
+}
+
+impl Config {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let json = read_to_string(path).map_err(|source| Error::Io {
+            path: path.into(),
+            source,
+        })?;
+
+        serde_json::from_str(&json).map_err(|source| Error::Config {
+            path: path.into(),
+            source,
+        })
+    }
+
+    /// Loads the config from [Config::default_path] or returns the default config if there's none.
+    pub fn load_default() -> Result<Self> {
+        match Self::default_path() {
+            Some(path) if path.is_file() => Self::load(path),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    /// `$XDG_CONFIG_HOME/rip8/config.json` or `~/.config/rip8/config.json`.
+    pub fn default_path() -> Option<PathBuf> {
+        let config_dir = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+
+        Some(config_dir.join("rip8").join("config.json"))
+    }
+}
@@ -0,0 +1,580 @@
+//! Lowers CHIP-8 blocks to Cranelift IR, which takes care of the register allocation and the
+//! calling convention of the host.
+
+use cranelift_codegen::control::ControlPlane;
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{
+    types, AbiParam, Block, Function, InstBuilder, MemFlags, Signature, UserFuncName, Value,
+};
+use cranelift_codegen::isa::{CallConv, OwnedTargetIsa};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_codegen::Context;
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+use log::debug;
+
+use crate::arena::CodeArena;
+use crate::cache::CompileBlock;
+use crate::chip8::{self, Chip8, Chip8Field, Chip8State};
+use crate::error::{Error, Fault, Result};
+use crate::instruction::Instruction;
+use crate::jit::Helper;
+use crate::quirks::Quirks;
+use crate::Addr;
+
+use std::fmt;
+
+/// Compiles blocks for the host with Cranelift. It keeps its buffers between the blocks.
+pub struct Compiler {
+    isa: OwnedTargetIsa,
+    context: Context,
+    function_context: FunctionBuilderContext,
+}
+
+impl fmt::Debug for Compiler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Compiler")
+            .field("isa", &self.isa.name())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Compiler {
+    pub fn new() -> Result<Self> {
+        let mut flags = settings::builder();
+        flags
+            .set("opt_level", "speed")
+            .map_err(|err| Error::Cranelift(err.to_string()))?;
+
+        let isa = cranelift_native::builder()
+            .map_err(|err| Error::Cranelift(err.to_string()))?
+            .finish(settings::Flags::new(flags))
+            .map_err(|err| Error::Cranelift(err.to_string()))?;
+
+        Ok(Self {
+            isa,
+            context: Context::new(),
+            function_context: FunctionBuilderContext::new(),
+        })
+    }
+
+    /// Compiles the block which starts at the pc of `state`, like [crate::jit::compile].
+    pub fn compile(&mut self, state: &Chip8State, arena: &mut CodeArena) -> Result<CompileBlock> {
+        let pointer = self.isa.pointer_type();
+        let call_conv = self.isa.default_call_conv();
+
+        let mut signature = Signature::new(call_conv);
+        signature.params.push(AbiParam::new(pointer));
+        self.context.clear();
+        self.context.func = Function::with_name_signature(UserFuncName::default(), signature);
+
+        let mut builder = FunctionBuilder::new(&mut self.context.func, &mut self.function_context);
+        let entry = builder.create_block();
+        let exit = builder.create_block();
+        builder.append_block_params_for_function_params(entry);
+        builder.switch_to_block(entry);
+        let state_ptr = builder.block_params(entry)[0];
+
+        let mut translator = Translator {
+            builder,
+            state: state_ptr,
+            exit,
+            pointer,
+            call_conv,
+            mem: &state.mem,
+            quirks: state.quirks,
+            addr: state.pc,
+            instructions: 0,
+        };
+        translator.translate_block()?;
+        let instructions = translator.instructions;
+        let end_addr = translator.next_addr();
+
+        let mut builder = translator.builder;
+        builder.ins().jump(exit, &[]);
+        builder.switch_to_block(exit);
+        builder.ins().return_(&[]);
+        builder.seal_all_blocks();
+        builder.finalize();
+
+        let compiled = self
+            .context
+            .compile(&*self.isa, &mut ControlPlane::default())
+            .map_err(|err| Error::Cranelift(err.inner.to_string()))?;
+        // the code is moved around and stored on disk, so it has to be position independent
+        if !compiled.buffer.relocs().is_empty() {
+            return Err(Error::Cranelift(
+                "the compiled block needs relocations".to_string(),
+            ));
+        }
+
+        CompileBlock::new(
+            arena,
+            compiled.code_buffer(),
+            state.pc..end_addr,
+            instructions,
+        )
+    }
+}
+
+struct Translator<'a> {
+    builder: FunctionBuilder<'a>,
+    /// The pointer to the [Chip8State].
+    state: Value,
+    /// Returns from the compiled block.
+    exit: Block,
+    pointer: types::Type,
+    call_conv: CallConv,
+    mem: &'a [u8],
+    quirks: Quirks,
+    /// The address of the instruction which is currently translated.
+    addr: Addr,
+    /// The amount of translated instructions.
+    instructions: u64,
+}
+
+impl Translator<'_> {
+    fn translate_block(&mut self) -> Result<()> {
+        loop {
+            let instruction = Instruction::decode(self.mem, self.addr).ok_or_else(|| {
+                Error::UnknownInstruction {
+                    addr: self.addr,
+                    instruction: chip8::read_word(self.mem, self.addr),
+                }
+            })?;
+            debug!("Lowering '{}' at {:#x}", instruction, self.addr);
+            self.instructions += 1;
+
+            if !self.translate(instruction) {
+                return Ok(());
+            }
+
+            self.addr += chip8::instruction_size(self.mem, self.addr);
+        }
+    }
+
+    /// Lowers `instruction` and returns `false` if the block ends with it.
+    fn translate(&mut self, instruction: Instruction) -> bool {
+        match instruction {
+            Instruction::Scd(n) => self.call_and_continue(Helper::Scd, &[n]),
+            Instruction::Scu(n) => self.call_and_continue(Helper::Scu, &[n]),
+            Instruction::Cls => self.call_and_continue(Helper::Cls, &[]),
+            Instruction::Ret => {
+                self.ret();
+                false
+            }
+            Instruction::Scr => self.call_and_continue(Helper::Scr, &[]),
+            Instruction::Scl => self.call_and_continue(Helper::Scl, &[]),
+            Instruction::Exit => {
+                self.call(Helper::Exit, &[]);
+                self.set_pc_to_next();
+                false
+            }
+            Instruction::Low => self.call_and_continue(Helper::Low, &[]),
+            Instruction::High => self.call_and_continue(Helper::High, &[]),
+            Instruction::Sys(_) => {
+                // machine code routines of the COSMAC VIP can't be executed
+                self.set_pc_to_next();
+                false
+            }
+            Instruction::Jp(nnn) => {
+                let target = self.constant(u64::from(nnn));
+                self.store(Chip8Field::PC, target);
+                false
+            }
+            Instruction::Call(nnn) => {
+                self.call_subroutine(nnn);
+                false
+            }
+            Instruction::SeByte(x, kk) => {
+                let vx = self.reg(x);
+                let condition = self.builder.ins().icmp_imm(IntCC::Equal, vx, i64::from(kk));
+                self.skip_if(condition);
+                false
+            }
+            Instruction::SneByte(x, kk) => {
+                let vx = self.reg(x);
+                let condition = self
+                    .builder
+                    .ins()
+                    .icmp_imm(IntCC::NotEqual, vx, i64::from(kk));
+                self.skip_if(condition);
+                false
+            }
+            Instruction::SeReg(x, y) => {
+                let (vx, vy) = (self.reg(x), self.reg(y));
+                let condition = self.builder.ins().icmp(IntCC::Equal, vx, vy);
+                self.skip_if(condition);
+                false
+            }
+            Instruction::SneReg(x, y) => {
+                let (vx, vy) = (self.reg(x), self.reg(y));
+                let condition = self.builder.ins().icmp(IntCC::NotEqual, vx, vy);
+                self.skip_if(condition);
+                false
+            }
+            Instruction::SaveRange(x, y) => self.call_and_continue(Helper::LdIRange, &[x, y]),
+            Instruction::LoadRange(x, y) => self.call_and_continue(Helper::LdRangeI, &[x, y]),
+            Instruction::LdByte(x, kk) => {
+                let value = self.constant(u64::from(kk));
+                self.set_reg(x, value);
+                self.next()
+            }
+            Instruction::AddByte(x, kk) => {
+                let vx = self.reg(x);
+                let sum = self.builder.ins().iadd_imm(vx, i64::from(kk));
+                let sum = self.mask_byte(sum);
+                self.set_reg(x, sum);
+                self.next()
+            }
+            Instruction::LdReg(x, y) => {
+                let vy = self.reg(y);
+                self.set_reg(x, vy);
+                self.next()
+            }
+            Instruction::Or(x, y) => {
+                let (vx, vy) = (self.reg(x), self.reg(y));
+                let result = self.builder.ins().bor(vx, vy);
+                self.logic_result(x, result)
+            }
+            Instruction::And(x, y) => {
+                let (vx, vy) = (self.reg(x), self.reg(y));
+                let result = self.builder.ins().band(vx, vy);
+                self.logic_result(x, result)
+            }
+            Instruction::Xor(x, y) => {
+                let (vx, vy) = (self.reg(x), self.reg(y));
+                let result = self.builder.ins().bxor(vx, vy);
+                self.logic_result(x, result)
+            }
+            Instruction::AddReg(x, y) => {
+                let (vx, vy) = (self.reg(x), self.reg(y));
+                let sum = self.builder.ins().iadd(vx, vy);
+                // the carry is the ninth bit
+                let carry = self.builder.ins().ushr_imm(sum, 8);
+                let sum = self.mask_byte(sum);
+                self.set_reg_and_vf(x, sum, carry);
+                self.next()
+            }
+            Instruction::Sub(x, y) => {
+                let (vx, vy) = (self.reg(x), self.reg(y));
+                self.subtract(x, vx, vy);
+                self.next()
+            }
+            Instruction::Subn(x, y) => {
+                let (vx, vy) = (self.reg(x), self.reg(y));
+                self.subtract(x, vy, vx);
+                self.next()
+            }
+            Instruction::Shr(x, y) => {
+                let source = self.reg(if self.quirks.shift_vy { y } else { x });
+                let flag = self.builder.ins().band_imm(source, 1);
+                let result = self.builder.ins().ushr_imm(source, 1);
+                self.set_reg_and_vf(x, result, flag);
+                self.next()
+            }
+            Instruction::Shl(x, y) => {
+                let source = self.reg(if self.quirks.shift_vy { y } else { x });
+                let flag = self.builder.ins().ushr_imm(source, 7);
+                let flag = self.builder.ins().band_imm(flag, 1);
+                let result = self.builder.ins().ishl_imm(source, 1);
+                let result = self.mask_byte(result);
+                self.set_reg_and_vf(x, result, flag);
+                self.next()
+            }
+            Instruction::LdI(nnn) => {
+                let value = self.constant(u64::from(nnn));
+                self.store(Chip8Field::I, value);
+                self.next()
+            }
+            Instruction::JpV0(nnn) => {
+                let reg = if self.quirks.jump_vx {
+                    ((nnn >> 8) & 0xf) as u8
+                } else {
+                    0
+                };
+                let offset = self.reg(reg);
+                let target = self.builder.ins().iadd_imm(offset, i64::from(nnn));
+                self.store(Chip8Field::PC, target);
+                false
+            }
+            Instruction::Rnd(x, kk) => self.call_and_continue(Helper::Rnd, &[x, kk]),
+            Instruction::Drw(x, y, n) => {
+                self.call(Helper::Drw, &[x, y, n]);
+                self.set_pc_to_next();
+                // the block has to end to be able to wait for the next frame
+                !self.quirks.display_wait
+            }
+            Instruction::Skp(x) => {
+                self.call(Helper::Skp, &[x]);
+                self.advance_pc();
+                false
+            }
+            Instruction::Sknp(x) => {
+                self.call(Helper::Sknp, &[x]);
+                self.advance_pc();
+                false
+            }
+            Instruction::Plane(n) => {
+                let planes = self.constant(u64::from(n & 0x3));
+                self.store(Chip8Field::Planes, planes);
+                self.next()
+            }
+            Instruction::Audio => self.call_and_continue(Helper::Audio, &[]),
+            Instruction::LdXDt(x) => {
+                let delay = self.load(Chip8Field::Delay);
+                self.set_reg(x, delay);
+                self.next()
+            }
+            Instruction::LdK(x) => {
+                // ld_k sets pc itself, it stays here while no key is pressed
+                self.call(Helper::LdK, &[x]);
+                false
+            }
+            Instruction::LdDtX(x) => {
+                let vx = self.reg(x);
+                self.store(Chip8Field::Delay, vx);
+                self.next()
+            }
+            Instruction::LdSt(x) => {
+                let vx = self.reg(x);
+                self.store(Chip8Field::Sound, vx);
+                self.next()
+            }
+            Instruction::AddI(x) => {
+                let (vx, i) = (self.reg(x), self.load(Chip8Field::I));
+                let sum = self.builder.ins().iadd(i, vx);
+                let sum = self.builder.ins().band_imm(sum, Chip8::ADDR_MASK as i64);
+                self.store(Chip8Field::I, sum);
+                self.next()
+            }
+            Instruction::LdF(x) => self.call_and_continue(Helper::LdF, &[x]),
+            Instruction::LdHf(x) => self.call_and_continue(Helper::LdHf, &[x]),
+            Instruction::LdB(x) => self.call_and_continue(Helper::LdB, &[x]),
+            Instruction::Pitch(x) => {
+                let vx = self.reg(x);
+                self.store(Chip8Field::Pitch, vx);
+                self.next()
+            }
+            Instruction::LdIX(x) => {
+                self.call(Helper::LdIRange, &[0, x]);
+                self.increment_i(x);
+                self.next()
+            }
+            Instruction::LdXI(x) => {
+                self.call(Helper::LdRangeI, &[0, x]);
+                self.increment_i(x);
+                self.next()
+            }
+            Instruction::LdRX(x) => self.call_and_continue(Helper::LdRX, &[x]),
+            Instruction::LdXR(x) => self.call_and_continue(Helper::LdXR, &[x]),
+        }
+    }
+
+    fn constant(&mut self, value: u64) -> Value {
+        self.builder.ins().iconst(types::I64, value as i64)
+    }
+
+    fn load(&mut self, field: Chip8Field) -> Value {
+        self.builder.ins().load(
+            types::I64,
+            MemFlags::trusted(),
+            self.state,
+            field.offset() as i32,
+        )
+    }
+
+    fn store(&mut self, field: Chip8Field, value: Value) {
+        self.builder.ins().store(
+            MemFlags::trusted(),
+            value,
+            self.state,
+            field.offset() as i32,
+        );
+    }
+
+    fn reg(&mut self, index: u8) -> Value {
+        self.load(Chip8Field::Reg(index))
+    }
+
+    fn set_reg(&mut self, index: u8, value: Value) {
+        self.store(Chip8Field::Reg(index), value);
+    }
+
+    /// Stores Vf last, in case Vx is Vf.
+    fn set_reg_and_vf(&mut self, index: u8, value: Value, flag: Value) {
+        self.set_reg(index, value);
+        self.set_reg(0xf, flag);
+    }
+
+    fn mask_byte(&mut self, value: Value) -> Value {
+        self.builder
+            .ins()
+            .band_imm(value, i64::from(Chip8::REG_MAX_VALUE))
+    }
+
+    /// Stores `minuend - subtrahend` in Vx and sets Vf if there's no borrow.
+    fn subtract(&mut self, x: u8, minuend: Value, subtrahend: Value) {
+        let no_borrow =
+            self.builder
+                .ins()
+                .icmp(IntCC::UnsignedGreaterThanOrEqual, minuend, subtrahend);
+        let flag = self.builder.ins().uextend(types::I64, no_borrow);
+        let difference = self.builder.ins().isub(minuend, subtrahend);
+        let difference = self.mask_byte(difference);
+        self.set_reg_and_vf(x, difference, flag);
+    }
+
+    /// Stores the result of a bitwise operation and resets Vf if the `vf_reset` quirk is enabled.
+    fn logic_result(&mut self, x: u8, result: Value) -> bool {
+        self.set_reg(x, result);
+        if self.quirks.vf_reset {
+            let zero = self.constant(0);
+            self.set_reg(0xf, zero);
+        }
+        self.next()
+    }
+
+    /// Adds `X + 1` to `I` if the `increment_i` quirk is enabled.
+    fn increment_i(&mut self, x: u8) {
+        if self.quirks.increment_i {
+            let i = self.load(Chip8Field::I);
+            let i = self.builder.ins().iadd_imm(i, i64::from(x) + 1);
+            let i = self.builder.ins().band_imm(i, Chip8::ADDR_MASK as i64);
+            self.store(Chip8Field::I, i);
+        }
+    }
+
+    fn next_addr(&self) -> Addr {
+        self.addr + chip8::instruction_size(self.mem, self.addr)
+    }
+
+    fn set_pc_to_next(&mut self) {
+        let next = self.constant(self.next_addr());
+        self.store(Chip8Field::PC, next);
+    }
+
+    /// Adds the size of the instruction to pc, which got changed by a helper.
+    fn advance_pc(&mut self) {
+        let pc = self.load(Chip8Field::PC);
+        let size = chip8::instruction_size(self.mem, self.addr);
+        let pc = self.builder.ins().iadd_imm(pc, size as i64);
+        self.store(Chip8Field::PC, pc);
+    }
+
+    /// Sets pc to the next instruction and continues the block.
+    fn next(&mut self) -> bool {
+        self.set_pc_to_next();
+        true
+    }
+
+    /// Skips the next instruction if `condition` is set.
+    fn skip_if(&mut self, condition: Value) {
+        let next = self.next_addr();
+        let skipped = next + chip8::instruction_size(self.mem, next);
+
+        let next = self.constant(next);
+        let skipped = self.constant(skipped);
+        let pc = self.builder.ins().select(condition, skipped, next);
+        self.store(Chip8Field::PC, pc);
+    }
+
+    /// Calls `helper` with the state pointer and `args`. The address is read from the helper table
+    /// of the state, so the code doesn't need any relocations.
+    fn call(&mut self, helper: Helper, args: &[u8]) {
+        let mut signature = Signature::new(self.call_conv);
+        signature.params.push(AbiParam::new(self.pointer));
+        signature
+            .params
+            .extend(args.iter().map(|_| AbiParam::new(types::I64)));
+        let signature = self.builder.import_signature(signature);
+
+        let callee = self.builder.ins().load(
+            self.pointer,
+            MemFlags::trusted(),
+            self.state,
+            Chip8Field::Helper(helper).offset() as i32,
+        );
+        let mut values = vec![self.state];
+        for &arg in args {
+            values.push(self.constant(u64::from(arg)));
+        }
+
+        self.builder.ins().call_indirect(signature, callee, &values);
+    }
+
+    fn call_and_continue(&mut self, helper: Helper, args: &[u8]) -> bool {
+        self.call(helper, args);
+        self.next()
+    }
+
+    /// Reports `fault` and leaves the block, pc stays at this instruction.
+    fn fault(&mut self, fault: Fault) {
+        let code = self.constant(fault as u64);
+        self.store(Chip8Field::Fault, code);
+        self.builder.ins().jump(self.exit, &[]);
+    }
+
+    fn call_subroutine(&mut self, nnn: u16) {
+        let push = self.builder.create_block();
+        let overflow = self.builder.create_block();
+
+        let sp = self.load(Chip8Field::SP);
+        let has_space = self.builder.ins().icmp_imm(
+            IntCC::UnsignedLessThan,
+            sp,
+            self.quirks.stack_limit() as i64,
+        );
+        self.builder.ins().brif(has_space, push, &[], overflow, &[]);
+
+        self.builder.switch_to_block(overflow);
+        self.fault(Fault::StackOverflow);
+
+        // the return address is stored at `stack + sp * 8`
+        self.builder.switch_to_block(push);
+        let slot = self.stack_slot(sp);
+        let return_addr = self.constant(self.next_addr());
+        self.builder.ins().store(
+            MemFlags::trusted(),
+            return_addr,
+            slot,
+            Chip8Field::Stack.offset() as i32,
+        );
+
+        let sp = self.builder.ins().iadd_imm(sp, 1);
+        self.store(Chip8Field::SP, sp);
+        let target = self.constant(u64::from(nnn));
+        self.store(Chip8Field::PC, target);
+    }
+
+    fn ret(&mut self) {
+        let pop = self.builder.create_block();
+        let underflow = self.builder.create_block();
+
+        let sp = self.load(Chip8Field::SP);
+        self.builder.ins().brif(sp, pop, &[], underflow, &[]);
+
+        self.builder.switch_to_block(underflow);
+        self.fault(Fault::StackUnderflow);
+
+        self.builder.switch_to_block(pop);
+        let sp = self.builder.ins().iadd_imm(sp, -1);
+        self.store(Chip8Field::SP, sp);
+        let slot = self.stack_slot(sp);
+        let return_addr = self.builder.ins().load(
+            types::I64,
+            MemFlags::trusted(),
+            slot,
+            Chip8Field::Stack.offset() as i32,
+        );
+        self.store(Chip8Field::PC, return_addr);
+    }
+
+    /// Returns `state + sp * 8`, the stack offset has to be added.
+    fn stack_slot(&mut self, sp: Value) -> Value {
+        let offset = self
+            .builder
+            .ins()
+            .imul_imm(sp, std::mem::size_of::<u64>() as i64);
+        self.builder.ins().iadd(self.state, offset)
+    }
+}
@@ -0,0 +1,142 @@
+mod terminal;
+
+pub use terminal::{Terminal, TerminalMode};
+
+use minifb::{Key, KeyRepeat, ScaleMode, Window, WindowOptions};
+
+use crate::chip8::{WINDOW_HEIGHTusize, WINDOW_WIDTHusize};
+use crate::Result;
+
+use std::fmt;
+
+/// Shows the frames and reads the keyboard.
+pub trait Display: fmt::Debug {
+    /// Shows a frame whose pixels are stored as `0x00rrggbb` and processes the input.
+    fn update_with_buffer(&mut self, buffer: &[u32], width: usize, height: usize) -> Result<()>;
+
+    /// Processes the input without showing a new frame.
+    fn update(&mut self);
+
+    fn is_open(&self) -> bool;
+
+    /// Returns the keys which are held down.
+    fn get_keys(&self) -> Vec<Key>;
+
+    /// Returns the keys which got pressed since the previous update.
+    fn get_keys_pressed(&self) -> Vec<Key>;
+
+    fn is_key_pressed(&self, key: Key) -> bool {
+        self.get_keys_pressed().contains(&key)
+    }
+
+    /// Sets the color which fills the area around the screen, stored as `0x00rrggbb`.
+    fn set_background_color(&mut self, _color: u32) {}
+}
+
+/// Opens a window in which a low resolution pixel is `scale` pixels big. The window can be resized,
+/// the frames are scaled by the largest integer which fits and centered in it.
+pub fn open_window(scale: usize) -> Result<Window> {
+    let window = Window::new(
+        "RIP-8",
+        WINDOW_WIDTHusize * scale,
+        WINDOW_HEIGHTusize * scale,
+        WindowOptions {
+            resize: true,
+            scale_mode: ScaleMode::Center,
+            ..WindowOptions::default()
+        },
+    )?;
+
+    Ok(window)
+}
+
+/// The largest integer by which a frame of `width` x `height` can be scaled to fit into a window
+/// of `window_width` x `window_height`, at least 1.
+fn integer_scale(
+    (window_width, window_height): (usize, usize),
+    width: usize,
+    height: usize,
+) -> usize {
+    if width == 0 || height == 0 {
+        return 1;
+    }
+
+    (window_width / width).min(window_height / height).max(1)
+}
+
+/// Repeats every pixel of `buffer` `scale` times in both directions.
+fn upscale(buffer: &[u32], width: usize, scale: usize) -> Vec<u32> {
+    buffer
+        .chunks(width)
+        .flat_map(|row| {
+            let row: Vec<u32> = row
+                .iter()
+                .flat_map(|&pixel| std::iter::repeat_n(pixel, scale))
+                .collect();
+            std::iter::repeat_n(row, scale).flatten()
+        })
+        .collect()
+}
+
+impl Display for Window {
+    fn update_with_buffer(&mut self, buffer: &[u32], width: usize, height: usize) -> Result<()> {
+        let scale = integer_scale(self.get_size(), width, height);
+        if scale == 1 {
+            Window::update_with_buffer(self, buffer, width, height)?;
+        } else {
+            let scaled = upscale(buffer, width, scale);
+            Window::update_with_buffer(self, &scaled, width * scale, height * scale)?;
+        }
+
+        Ok(())
+    }
+
+    fn update(&mut self) {
+        Window::update(self);
+    }
+
+    fn is_open(&self) -> bool {
+        Window::is_open(self)
+    }
+
+    fn get_keys(&self) -> Vec<Key> {
+        Window::get_keys(self)
+    }
+
+    fn get_keys_pressed(&self) -> Vec<Key> {
+        Window::get_keys_pressed(self, KeyRepeat::No)
+    }
+
+    fn is_key_pressed(&self, key: Key) -> bool {
+        Window::is_key_pressed(self, key, KeyRepeat::No)
+    }
+
+    fn set_background_color(&mut self, color: u32) {
+        let [blue, green, red, _] = color.to_le_bytes().map(usize::from);
+        Window::set_background_color(self, red, green, blue);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scales_by_the_largest_integer_which_fits() {
+        assert_eq!(integer_scale((640, 320), 64, 32), 10);
+        assert_eq!(integer_scale((700, 330), 64, 32), 10);
+        assert_eq!(integer_scale((1000, 330), 128, 64), 5);
+        assert_eq!(integer_scale((63, 31), 64, 32), 1);
+        assert_eq!(integer_scale((640, 320), 0, 0), 1);
+    }
+
+    #[test]
+    fn repeats_the_pixels() {
+        let buffer = [1, 2, 3, 4];
+        assert_eq!(
+            upscale(&buffer, 2, 2),
+            [1, 1, 2, 2, 1, 1, 2, 2, 3, 3, 4, 4, 3, 3, 4, 4]
+        );
+        assert_eq!(upscale(&buffer, 2, 1), buffer);
+    }
+}
@@ -0,0 +1,511 @@
+use fnv::FnvHashMap;
+use minifb::Key;
+use serde::Deserialize;
+
+use super::Display;
+use crate::{Error, Result};
+
+use std::fmt::{self, Write as _};
+use std::io::{self, Read, Write};
+use std::mem::MaybeUninit;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// Terminals only report key presses, so a key counts as held down until it hasn't been repeated
+/// for this long.
+const HOLD_DURATION: Duration = Duration::new(0, 150_000_000);
+const ESCAPE: u8 = 0x1b;
+const CTRL_C: u8 = 0x03;
+/// The dots of a braille character for the pixels of a 2x4 block, row by row.
+const BRAILLE_DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+const BRAILLE_BASE: u32 = 0x2800;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TerminalMode {
+    /// Two pixels per character in full color.
+    #[default]
+    HalfBlocks,
+    /// Eight pixels per character, only one color per character.
+    Braille,
+}
+
+impl TerminalMode {
+    pub const NAMES: [&'static str; 2] = ["halfblocks", "braille"];
+}
+
+impl FromStr for TerminalMode {
+    type Err = String;
+
+    fn from_str(name: &str) -> std::result::Result<Self, Self::Err> {
+        match name.to_lowercase().as_str() {
+            "halfblocks" | "halfblock" => Ok(Self::HalfBlocks),
+            "braille" => Ok(Self::Braille),
+            _ => Err(format!("unknown terminal mode '{}'", name)),
+        }
+    }
+}
+
+/// Draws the screen with Unicode characters and 24-bit colors and reads the keys from stdin,
+/// which is put into raw mode.
+pub struct Terminal {
+    mode: TerminalMode,
+    /// The settings of stdin which are restored when the terminal is dropped.
+    original: libc::termios,
+    /// The keys which are held down and when they were pressed the last time.
+    held: FnvHashMap<Key, Instant>,
+    pressed: Vec<Key>,
+    open: bool,
+    /// The previous frame and its resolution, unchanged frames aren't drawn again.
+    previous: Vec<u32>,
+    resolution: (usize, usize),
+    background: u32,
+}
+
+impl Terminal {
+    pub fn new(mode: TerminalMode) -> Result<Self> {
+        let original = unsafe {
+            let mut original = MaybeUninit::uninit();
+            if libc::tcgetattr(libc::STDIN_FILENO, original.as_mut_ptr()) != 0 {
+                return Err(Error::Terminal(io::Error::last_os_error()));
+            }
+            original.assume_init()
+        };
+
+        // no echo, no line buffering, no signals and reads which don't block
+        let mut raw = original;
+        unsafe { libc::cfmakeraw(&mut raw) };
+        raw.c_oflag |= libc::OPOST;
+        raw.c_cc[libc::VMIN] = 0;
+        raw.c_cc[libc::VTIME] = 0;
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) } != 0 {
+            return Err(Error::Terminal(io::Error::last_os_error()));
+        }
+
+        let mut terminal = Self {
+            mode,
+            original,
+            held: FnvHashMap::default(),
+            pressed: Vec::new(),
+            open: true,
+            previous: Vec::new(),
+            resolution: (0, 0),
+            background: 0,
+        };
+        // alternate screen, hidden cursor
+        terminal
+            .write("\x1b[?1049h\x1b[?25l\x1b[2J")
+            .map_err(Error::Terminal)?;
+
+        Ok(terminal)
+    }
+
+    fn write(&mut self, output: &str) -> io::Result<()> {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(output.as_bytes())?;
+        stdout.flush()
+    }
+
+    fn read_input(&mut self) {
+        let mut input = Vec::new();
+        let mut buffer = [0u8; 64];
+        let mut stdin = io::stdin().lock();
+
+        while let Ok(amount @ 1..) = stdin.read(&mut buffer) {
+            input.extend_from_slice(&buffer[..amount]);
+        }
+
+        let now = Instant::now();
+        self.pressed.clear();
+        self.held
+            .retain(|_, pressed_at| now.duration_since(*pressed_at) < HOLD_DURATION);
+
+        let mut bytes = input.as_slice();
+        while !bytes.is_empty() {
+            if bytes[0] == CTRL_C {
+                self.open = false;
+            }
+
+            let (key, length) = parse_key(bytes);
+            if let Some(key) = key {
+                // repeated presses keep the key held down but don't count as new presses
+                if self.held.insert(key, now).is_none() {
+                    self.pressed.push(key);
+                }
+            }
+            bytes = &bytes[length..];
+        }
+    }
+}
+
+impl Display for Terminal {
+    fn update_with_buffer(&mut self, buffer: &[u32], width: usize, height: usize) -> Result<()> {
+        self.read_input();
+
+        if self.resolution == (width, height) && self.previous == buffer {
+            return Ok(());
+        }
+        self.previous = buffer.to_vec();
+
+        let mut output = if self.resolution != (width, height) {
+            self.resolution = (width, height);
+            String::from("\x1b[2J")
+        } else {
+            String::new()
+        };
+
+        output.push_str(&match self.mode {
+            TerminalMode::HalfBlocks => render_half_blocks(buffer, width, height, self.background),
+            TerminalMode::Braille => render_braille(buffer, width, height, self.background),
+        });
+
+        self.write(&output).map_err(Error::Terminal)
+    }
+
+    fn update(&mut self) {
+        self.read_input();
+    }
+
+    fn is_open(&self) -> bool {
+        self.open
+    }
+
+    fn get_keys(&self) -> Vec<Key> {
+        self.held.keys().copied().collect()
+    }
+
+    fn get_keys_pressed(&self) -> Vec<Key> {
+        self.pressed.clone()
+    }
+
+    fn set_background_color(&mut self, color: u32) {
+        self.background = color;
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = self.write("\x1b[0m\x1b[?25h\x1b[?1049l");
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original) };
+    }
+}
+
+impl fmt::Debug for Terminal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Terminal")
+            .field("mode", &self.mode)
+            .field("held", &self.held)
+            .field("pressed", &self.pressed)
+            .field("open", &self.open)
+            .field("resolution", &self.resolution)
+            .field("background", &self.background)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Builds the output of a frame and only changes the colors if they differ from the previous
+/// character.
+#[derive(Debug)]
+struct Cells {
+    output: String,
+    colors: Option<(u32, u32)>,
+}
+
+impl Default for Cells {
+    fn default() -> Self {
+        Self {
+            output: String::from("\x1b[H"),
+            colors: None,
+        }
+    }
+}
+
+impl Cells {
+    fn push(&mut self, foreground: u32, background: u32, character: char) {
+        if self.colors != Some((foreground, background)) {
+            self.colors = Some((foreground, background));
+
+            let [fg_blue, fg_green, fg_red, _] = foreground.to_le_bytes();
+            let [bg_blue, bg_green, bg_red, _] = background.to_le_bytes();
+            let _ = write!(
+                self.output,
+                "\x1b[38;2;{};{};{};48;2;{};{};{}m",
+                fg_red, fg_green, fg_blue, bg_red, bg_green, bg_blue
+            );
+        }
+
+        self.output.push(character);
+    }
+
+    fn end_line(&mut self) {
+        self.output.push_str("\x1b[0m\r\n");
+        self.colors = None;
+    }
+}
+
+/// Draws two pixels per character, `background` fills the missing row of odd heights.
+fn render_half_blocks(buffer: &[u32], width: usize, height: usize, background: u32) -> String {
+    let mut cells = Cells::default();
+
+    for y in (0..height).step_by(2) {
+        for x in 0..width {
+            let top = buffer[y * width + x];
+            let bottom = buffer
+                .get((y + 1) * width + x)
+                .copied()
+                .unwrap_or(background);
+
+            cells.push(top, bottom, '▀');
+        }
+        cells.end_line();
+    }
+
+    cells.output
+}
+
+/// Draws 2x4 pixels per character, the pixels which don't have the `background` color are dots.
+fn render_braille(buffer: &[u32], width: usize, height: usize, background: u32) -> String {
+    let mut cells = Cells::default();
+
+    for y in (0..height).step_by(4) {
+        for x in (0..width).step_by(2) {
+            let mut dots = 0;
+            let mut color = None;
+
+            for (dy, row) in BRAILLE_DOTS.iter().enumerate() {
+                for (dx, dot) in row.iter().enumerate() {
+                    if y + dy >= height || x + dx >= width {
+                        continue;
+                    }
+
+                    let pixel = buffer[(y + dy) * width + x + dx];
+                    if pixel != background {
+                        dots |= dot;
+                        color.get_or_insert(pixel);
+                    }
+                }
+            }
+
+            let character = char::from_u32(BRAILLE_BASE + dots).unwrap_or(' ');
+            cells.push(color.unwrap_or(background), background, character);
+        }
+        cells.end_line();
+    }
+
+    cells.output
+}
+
+/// Returns the key at the start of `bytes` and the amount of bytes which belong to it.
+fn parse_key(bytes: &[u8]) -> (Option<Key>, usize) {
+    if bytes[0] == ESCAPE {
+        return parse_escape_sequence(bytes);
+    }
+
+    let key = match bytes[0].to_ascii_uppercase() {
+        byte @ b'0'..=b'9' => digit_key(byte - b'0'),
+        byte @ b'A'..=b'Z' => letter_key(byte - b'A'),
+        b' ' => Some(Key::Space),
+        b'\r' | b'\n' => Some(Key::Enter),
+        b'\t' => Some(Key::Tab),
+        0x7f | 0x08 => Some(Key::Backspace),
+        b',' => Some(Key::Comma),
+        b'.' => Some(Key::Period),
+        b'-' => Some(Key::Minus),
+        b'=' => Some(Key::Equal),
+        b'/' => Some(Key::Slash),
+        b';' => Some(Key::Semicolon),
+        b'\'' => Some(Key::Apostrophe),
+        b'[' => Some(Key::LeftBracket),
+        b']' => Some(Key::RightBracket),
+        b'\\' => Some(Key::Backslash),
+        b'`' => Some(Key::Backquote),
+        _ => None,
+    };
+
+    (key, 1)
+}
+
+/// Parses the sequences of the arrow and function keys, a single escape is the escape key.
+fn parse_escape_sequence(bytes: &[u8]) -> (Option<Key>, usize) {
+    match bytes {
+        [ESCAPE, b'[', b'A', ..] => (Some(Key::Up), 3),
+        [ESCAPE, b'[', b'B', ..] => (Some(Key::Down), 3),
+        [ESCAPE, b'[', b'C', ..] => (Some(Key::Right), 3),
+        [ESCAPE, b'[', b'D', ..] => (Some(Key::Left), 3),
+        [ESCAPE, b'[', b'H', ..] => (Some(Key::Home), 3),
+        [ESCAPE, b'[', b'F', ..] => (Some(Key::End), 3),
+        [ESCAPE, b'O', byte @ b'P'..=b'S', ..] => (function_key(byte - b'P' + 1), 3),
+        [ESCAPE, b'[', rest @ ..] => {
+            // `ESC [ <number> ~`
+            let digits = rest.iter().take_while(|byte| byte.is_ascii_digit()).count();
+            if rest.get(digits) != Some(&b'~') {
+                return (None, (3 + digits).min(bytes.len()));
+            }
+
+            let number: u32 = std::str::from_utf8(&rest[..digits])
+                .ok()
+                .and_then(|number| number.parse().ok())
+                .unwrap_or(0);
+            let key = match number {
+                2 => Some(Key::Insert),
+                3 => Some(Key::Delete),
+                5 => Some(Key::PageUp),
+                6 => Some(Key::PageDown),
+                15 => function_key(5),
+                17..=21 => function_key(number as u8 - 11),
+                23 | 24 => function_key(number as u8 - 12),
+                _ => None,
+            };
+
+            (key, 3 + digits)
+        }
+        _ => (Some(Key::Escape), 1),
+    }
+}
+
+fn digit_key(digit: u8) -> Option<Key> {
+    const DIGITS: [Key; 10] = [
+        Key::Key0,
+        Key::Key1,
+        Key::Key2,
+        Key::Key3,
+        Key::Key4,
+        Key::Key5,
+        Key::Key6,
+        Key::Key7,
+        Key::Key8,
+        Key::Key9,
+    ];
+
+    DIGITS.get(usize::from(digit)).copied()
+}
+
+fn letter_key(letter: u8) -> Option<Key> {
+    const LETTERS: [Key; 26] = [
+        Key::A,
+        Key::B,
+        Key::C,
+        Key::D,
+        Key::E,
+        Key::F,
+        Key::G,
+        Key::H,
+        Key::I,
+        Key::J,
+        Key::K,
+        Key::L,
+        Key::M,
+        Key::N,
+        Key::O,
+        Key::P,
+        Key::Q,
+        Key::R,
+        Key::S,
+        Key::T,
+        Key::U,
+        Key::V,
+        Key::W,
+        Key::X,
+        Key::Y,
+        Key::Z,
+    ];
+
+    LETTERS.get(usize::from(letter)).copied()
+}
+
+fn function_key(number: u8) -> Option<Key> {
+    const FUNCTION_KEYS: [Key; 12] = [
+        Key::F1,
+        Key::F2,
+        Key::F3,
+        Key::F4,
+        Key::F5,
+        Key::F6,
+        Key::F7,
+        Key::F8,
+        Key::F9,
+        Key::F10,
+        Key::F11,
+        Key::F12,
+    ];
+
+    FUNCTION_KEYS
+        .get(usize::from(number).wrapping_sub(1))
+        .copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: u32 = 0xffffff;
+    const WHITE_ON_BLACK: &str = "\x1b[38;2;255;255;255;48;2;0;0;0m";
+    const BLACK_ON_BLACK: &str = "\x1b[38;2;0;0;0;48;2;0;0;0m";
+    const END_LINE: &str = "\x1b[0m\r\n";
+
+    #[test]
+    fn parses_keys() {
+        assert_eq!(parse_key(b"a"), (Some(Key::A), 1));
+        assert_eq!(parse_key(b"Zx"), (Some(Key::Z), 1));
+        assert_eq!(parse_key(b"7"), (Some(Key::Key7), 1));
+        assert_eq!(parse_key(b" "), (Some(Key::Space), 1));
+        assert_eq!(parse_key(b"\r"), (Some(Key::Enter), 1));
+        assert_eq!(parse_key(b"\x7f"), (Some(Key::Backspace), 1));
+        assert_eq!(parse_key(b"["), (Some(Key::LeftBracket), 1));
+        assert_eq!(parse_key(&[CTRL_C]), (None, 1));
+        assert_eq!(parse_key("ä".as_bytes()), (None, 1));
+    }
+
+    #[test]
+    fn parses_escape_sequences() {
+        assert_eq!(parse_key(b"\x1b[A"), (Some(Key::Up), 3));
+        assert_eq!(parse_key(b"\x1b[Bq"), (Some(Key::Down), 3));
+        assert_eq!(parse_key(b"\x1b[C"), (Some(Key::Right), 3));
+        assert_eq!(parse_key(b"\x1b[D"), (Some(Key::Left), 3));
+        assert_eq!(parse_key(b"\x1bOP"), (Some(Key::F1), 3));
+        assert_eq!(parse_key(b"\x1bOS"), (Some(Key::F4), 3));
+        assert_eq!(parse_key(b"\x1b[15~"), (Some(Key::F5), 5));
+        assert_eq!(parse_key(b"\x1b[24~"), (Some(Key::F12), 5));
+        assert_eq!(parse_key(b"\x1b[3~"), (Some(Key::Delete), 4));
+        assert_eq!(parse_key(b"\x1b[99~"), (None, 5));
+
+        // a lone escape is the escape key
+        assert_eq!(parse_key(b"\x1b"), (Some(Key::Escape), 1));
+        assert_eq!(parse_key(b"\x1b\x1b[A"), (Some(Key::Escape), 1));
+
+        // truncated sequences are skipped without reading beyond the input
+        assert_eq!(parse_key(b"\x1b["), (None, 2));
+        assert_eq!(parse_key(b"\x1b[15"), (None, 4));
+        assert_eq!(parse_key(b"\x1b[1a"), (None, 4));
+    }
+
+    #[test]
+    fn renders_half_blocks() {
+        // the missing bottom row of the odd height is the background
+        let output = render_half_blocks(&[WHITE, 0, WHITE], 1, 3, 0);
+        let line = format!("{}▀{}", WHITE_ON_BLACK, END_LINE);
+
+        assert_eq!(output, format!("\x1b[H{}{}", line, line));
+    }
+
+    #[test]
+    fn renders_braille() {
+        #[rustfmt::skip]
+        let buffer = [
+            WHITE, 0, 0,
+            0, 0, 0,
+            0, 0, 0,
+            0, WHITE, 0,
+            0, 0, WHITE,
+        ];
+        let output = render_braille(&buffer, 3, 5, 0);
+
+        assert_eq!(
+            output,
+            format!(
+                "\x1b[H{}⢁{}⠀{}{}⠀{}⠁{}",
+                WHITE_ON_BLACK, BLACK_ON_BLACK, END_LINE, BLACK_ON_BLACK, WHITE_ON_BLACK, END_LINE
+            )
+        );
+    }
+}
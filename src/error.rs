@@ -0,0 +1,182 @@
+use iced_x86::IcedError;
+
+use crate::backend::Backend;
+use crate::Addr;
+
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Errors of the emulated program which are detected by the compiled code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u64)]
+pub enum Fault {
+    StackOverflow = 1,
+    StackUnderflow = 2,
+}
+
+impl Fault {
+    /// Converts the value of [crate::chip8::Chip8State::fault], `0` means that there's no fault.
+    pub fn from_code(code: u64) -> Option<Self> {
+        match code {
+            1 => Some(Self::StackOverflow),
+            2 => Some(Self::StackUnderflow),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::StackOverflow => write!(f, "stack overflow"),
+            Self::StackUnderflow => write!(f, "stack underflow"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// A file couldn't be read.
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    /// The ROM database file isn't valid JSON or doesn't match the expected format.
+    RomDatabase {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    /// A file couldn't be written.
+    Write {
+        path: PathBuf,
+        source: io::Error,
+    },
+    /// The config file isn't valid JSON or doesn't match the expected format.
+    Config {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    /// The ROM doesn't fit into the memory.
+    RomTooBig {
+        size: usize,
+        max_size: usize,
+    },
+    /// The recompiler reached an instruction it doesn't know.
+    UnknownInstruction {
+        addr: Addr,
+        instruction: u16,
+    },
+    /// The generated x86 code couldn't be assembled.
+    Assembler(IcedError),
+    /// No executable memory could be mapped for a compiled block.
+    ExecutableMemory(io::Error),
+    /// Cranelift couldn't be set up for the host or failed to compile a block.
+    Cranelift(String),
+    /// The backend isn't part of this build.
+    BackendUnavailable(Backend),
+    /// `rip8 aot` couldn't create the executable or it can't run its game.
+    Aot(String),
+    Window(minifb::Error),
+    /// The terminal couldn't be put into raw mode or written to.
+    Terminal(io::Error),
+    /// The thread which runs the emulation couldn't be started.
+    Thread(io::Error),
+    /// The save state is damaged or was created by an incompatible version.
+    SaveState(&'static str),
+    /// The emulated program did something invalid.
+    Emulation {
+        fault: Fault,
+        pc: Addr,
+        stack: Vec<Addr>,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io { path, source } => {
+                write!(f, "couldn't read '{}': {}", path.display(), source)
+            }
+            Self::RomDatabase { path, source } => {
+                write!(f, "invalid ROM database '{}': {}", path.display(), source)
+            }
+            Self::Write { path, source } => {
+                write!(f, "couldn't write '{}': {}", path.display(), source)
+            }
+            Self::Config { path, source } => {
+                write!(f, "invalid config file '{}': {}", path.display(), source)
+            }
+            Self::RomTooBig { size, max_size } => write!(
+                f,
+                "the ROM has {} bytes but at most {} bytes fit into the memory",
+                size, max_size
+            ),
+            Self::UnknownInstruction { addr, instruction } => write!(
+                f,
+                "unknown instruction {:#06x} at address {:#x}",
+                instruction, addr
+            ),
+            Self::Assembler(err) => write!(f, "couldn't assemble the compiled block: {}", err),
+            Self::ExecutableMemory(err) => write!(f, "couldn't map executable memory: {}", err),
+            Self::Cranelift(err) => write!(f, "cranelift error: {}", err),
+            Self::BackendUnavailable(backend) => write!(
+                f,
+                "the {} backend isn't available in this build, enable the '{}' feature",
+                backend, backend
+            ),
+            Self::Aot(err) => write!(f, "ahead-of-time compilation failed: {}", err),
+            Self::Window(err) => write!(f, "window error: {}", err),
+            Self::Terminal(err) => write!(f, "terminal error: {}", err),
+            Self::Thread(err) => write!(f, "couldn't start the emulation thread: {}", err),
+            Self::SaveState(reason) => write!(f, "invalid save state: {}", reason),
+            Self::Emulation { fault, pc, stack } => {
+                write!(f, "{} at address {:#x}, stack: [", fault, pc)?;
+                for (index, addr) in stack.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{:#x}", addr)?;
+                }
+                write!(f, "]")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io { source, .. } => Some(source),
+            Self::RomDatabase { source, .. } => Some(source),
+            Self::Write { source, .. } => Some(source),
+            Self::Config { source, .. } => Some(source),
+            Self::Assembler(err) => Some(err),
+            Self::ExecutableMemory(err) => Some(err),
+            Self::Window(err) => Some(err),
+            Self::Terminal(err) => Some(err),
+            Self::Thread(err) => Some(err),
+            Self::RomTooBig { .. }
+            | Self::UnknownInstruction { .. }
+            | Self::Cranelift(_)
+            | Self::BackendUnavailable(_)
+            | Self::Aot(_)
+            | Self::SaveState(_)
+            | Self::Emulation { .. } => None,
+        }
+    }
+}
+
+impl From<IcedError> for Error {
+    fn from(err: IcedError) -> Self {
+        Self::Assembler(err)
+    }
+}
+
+impl From<minifb::Error> for Error {
+    fn from(err: minifb::Error) -> Self {
+        Self::Window(err)
+    }
+}
@@ -0,0 +1,362 @@
+//! The C ABI of the emulator, the declarations are in `include/rip8.h`.
+//!
+//! The emulator is headless, the host reads the framebuffer after every frame and draws it itself.
+//!
+//! Panics can't unwind into the host, every function catches them and reports them as an error.
+//! The emulator is unloaded afterwards because its state might be inconsistent.
+
+use crate::chip8::{Chip8, Keys};
+use crate::rom_db::RomDatabase;
+
+use std::any::Any;
+use std::ffi::CString;
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+/// The function succeeded.
+pub const RIP8_OK: c_int = 0;
+/// The emulated program exited, for example with `00FD`.
+pub const RIP8_EXITED: c_int = 1;
+/// The function failed or panicked, see `rip8_last_error`. A panic also unloads the ROM.
+pub const RIP8_ERROR: c_int = -1;
+
+/// An emulator which is used through the C ABI.
+pub struct Rip8 {
+    /// `None` until a ROM is loaded.
+    chip8: Option<Chip8>,
+    keys: Keys,
+    /// The message of the last error.
+    error: Option<CString>,
+}
+
+impl Rip8 {
+    fn set_error(&mut self, message: String) {
+        // the messages don't contain NUL bytes, but if they do they're cut off there
+        let message = message.split('\0').next().unwrap_or_default().to_string();
+        self.error = CString::new(message).ok();
+    }
+
+    fn chip8(&mut self) -> Option<&mut Chip8> {
+        if self.chip8.is_none() {
+            self.set_error("no ROM is loaded".to_string());
+        }
+
+        self.chip8.as_mut()
+    }
+}
+
+/// Returns the message of a panic.
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
+
+/// Calls `f` with the emulator and returns `error` if it panics.
+///
+/// # Safety
+/// `rip8` has to be a valid emulator.
+unsafe fn catch_panic<T>(rip8: *mut Rip8, error: T, f: impl FnOnce(&mut Rip8) -> T) -> T {
+    match panic::catch_unwind(AssertUnwindSafe(|| f(&mut *rip8))) {
+        Ok(result) => result,
+        Err(payload) => {
+            let rip8 = &mut *rip8;
+            rip8.chip8 = None;
+            rip8.set_error(format!(
+                "the emulator panicked, load the ROM again: {}",
+                panic_message(&*payload)
+            ));
+            error
+        }
+    }
+}
+
+/// What happened in the frame which got executed by `rip8_step_frame`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Rip8Frame {
+    /// The amount of executed CHIP-8 instructions.
+    pub instructions: u64,
+    /// Set while the sound timer is active and the buzzer should be audible.
+    pub sound: bool,
+}
+
+/// Creates an emulator without a ROM, it has to be freed with `rip8_destroy`. Returns null if it
+/// couldn't be created.
+#[no_mangle]
+pub extern "C" fn rip8_create() -> *mut Rip8 {
+    panic::catch_unwind(|| {
+        Box::into_raw(Box::new(Rip8 {
+            chip8: None,
+            keys: Keys::NONE,
+            error: None,
+        }))
+    })
+    .unwrap_or(ptr::null_mut())
+}
+
+/// Frees an emulator of `rip8_create`.
+///
+/// # Safety
+/// `rip8` has to be returned by `rip8_create` and can't be used afterwards. It may be null.
+#[no_mangle]
+pub unsafe extern "C" fn rip8_destroy(rip8: *mut Rip8) {
+    if !rip8.is_null() {
+        // a panicking drop leaks the rest of the emulator
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(rip8))));
+    }
+}
+
+/// Resets the emulator and loads the `size` bytes of `rom`. The quirks and the speed are taken
+/// from the ROM database if the ROM is known.
+///
+/// # Safety
+/// `rip8` has to be a valid emulator and `rom` has to point to `size` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn rip8_load_rom(rip8: *mut Rip8, rom: *const u8, size: usize) -> c_int {
+    let rom = if size == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(rom, size)
+    };
+
+    catch_panic(rip8, RIP8_ERROR, |rip8| {
+        let database = RomDatabase::builtin();
+        let rom_info = database.lookup(rom).cloned().unwrap_or_default();
+        let mut quirks = rom_info.platform.unwrap_or_default().quirks();
+        rom_info.quirks.apply(&mut quirks);
+
+        let result = Chip8::builder()
+            .rom(rom)
+            .quirks(quirks)
+            .speed(rom_info.speed.unwrap_or(Chip8::DEFAULT_SPEED))
+            .build();
+
+        match result {
+            Ok(chip8) => {
+                rip8.chip8 = Some(chip8);
+                RIP8_OK
+            }
+            Err(err) => {
+                rip8.set_error(err.to_string());
+                RIP8_ERROR
+            }
+        }
+    })
+}
+
+/// Sets the keys which are held in the following frames, bit `n` is set if key `n` is pressed.
+///
+/// # Safety
+/// `rip8` has to be a valid emulator.
+#[no_mangle]
+pub unsafe extern "C" fn rip8_set_keys(rip8: *mut Rip8, keys: u16) {
+    catch_panic(rip8, (), |rip8| rip8.keys = Keys(keys));
+}
+
+/// Executes one frame and decrements the timers. Returns `RIP8_EXITED` if the program exited and
+/// `RIP8_ERROR` if it did something invalid. `frame` may be null.
+///
+/// # Safety
+/// `rip8` has to be a valid emulator and `frame` has to be null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn rip8_step_frame(rip8: *mut Rip8, frame: *mut Rip8Frame) -> c_int {
+    catch_panic(rip8, RIP8_ERROR, |rip8| {
+        let keys = rip8.keys;
+        let chip8 = match rip8.chip8() {
+            Some(chip8) => chip8,
+            None => return RIP8_ERROR,
+        };
+
+        match chip8.step_frame(keys) {
+            Ok(result) => {
+                if !frame.is_null() {
+                    *frame = Rip8Frame {
+                        instructions: result.instructions,
+                        sound: result.sound,
+                    };
+                }
+
+                if result.running {
+                    RIP8_OK
+                } else {
+                    RIP8_EXITED
+                }
+            }
+            Err(err) => {
+                rip8.set_error(err.to_string());
+                RIP8_ERROR
+            }
+        }
+    })
+}
+
+/// Returns the pixels of the screen row by row and stores the current resolution in `width` and
+/// `height`. Every pixel is one byte which stores one bit per XO-CHIP plane. The pointer is valid
+/// until the next call which changes the emulator, it's null if no ROM is loaded.
+///
+/// # Safety
+/// `rip8` has to be a valid emulator, `width` and `height` have to be null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn rip8_get_framebuffer(
+    rip8: *mut Rip8,
+    width: *mut usize,
+    height: *mut usize,
+) -> *const u8 {
+    catch_panic(rip8, ptr::null(), |rip8| {
+        let chip8 = match rip8.chip8() {
+            Some(chip8) => chip8,
+            None => return ptr::null(),
+        };
+
+        let (fb_width, fb_height) = chip8.resolution();
+        if !width.is_null() {
+            *width = fb_width;
+        }
+        if !height.is_null() {
+            *height = fb_height;
+        }
+
+        chip8.framebuffer().as_ptr()
+    })
+}
+
+/// Serializes the emulated machine into `buffer` if it has at least `size` bytes and returns the
+/// size of the state. Call it with a null `buffer` to get the needed size. Returns `0` on errors.
+///
+/// # Safety
+/// `rip8` has to be a valid emulator and `buffer` has to be null or point to `size` writable
+/// bytes.
+#[no_mangle]
+pub unsafe extern "C" fn rip8_save_state(rip8: *mut Rip8, buffer: *mut u8, size: usize) -> usize {
+    catch_panic(rip8, 0, |rip8| {
+        let chip8 = match rip8.chip8() {
+            Some(chip8) => chip8,
+            None => return 0,
+        };
+
+        let state = chip8.save_state();
+        if !buffer.is_null() && size >= state.len() {
+            ptr::copy_nonoverlapping(state.as_ptr(), buffer, state.len());
+        }
+
+        state.len()
+    })
+}
+
+/// Restores a state of `rip8_save_state`, the ROM has to be loaded before.
+///
+/// # Safety
+/// `rip8` has to be a valid emulator and `data` has to point to `size` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn rip8_load_state(rip8: *mut Rip8, data: *const u8, size: usize) -> c_int {
+    let data = if size == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(data, size)
+    };
+
+    catch_panic(rip8, RIP8_ERROR, |rip8| {
+        let chip8 = match rip8.chip8() {
+            Some(chip8) => chip8,
+            None => return RIP8_ERROR,
+        };
+
+        match chip8.load_state(data) {
+            Ok(()) => RIP8_OK,
+            Err(err) => {
+                rip8.set_error(err.to_string());
+                RIP8_ERROR
+            }
+        }
+    })
+}
+
+/// Returns the message of the last error or null if there wasn't one. The string is valid until
+/// the next error happens.
+///
+/// # Safety
+/// `rip8` has to be a valid emulator.
+#[no_mangle]
+pub unsafe extern "C" fn rip8_last_error(rip8: *const Rip8) -> *const c_char {
+    panic::catch_unwind(AssertUnwindSafe(|| match &(*rip8).error {
+        Some(message) => message.as_ptr(),
+        None => ptr::null(),
+    }))
+    .unwrap_or(ptr::null())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::ffi::CStr;
+    use std::path::Path;
+
+    /// Compares `include/rip8.h` with the declarations of this module. Run the test with
+    /// `RIP8_UPDATE_HEADER=1` to write them into it.
+    #[test]
+    fn header_is_up_to_date() {
+        let crate_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let config = cbindgen::Config::from_file(crate_dir.join("cbindgen.toml")).unwrap();
+        let bindings = cbindgen::Builder::new()
+            .with_config(config)
+            .with_src(crate_dir.join("src").join("ffi.rs"))
+            .generate()
+            .unwrap();
+
+        let path = crate_dir.join("include").join("rip8.h");
+        if std::env::var_os("RIP8_UPDATE_HEADER").is_some() {
+            bindings.write_to_file(&path);
+        }
+
+        let mut header = Vec::new();
+        bindings.write(&mut header);
+        assert!(
+            std::fs::read(&path).unwrap() == header,
+            "include/rip8.h is outdated, run the test with RIP8_UPDATE_HEADER=1"
+        );
+    }
+
+    unsafe fn last_error(rip8: *mut Rip8) -> String {
+        CStr::from_ptr(rip8_last_error(rip8))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn reports_errors() {
+        unsafe {
+            let rip8 = rip8_create();
+            assert!(rip8_last_error(rip8).is_null());
+            assert_eq!(rip8_step_frame(rip8, ptr::null_mut()), RIP8_ERROR);
+            assert_eq!(last_error(rip8), "no ROM is loaded");
+
+            let rom = [0x00, 0xfd];
+            assert_eq!(rip8_load_rom(rip8, rom.as_ptr(), rom.len()), RIP8_OK);
+            assert_eq!(rip8_step_frame(rip8, ptr::null_mut()), RIP8_EXITED);
+            rip8_destroy(rip8);
+        }
+    }
+
+    #[test]
+    fn catches_panics() {
+        unsafe {
+            let rip8 = rip8_create();
+            let rom = [0x00, 0xe0];
+            assert_eq!(rip8_load_rom(rip8, rom.as_ptr(), rom.len()), RIP8_OK);
+
+            let result = catch_panic(rip8, RIP8_ERROR, |_| panic!("broken"));
+            assert_eq!(result, RIP8_ERROR);
+            assert!(last_error(rip8).ends_with("broken"));
+            assert!(rip8_get_framebuffer(rip8, ptr::null_mut(), ptr::null_mut()).is_null());
+            rip8_destroy(rip8);
+        }
+    }
+}
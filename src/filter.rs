@@ -0,0 +1,176 @@
+use serde::Deserialize;
+
+use crate::chip8::Chip8;
+use crate::palette::Palette;
+
+use std::collections::VecDeque;
+
+/// Reduces the flickering of sprites which are erased and redrawn in every frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Filter {
+    /// Every frame is shown as it is.
+    #[default]
+    None,
+    /// Pixels which are turned off fade to the background like the phosphor of a CRT. The
+    /// brightness halves every `half_life` milliseconds.
+    Decay { half_life: u64 },
+    /// A pixel is shown if it was set in any of the last `frames` frames.
+    Blend { frames: usize },
+}
+
+/// Turns the framebuffer into the colors of the window, see [Filter].
+#[derive(Debug, Clone, Default)]
+pub struct DisplayFilter {
+    filter: Filter,
+    /// The previous frames for [Filter::Blend], the newest one is at the front.
+    history: VecDeque<Vec<u8>>,
+    /// The previously shown colors for [Filter::Decay].
+    colors: Vec<[f32; 3]>,
+}
+
+impl DisplayFilter {
+    pub fn new(filter: Filter) -> Self {
+        Self {
+            filter,
+            ..Self::default()
+        }
+    }
+
+    /// Returns the colors of the pixels of `fb`, which has to contain a whole frame.
+    pub fn apply(&mut self, fb: &[u8], palette: &Palette) -> Vec<u32> {
+        match self.filter {
+            Filter::None => fb
+                .iter()
+                .map(|&pixel| palette[usize::from(pixel)])
+                .collect(),
+            Filter::Decay { half_life } => self.decay(fb, palette, half_life),
+            Filter::Blend { frames } => self.blend(fb, palette, frames),
+        }
+    }
+
+    fn decay(&mut self, fb: &[u8], palette: &Palette, half_life: u64) -> Vec<u32> {
+        let frame_time = Chip8::FREQUENCY.as_secs_f32() * 1000.0;
+        let factor = 0.5f32.powf(frame_time / half_life.max(1) as f32);
+        let background = to_rgb(palette[0]);
+
+        if self.colors.len() != fb.len() {
+            self.colors = vec![background; fb.len()];
+        }
+
+        fb.iter()
+            .zip(self.colors.iter_mut())
+            .map(|(&pixel, color)| {
+                if pixel != 0 {
+                    *color = to_rgb(palette[usize::from(pixel)]);
+                } else {
+                    for (channel, background) in color.iter_mut().zip(background) {
+                        *channel = background + (*channel - background) * factor;
+                    }
+                }
+
+                from_rgb(*color)
+            })
+            .collect()
+    }
+
+    fn blend(&mut self, fb: &[u8], palette: &Palette, frames: usize) -> Vec<u32> {
+        if self.history.front().map(Vec::len) != Some(fb.len()) {
+            self.history.clear();
+        }
+
+        self.history.push_front(fb.to_vec());
+        self.history.truncate(frames.max(1));
+
+        (0..fb.len())
+            .map(|index| {
+                let pixel = self
+                    .history
+                    .iter()
+                    .fold(0, |pixel, frame| pixel | frame[index]);
+                palette[usize::from(pixel)]
+            })
+            .collect()
+    }
+}
+
+fn to_rgb(color: u32) -> [f32; 3] {
+    let [blue, green, red, _] = color.to_le_bytes();
+    [red, green, blue].map(f32::from)
+}
+
+fn from_rgb([red, green, blue]: [f32; 3]) -> u32 {
+    u32::from_le_bytes([blue as u8, green as u8, red as u8, 0])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PALETTE: Palette = [0x202020, 0xffffff, 0xff0000, 0x0000ff];
+
+    #[test]
+    fn shows_frames_unchanged() {
+        let mut filter = DisplayFilter::new(Filter::None);
+
+        assert_eq!(
+            filter.apply(&[0, 1, 2, 3], &PALETTE),
+            [0x202020, 0xffffff, 0xff0000, 0x0000ff]
+        );
+    }
+
+    #[test]
+    fn decays_to_the_background() {
+        // a frame takes 16 milliseconds
+        let mut filter = DisplayFilter::new(Filter::Decay { half_life: 16 });
+
+        assert_eq!(filter.apply(&[1, 1], &PALETTE), [0xffffff, 0xffffff]);
+        // halfway between 0x20 and 0xff is 0x8f
+        assert_eq!(filter.apply(&[0, 0], &PALETTE), [0x8f8f8f, 0x8f8f8f]);
+        assert_eq!(filter.apply(&[0, 0], &PALETTE), [0x575757, 0x575757]);
+        // set pixels are shown at full brightness again
+        assert_eq!(filter.apply(&[1, 0], &PALETTE), [0xffffff, 0x3b3b3b]);
+
+        // a longer half life takes more frames
+        let mut filter = DisplayFilter::new(Filter::Decay { half_life: 48 });
+        filter.apply(&[1], &PALETTE);
+        for _ in 0..2 {
+            assert!(filter.apply(&[0], &PALETTE)[0] > 0x8f8f8f);
+        }
+        assert_eq!(filter.apply(&[0], &PALETTE), [0x8f8f8f]);
+    }
+
+    #[test]
+    fn blends_the_last_frames() {
+        let mut filter = DisplayFilter::new(Filter::Blend { frames: 2 });
+
+        assert_eq!(
+            filter.apply(&[1, 0, 0], &PALETTE),
+            [0xffffff, 0x202020, 0x202020]
+        );
+        assert_eq!(
+            filter.apply(&[0, 2, 0], &PALETTE),
+            [0xffffff, 0xff0000, 0x202020]
+        );
+        // the first frame is older than 2 frames now, the planes are ORed
+        assert_eq!(
+            filter.apply(&[0, 1, 0], &PALETTE),
+            [0x202020, 0x0000ff, 0x202020]
+        );
+        assert_eq!(
+            filter.apply(&[0, 0, 0], &PALETTE),
+            [0x202020, 0xffffff, 0x202020]
+        );
+    }
+
+    #[test]
+    fn resets_the_history_on_resolution_changes() {
+        let mut filter = DisplayFilter::new(Filter::Blend { frames: 4 });
+        filter.apply(&[1, 1], &PALETTE);
+        assert_eq!(filter.apply(&[0, 0, 0], &PALETTE), [0x202020; 3]);
+
+        let mut filter = DisplayFilter::new(Filter::Decay { half_life: 16 });
+        filter.apply(&[1, 1], &PALETTE);
+        assert_eq!(filter.apply(&[0, 0, 0], &PALETTE), [0x202020; 3]);
+    }
+}
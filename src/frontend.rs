@@ -0,0 +1,298 @@
+use log::{info, warn};
+
+use crate::chip8::{Chip8, Keys};
+use crate::display::Display;
+use crate::keymap::Keymap;
+use crate::{Error, Result};
+
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TryRecvError, TrySendError};
+use std::thread;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// The amount of frames which can wait for the display before new ones are dropped.
+const FRAME_QUEUE_SIZE: usize = 2;
+
+/// Sent from the display to the emulation thread.
+#[derive(Debug)]
+enum Command {
+    /// The keys which are held from now on.
+    Keys(Keys),
+    Screenshot,
+    ToggleRecording,
+}
+
+/// A finished frame in the colors of the display.
+#[derive(Debug)]
+struct Frame {
+    pixels: Vec<u32>,
+    width: usize,
+    height: usize,
+}
+
+/// Shows the frames of an emulator on a display and reads the keys from it. The emulator runs on
+/// its own thread in real time, so a slow display doesn't slow down the emulation.
+#[derive(Debug)]
+pub struct Frontend {
+    display: Box<dyn Display>,
+    keymap: Keymap,
+}
+
+impl Frontend {
+    pub fn new(display: Box<dyn Display>, keymap: Keymap) -> Self {
+        Self { display, keymap }
+    }
+
+    /// Runs `chip8` until it stops, the display gets closed or it finished `last_frame` frames.
+    /// Returns the emulator afterwards.
+    pub fn run(&mut self, chip8: Chip8, last_frame: Option<u64>) -> Result<Chip8> {
+        // the background color also fills the borders of a resized window
+        self.display.set_background_color(chip8.palette()[0]);
+
+        let (command_sender, commands) = mpsc::channel();
+        let (frame_sender, frames) = mpsc::sync_channel(FRAME_QUEUE_SIZE);
+        let emulation = thread::Builder::new()
+            .name("emulation".to_string())
+            .spawn(move || emulate(chip8, commands, frame_sender, last_frame))
+            .map_err(Error::Thread)?;
+
+        let shown = self.show(&frames, &command_sender);
+
+        // closing the command channel stops the emulation thread
+        drop(command_sender);
+        drop(frames);
+        let chip8 = match emulation.join() {
+            Ok(result) => result?,
+            Err(panic) => std::panic::resume_unwind(panic),
+        };
+
+        shown.map(|()| chip8)
+    }
+
+    /// Shows the frames and sends the input until the emulation or the display stops.
+    fn show(&mut self, frames: &Receiver<Frame>, commands: &mpsc::Sender<Command>) -> Result<()> {
+        let mut keys = Keys::NONE;
+
+        loop {
+            match frames.recv_timeout(Chip8::FREQUENCY) {
+                Ok(mut frame) => {
+                    // skip the frames which the display couldn't keep up with
+                    while let Ok(newer) = frames.try_recv() {
+                        frame = newer;
+                    }
+
+                    self.display
+                        .update_with_buffer(&frame.pixels, frame.width, frame.height)?;
+                }
+                Err(RecvTimeoutError::Timeout) => self.display.update(),
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+
+            let hotkeys = self.keymap.hotkeys;
+            if !self.display.is_open() || self.display.is_key_pressed(hotkeys.quit) {
+                return Ok(());
+            }
+
+            let mut pending = Vec::new();
+            if self.display.is_key_pressed(hotkeys.screenshot) {
+                pending.push(Command::Screenshot);
+            }
+            if self.display.is_key_pressed(hotkeys.record) {
+                pending.push(Command::ToggleRecording);
+            }
+
+            let held = self.held_keys();
+            if held != keys {
+                keys = held;
+                pending.push(Command::Keys(keys));
+            }
+
+            for command in pending {
+                // the emulation stopped if the channel is closed, which is noticed above
+                let _ = commands.send(command);
+            }
+        }
+    }
+
+    fn held_keys(&self) -> Keys {
+        let mut keys = Keys::NONE;
+        for key in self.display.get_keys() {
+            if let Some(value) = self.keymap.value(key) {
+                keys.set(value, true);
+            }
+        }
+
+        keys
+    }
+}
+
+/// Executes frames in real time until the emulator stops, the display disconnects or
+/// `last_frame` is reached.
+fn emulate(
+    mut chip8: Chip8,
+    commands: Receiver<Command>,
+    frames: SyncSender<Frame>,
+    last_frame: Option<u64>,
+) -> Result<Chip8> {
+    let mut keys = Keys::NONE;
+    let mut tick = Instant::now();
+
+    while chip8.is_running() && last_frame.is_none_or(|last_frame| chip8.frames() < last_frame) {
+        loop {
+            match commands.try_recv() {
+                Ok(Command::Keys(held)) => keys = held,
+                Ok(Command::Screenshot) => save_screenshot(&chip8),
+                Ok(Command::ToggleRecording) => toggle_recording(&mut chip8),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return Ok(chip8),
+            }
+        }
+
+        chip8.step_frame(keys)?;
+
+        let (width, height) = chip8.resolution();
+        let frame = Frame {
+            pixels: chip8.pixels().to_vec(),
+            width,
+            height,
+        };
+        match frames.try_send(frame) {
+            // the display is behind, it gets one of the next frames
+            Ok(()) | Err(TrySendError::Full(_)) => {}
+            Err(TrySendError::Disconnected(_)) => return Ok(chip8),
+        }
+
+        thread::sleep(Chip8::FREQUENCY.saturating_sub(tick.elapsed()));
+        tick = Instant::now();
+    }
+
+    Ok(chip8)
+}
+
+fn save_screenshot(chip8: &Chip8) {
+    let path = hotkey_file_name("png");
+
+    match chip8.save_screenshot(&path) {
+        Ok(()) => info!("Saved screenshot '{}'", path),
+        Err(err) => warn!("{}", err),
+    }
+}
+
+fn toggle_recording(chip8: &mut Chip8) {
+    let result = if chip8.is_recording() {
+        chip8.stop_recording()
+    } else {
+        chip8.start_recording(hotkey_file_name("gif"))
+    };
+
+    if let Err(err) = result {
+        warn!("{}", err);
+    }
+}
+
+/// The name of a file which is created by a hotkey.
+fn hotkey_file_name(extension: &str) -> String {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+
+    format!("rip8-{}.{}", millis, extension)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quirks::Platform;
+
+    use minifb::Key;
+
+    /// A display which closes after showing `close_after` frames and holds `held` all the time.
+    #[derive(Debug)]
+    struct FakeDisplay {
+        shown: usize,
+        close_after: usize,
+        held: Vec<Key>,
+    }
+
+    impl Display for FakeDisplay {
+        fn update_with_buffer(
+            &mut self,
+            buffer: &[u32],
+            width: usize,
+            height: usize,
+        ) -> Result<()> {
+            assert_eq!(buffer.len(), width * height);
+            self.shown += 1;
+            Ok(())
+        }
+
+        fn update(&mut self) {}
+
+        fn is_open(&self) -> bool {
+            self.shown < self.close_after
+        }
+
+        fn get_keys(&self) -> Vec<Key> {
+            self.held.clone()
+        }
+
+        fn get_keys_pressed(&self) -> Vec<Key> {
+            Vec::new()
+        }
+    }
+
+    fn frontend(close_after: usize, held: Vec<Key>) -> Frontend {
+        let mut keymap = Keymap::default();
+        keymap.rebind(5, &[Key::Space]);
+        let display = FakeDisplay {
+            shown: 0,
+            close_after,
+            held,
+        };
+
+        Frontend::new(Box::new(display), keymap)
+    }
+
+    fn chip8(rom: &[u8]) -> Chip8 {
+        Chip8::builder()
+            .rom(rom)
+            .platform(Platform::Schip)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn returns_the_emulator_when_the_display_closes() {
+        let chip8 = frontend(3, Vec::new())
+            .run(chip8(&[0x12, 0x00]), None)
+            .unwrap();
+
+        assert!(chip8.is_running());
+        assert!(chip8.frames() >= 3);
+    }
+
+    #[test]
+    fn stops_after_the_last_frame() {
+        let chip8 = frontend(usize::MAX, Vec::new())
+            .run(chip8(&[0x12, 0x00]), Some(5))
+            .unwrap();
+
+        assert!(chip8.is_running());
+        assert_eq!(chip8.frames(), 5);
+    }
+
+    #[test]
+    fn sends_the_held_keys() {
+        #[rustfmt::skip]
+        let rom = [
+            0xf0, 0x0a, // LD V0, K
+            0x00, 0xfd, // EXIT
+        ];
+        let chip8 = frontend(usize::MAX, vec![Key::Space])
+            .run(chip8(&rom), None)
+            .unwrap();
+
+        assert!(!chip8.is_running());
+        assert_eq!(chip8.registers().v[0], 5);
+    }
+}
@@ -0,0 +1,481 @@
+use fnv::FnvHashMap;
+
+use crate::{Error, Result};
+
+use std::collections::hash_map::Entry;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// The amount of frames which are shown per second.
+const FRAME_RATE: u64 = 60;
+/// GIF delays are given in hundredths of a second.
+const DELAY_UNITS_PER_SECOND: u64 = 100;
+const MAX_COLORS: usize = 256;
+const MAX_CODE_SIZE: u32 = 12;
+
+/// Encodes frames into an animated GIF which loops forever.
+///
+/// Every frame is shown for 1/60 second. A frame which equals the previous one only extends the
+/// delay of the previous one.
+#[derive(Debug)]
+pub struct GifRecorder {
+    writer: BufWriter<File>,
+    path: PathBuf,
+    width: usize,
+    height: usize,
+    /// The latest frame, it's written as soon as its delay is known.
+    pending: Option<Vec<u32>>,
+    /// The amount of frames which the pending frame is shown.
+    pending_frames: u64,
+    /// The amount of frames which got written.
+    written_frames: u64,
+    finished: bool,
+}
+
+impl GifRecorder {
+    /// Creates the GIF at `path`. Every frame is resized to `width` x `height`.
+    pub fn create<P: AsRef<Path>>(path: P, width: usize, height: usize) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::create(path).map_err(|source| Error::Write {
+            path: path.into(),
+            source,
+        })?;
+
+        let mut recorder = Self {
+            writer: BufWriter::new(file),
+            path: path.into(),
+            width,
+            height,
+            pending: None,
+            pending_frames: 0,
+            written_frames: 0,
+            finished: false,
+        };
+        recorder
+            .write_header()
+            .map_err(|source| recorder.error(source))?;
+
+        Ok(recorder)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Adds a frame with the given size, its pixels are stored as `0x00rrggbb`.
+    pub fn push_frame(&mut self, pixels: &[u32], width: usize, height: usize) -> Result<()> {
+        let frame = resize(pixels, width, height, self.width, self.height);
+
+        if self.pending.as_ref() == Some(&frame) {
+            self.pending_frames += 1;
+            return Ok(());
+        }
+
+        self.write_pending().map_err(|source| self.error(source))?;
+        self.pending = Some(frame);
+        self.pending_frames = 1;
+
+        Ok(())
+    }
+
+    /// Writes the remaining frame and the end of the file.
+    pub fn finish(mut self) -> Result<()> {
+        self.finished = true;
+        self.write_end().map_err(|source| self.error(source))
+    }
+
+    fn error(&self, source: io::Error) -> Error {
+        Error::Write {
+            path: self.path.clone(),
+            source,
+        }
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        let writer = &mut self.writer;
+
+        writer.write_all(b"GIF89a")?;
+        writer.write_all(&(self.width as u16).to_le_bytes())?;
+        writer.write_all(&(self.height as u16).to_le_bytes())?;
+        // no global color table, every frame has its own one
+        writer.write_all(&[0, 0, 0])?;
+
+        // loop forever
+        writer.write_all(&[0x21, 0xff, 11])?;
+        writer.write_all(b"NETSCAPE2.0")?;
+        writer.write_all(&[3, 1, 0, 0, 0])
+    }
+
+    fn write_pending(&mut self) -> io::Result<()> {
+        let frame = match self.pending.take() {
+            Some(frame) => frame,
+            None => return Ok(()),
+        };
+
+        // the delays are rounded in a way that the sum of them doesn't drift away
+        let start = self.written_frames * DELAY_UNITS_PER_SECOND / FRAME_RATE;
+        self.written_frames += self.pending_frames;
+        let end = self.written_frames * DELAY_UNITS_PER_SECOND / FRAME_RATE;
+        let delay = (end - start).clamp(1, u64::from(u16::MAX)) as u16;
+
+        let (colors, indices) = index_colors(&frame);
+        let table_bits = colors.len().next_power_of_two().trailing_zeros().max(1);
+
+        let writer = &mut self.writer;
+
+        // graphic control extension with the delay
+        writer.write_all(&[0x21, 0xf9, 4, 0])?;
+        writer.write_all(&delay.to_le_bytes())?;
+        writer.write_all(&[0, 0])?;
+
+        // image descriptor with a local color table
+        writer.write_all(&[0x2c, 0, 0, 0, 0])?;
+        writer.write_all(&(self.width as u16).to_le_bytes())?;
+        writer.write_all(&(self.height as u16).to_le_bytes())?;
+        writer.write_all(&[0x80 | (table_bits as u8 - 1)])?;
+
+        for index in 0..1 << table_bits {
+            let color = colors.get(index).copied().unwrap_or_default();
+            let [blue, green, red, _] = color.to_le_bytes();
+            writer.write_all(&[red, green, blue])?;
+        }
+
+        let min_code_size = table_bits.max(2);
+        writer.write_all(&[min_code_size as u8])?;
+        for block in lzw_encode(&indices, min_code_size).chunks(255) {
+            writer.write_all(&[block.len() as u8])?;
+            writer.write_all(block)?;
+        }
+        writer.write_all(&[0])
+    }
+
+    fn write_end(&mut self) -> io::Result<()> {
+        self.write_pending()?;
+        self.writer.write_all(&[0x3b])?;
+        self.writer.flush()
+    }
+}
+
+impl Drop for GifRecorder {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = self.write_end();
+        }
+    }
+}
+
+/// Resizes the frame with nearest neighbour sampling.
+fn resize(
+    pixels: &[u32],
+    width: usize,
+    height: usize,
+    new_width: usize,
+    new_height: usize,
+) -> Vec<u32> {
+    (0..new_width * new_height)
+        .map(|index| {
+            let x = index % new_width * width / new_width;
+            let y = index / new_width * height / new_height;
+            pixels[y * width + x]
+        })
+        .collect()
+}
+
+/// Returns the color table and the indices of the pixels into it. Frames with too many colors are
+/// reduced to 3 bits of red and green and 2 bits of blue.
+fn index_colors(pixels: &[u32]) -> (Vec<u32>, Vec<u8>) {
+    let mut colors = Vec::new();
+    let mut indices = FnvHashMap::default();
+
+    for &pixel in pixels {
+        let pixel = pixel & 0xffffff;
+        if let Entry::Vacant(entry) = indices.entry(pixel) {
+            if colors.len() == MAX_COLORS {
+                return index_reduced_colors(pixels);
+            }
+
+            entry.insert(colors.len() as u8);
+            colors.push(pixel);
+        }
+    }
+
+    let indices = pixels
+        .iter()
+        .map(|pixel| indices[&(pixel & 0xffffff)])
+        .collect();
+
+    (colors, indices)
+}
+
+fn index_reduced_colors(pixels: &[u32]) -> (Vec<u32>, Vec<u8>) {
+    let colors = (0..MAX_COLORS as u32)
+        .map(|index| {
+            let red = (index >> 5) * 0xff / 7;
+            let green = ((index >> 2) & 0x7) * 0xff / 7;
+            let blue = (index & 0x3) * 0xff / 3;
+            (red << 16) | (green << 8) | blue
+        })
+        .collect();
+
+    let indices = pixels
+        .iter()
+        .map(|pixel| {
+            let [blue, green, red, _] = pixel.to_le_bytes();
+            (red & 0xe0) | ((green & 0xe0) >> 3) | (blue >> 6)
+        })
+        .collect();
+
+    (colors, indices)
+}
+
+/// Compresses the indices with the variable code size LZW variant of GIF.
+fn lzw_encode(indices: &[u8], min_code_size: u32) -> Vec<u8> {
+    let clear_code = 1u32 << min_code_size;
+    let end_code = clear_code + 1;
+
+    let mut output = BitWriter::default();
+    let mut dictionary: FnvHashMap<(u32, u8), u32> = FnvHashMap::default();
+    let mut next_code = end_code + 1;
+    let mut code_size = min_code_size + 1;
+
+    output.write(clear_code, code_size);
+
+    let mut indices = indices.iter();
+    let mut prefix = match indices.next() {
+        Some(&index) => u32::from(index),
+        None => {
+            output.write(end_code, code_size);
+            return output.finish();
+        }
+    };
+
+    for &index in indices {
+        if let Some(&code) = dictionary.get(&(prefix, index)) {
+            prefix = code;
+            continue;
+        }
+
+        output.write(prefix, code_size);
+
+        if next_code < 1 << MAX_CODE_SIZE {
+            dictionary.insert((prefix, index), next_code);
+            // the decoder increases the code size once it has seen the code of the next entry
+            if next_code == 1 << code_size {
+                code_size += 1;
+            }
+            next_code += 1;
+        } else {
+            output.write(clear_code, code_size);
+            dictionary.clear();
+            next_code = end_code + 1;
+            code_size = min_code_size + 1;
+        }
+
+        prefix = u32::from(index);
+    }
+
+    output.write(prefix, code_size);
+    output.write(end_code, code_size);
+    output.finish()
+}
+
+/// Packs codes least significant bit first.
+#[derive(Debug, Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u32,
+    amount_bits: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u32, size: u32) {
+        self.buffer |= code << self.amount_bits;
+        self.amount_bits += size;
+
+        while self.amount_bits >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.amount_bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.amount_bits > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decodes the codes of [lzw_encode] like a GIF decoder.
+    fn lzw_decode(data: &[u8], min_code_size: u32) -> Vec<u8> {
+        let clear_code = 1usize << min_code_size;
+        let end_code = clear_code + 1;
+        let initial_table =
+            || -> Vec<Vec<u8>> { (0..end_code + 1).map(|code| vec![code as u8]).collect() };
+
+        let mut table = initial_table();
+        let mut code_size = min_code_size + 1;
+        let mut previous: Option<Vec<u8>> = None;
+        let mut output = Vec::new();
+        let mut bit = 0;
+
+        loop {
+            let code = (0..code_size as usize)
+                .map(|offset| {
+                    let bit = bit + offset;
+                    usize::from(data[bit / 8] >> (bit % 8) & 1) << offset
+                })
+                .sum::<usize>();
+            bit += code_size as usize;
+
+            if code == clear_code {
+                table = initial_table();
+                code_size = min_code_size + 1;
+                previous = None;
+                continue;
+            }
+            if code == end_code {
+                return output;
+            }
+
+            let entry = match (table.get(code), &previous) {
+                (Some(entry), _) => entry.clone(),
+                (None, Some(previous)) if code == table.len() => {
+                    [&previous[..], &previous[..1]].concat()
+                }
+                _ => panic!("invalid code {}", code),
+            };
+            output.extend_from_slice(&entry);
+
+            if let Some(previous) = previous {
+                if table.len() < 1 << MAX_CODE_SIZE {
+                    table.push([&previous[..], &entry[..1]].concat());
+                }
+            }
+            if table.len() == 1 << code_size && code_size < MAX_CODE_SIZE {
+                code_size += 1;
+            }
+            previous = Some(entry);
+        }
+    }
+
+    /// Returns the delay and the pixels of every frame of a GIF of [GifRecorder].
+    fn read_gif(data: &[u8]) -> Vec<(u16, Vec<u32>)> {
+        let word = |pos: usize| u16::from_le_bytes([data[pos], data[pos + 1]]);
+        let sub_blocks = |mut pos: usize| {
+            let mut bytes = Vec::new();
+            while data[pos] != 0 {
+                bytes.extend_from_slice(&data[pos + 1..][..usize::from(data[pos])]);
+                pos += usize::from(data[pos]) + 1;
+            }
+            (bytes, pos + 1)
+        };
+
+        assert_eq!(&data[..6], b"GIF89a");
+        let mut frames = Vec::new();
+        let mut delay = 0;
+        let mut pos = 13;
+
+        loop {
+            match data[pos] {
+                0x21 => {
+                    if data[pos + 1] == 0xf9 {
+                        delay = word(pos + 4);
+                    }
+                    pos = sub_blocks(pos + 2).1;
+                }
+                0x2c => {
+                    let size = usize::from(word(pos + 5)) * usize::from(word(pos + 7));
+                    let table_size = 2 << (data[pos + 9] & 0x7);
+                    let colors: Vec<u32> = data[pos + 10..][..3 * table_size]
+                        .chunks(3)
+                        .map(|rgb| u32::from_be_bytes([0, rgb[0], rgb[1], rgb[2]]))
+                        .collect();
+                    pos += 10 + 3 * table_size;
+
+                    let min_code_size = u32::from(data[pos]);
+                    let (bytes, next) = sub_blocks(pos + 1);
+                    let indices = lzw_decode(&bytes, min_code_size);
+                    assert_eq!(indices.len(), size);
+
+                    let pixels = indices.iter().map(|&index| colors[usize::from(index)]);
+                    frames.push((delay, pixels.collect()));
+                    pos = next;
+                }
+                0x3b => return frames,
+                byte => panic!("unexpected block {:#x}", byte),
+            }
+        }
+    }
+
+    /// Records the 4x2 frames and returns the frames of the GIF.
+    fn record(name: &str, frames: &[[u32; 8]]) -> Vec<(u16, Vec<u32>)> {
+        let path = std::env::temp_dir().join(format!("rip8-{}-{}.gif", name, std::process::id()));
+        let mut recorder = GifRecorder::create(&path, 4, 2).unwrap();
+        for frame in frames {
+            recorder.push_frame(frame, 4, 2).unwrap();
+        }
+        recorder.finish().unwrap();
+
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        read_gif(&data)
+    }
+
+    #[test]
+    fn lzw_round_trip() {
+        // a linear congruential generator, the noise fills the dictionary many times
+        let mut seed = 1u32;
+        let noise: Vec<u8> = (0..100_000)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (seed >> 16) as u8
+            })
+            .collect();
+        assert_eq!(lzw_decode(&lzw_encode(&noise, 8), 8), noise);
+
+        let two_bits: Vec<u8> = noise.iter().map(|byte| byte & 0x3).collect();
+        assert_eq!(lzw_decode(&lzw_encode(&two_bits, 2), 2), two_bits);
+
+        let runs: Vec<u8> = (0..50_000).map(|index| (index / 300 % 2) as u8).collect();
+        assert_eq!(lzw_decode(&lzw_encode(&runs, 2), 2), runs);
+
+        assert!(lzw_decode(&lzw_encode(&[], 2), 2).is_empty());
+        assert_eq!(lzw_decode(&lzw_encode(&[3], 2), 2), [3]);
+    }
+
+    #[test]
+    fn merges_duplicate_frames() {
+        let black = [0; 8];
+        let mut white = [0xffffff; 8];
+        white[5] = 0x102030;
+
+        let frames = record("merge", &[black, black, white, white, white, black]);
+        assert_eq!(
+            frames,
+            [
+                (3, black.to_vec()),
+                (5, white.to_vec()),
+                (2, black.to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn delays_add_up_to_the_frame_rate() {
+        let frames: Vec<[u32; 8]> = (0..120).map(|index| [index % 2; 8]).collect();
+        let frames = record("delays", &frames);
+
+        assert_eq!(frames.len(), 120);
+        assert!(frames.iter().all(|&(delay, _)| delay == 1 || delay == 2));
+        let delays: u64 = frames.iter().map(|&(delay, _)| u64::from(delay)).sum();
+        assert_eq!(delays, 120 * DELAY_UNITS_PER_SECOND / FRAME_RATE);
+    }
+}
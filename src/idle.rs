@@ -0,0 +1,241 @@
+//! Detects loops which only wait for the delay timer or a key, like `LD V0, DT; SE V0, 0; JP`
+//! back to the start. Such a loop can't leave before the timers or the keys change, which only
+//! happens at the next frame, so the emulator ends the frame instead of executing it over and
+//! over.
+//!
+//! Blocks are side effect free if they only change the registers, `I` and pc, depending on
+//! nothing but those, the memory, the flags, the delay timer and the keys. A loop head is a side
+//! effect free block which is reached again through such blocks. When the execution gets back to
+//! a loop head in the same frame with the same registers and `I`, it would repeat the same blocks
+//! until the frame ends.
+
+use crate::cfg;
+use crate::chip8::{self, Chip8, Chip8State};
+use crate::instruction::Instruction;
+use crate::quirks::Quirks;
+use crate::Addr;
+
+use std::collections::BTreeSet;
+
+/// The amount of blocks a loop may consist of.
+const MAX_LOOP_BLOCKS: usize = 8;
+
+/// What's known about a compiled block at compile time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IdleInfo {
+    pub side_effect_free: bool,
+    /// The block starts a loop of side effect free blocks.
+    pub loop_head: bool,
+}
+
+impl IdleInfo {
+    /// Analyzes the compiled block which starts at `start`.
+    pub fn analyze(mem: &[u8], start: Addr, quirks: &Quirks) -> Self {
+        let side_effect_free = successors(mem, start, quirks).is_some();
+
+        Self {
+            side_effect_free,
+            loop_head: side_effect_free && is_loop_head(mem, start, quirks),
+        }
+    }
+}
+
+/// Remembers the loop heads which were reached since the last block with side effects in the
+/// current frame.
+#[derive(Debug, Default)]
+pub struct IdleDetector {
+    snapshots: Vec<Snapshot>,
+}
+
+impl IdleDetector {
+    /// Called before executing a loop head, returns `true` if the execution got back to it
+    /// without changing anything.
+    pub fn visit(&mut self, state: &Chip8State) -> bool {
+        let snapshot = Snapshot::new(state);
+        if self.snapshots.contains(&snapshot) {
+            return true;
+        }
+
+        // loops which count up in a register never repeat a snapshot
+        if self.snapshots.len() == MAX_LOOP_BLOCKS {
+            self.snapshots.clear();
+        }
+        self.snapshots.push(snapshot);
+
+        false
+    }
+
+    /// Forgets the snapshots, e.g. because a block had side effects or the frame ended.
+    pub fn reset(&mut self) {
+        self.snapshots.clear();
+    }
+}
+
+/// The registers and `I` at a loop head, everything else stays the same in a side effect free
+/// loop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Snapshot {
+    pc: Addr,
+    regs: [u64; Chip8::AMOUNT_REGISTERS],
+    i: u64,
+}
+
+impl Snapshot {
+    fn new(state: &Chip8State) -> Self {
+        Self {
+            pc: state.pc,
+            regs: state.regs,
+            i: state.i,
+        }
+    }
+}
+
+/// Returns `true` if `instruction` doesn't change anything but the registers, `I` and pc and
+/// always has the same result within a frame.
+fn is_side_effect_free(instruction: Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::Jp(_)
+            | Instruction::SeByte(..)
+            | Instruction::SneByte(..)
+            | Instruction::SeReg(..)
+            | Instruction::SneReg(..)
+            | Instruction::LoadRange(..)
+            | Instruction::LdByte(..)
+            | Instruction::AddByte(..)
+            | Instruction::LdReg(..)
+            | Instruction::Or(..)
+            | Instruction::And(..)
+            | Instruction::Xor(..)
+            | Instruction::AddReg(..)
+            | Instruction::Sub(..)
+            | Instruction::Shr(..)
+            | Instruction::Subn(..)
+            | Instruction::Shl(..)
+            | Instruction::LdI(_)
+            | Instruction::Skp(_)
+            | Instruction::Sknp(_)
+            | Instruction::LdXDt(_)
+            | Instruction::AddI(_)
+            | Instruction::LdF(_)
+            | Instruction::LdHf(_)
+            | Instruction::LdXI(_)
+            | Instruction::LdXR(_)
+    )
+}
+
+/// Returns the blocks which may run after the compiled block at `start`, `None` if it isn't side
+/// effect free.
+fn successors(mem: &[u8], start: Addr, quirks: &Quirks) -> Option<Vec<Addr>> {
+    let mut addr = start;
+    loop {
+        let instruction = Instruction::decode(mem, addr)?;
+        if !is_side_effect_free(instruction) {
+            return None;
+        }
+
+        let next = addr + chip8::instruction_size(mem, addr);
+        if instruction.ends_block(quirks) {
+            let skipped = next + chip8::instruction_size(mem, next);
+            return Some(cfg::terminator(instruction, addr, next, skipped).successors());
+        }
+        addr = next;
+    }
+}
+
+/// Returns `true` if the side effect free blocks after `start` lead back to it.
+fn is_loop_head(mem: &[u8], start: Addr, quirks: &Quirks) -> bool {
+    let mut visited = BTreeSet::new();
+    let mut pending = vec![start];
+
+    while let Some(addr) = pending.pop() {
+        if !visited.insert(addr) {
+            continue;
+        }
+        if visited.len() > MAX_LOOP_BLOCKS {
+            return false;
+        }
+
+        // the loop leaves through blocks with side effects
+        if let Some(successors) = successors(mem, addr, quirks) {
+            if successors.contains(&start) {
+                return true;
+            }
+            pending.extend(successors);
+        }
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn analyze(rom: &[u8], start: Addr, quirks: &Quirks) -> IdleInfo {
+        let mut mem = vec![0; Chip8::MEM_SIZE];
+        mem[Chip8::START_ADDRESS as usize..][..rom.len()].copy_from_slice(rom);
+        IdleInfo::analyze(&mem, start, quirks)
+    }
+
+    const IDLE: IdleInfo = IdleInfo {
+        side_effect_free: true,
+        loop_head: true,
+    };
+
+    #[test]
+    fn finds_loops_which_wait_for_the_delay_timer() {
+        #[rustfmt::skip]
+        let rom = [
+            0xf0, 0x07, // LD V0, DT
+            0x30, 0x00, // SE V0, 0
+            0x12, 0x00, // JP 0x200
+            0x00, 0xfd, // EXIT
+        ];
+        assert_eq!(analyze(&rom, 0x200, &Quirks::VIP), IDLE);
+        assert_eq!(analyze(&rom, 0x204, &Quirks::VIP), IDLE);
+        assert_eq!(analyze(&rom, 0x206, &Quirks::VIP), IdleInfo::default());
+    }
+
+    #[test]
+    fn finds_loops_which_wait_for_a_key() {
+        #[rustfmt::skip]
+        let rom = [
+            0x60, 0x05, // LD V0, 5
+            0xe0, 0xa1, // SKNP V0
+            0x12, 0x08, // JP 0x208
+            0x12, 0x02, // JP 0x202
+            0x00, 0xe0, // CLS
+        ];
+        assert_eq!(
+            analyze(&rom, 0x200, &Quirks::VIP),
+            IdleInfo {
+                side_effect_free: true,
+                loop_head: false,
+            }
+        );
+        assert_eq!(analyze(&rom, 0x206, &Quirks::VIP), IDLE);
+    }
+
+    #[test]
+    fn ignores_loops_with_side_effects() {
+        #[rustfmt::skip]
+        let rom = [
+            0xf0, 0x07, // LD V0, DT
+            0xf0, 0x18, // LD ST, V0
+            0x12, 0x00, // JP 0x200
+        ];
+        assert_eq!(analyze(&rom, 0x200, &Quirks::VIP), IdleInfo::default());
+
+        // the loop continues in a block with a side effect
+        #[rustfmt::skip]
+        let rom = [
+            0x30, 0x00, // SE V0, 0
+            0x12, 0x00, // JP 0x200
+            0xd0, 0x11, // DRW V0, V1, 1
+            0x12, 0x00, // JP 0x200
+        ];
+        assert!(analyze(&rom, 0x200, &Quirks::VIP).loop_head);
+        assert_eq!(analyze(&rom, 0x204, &Quirks::VIP), IdleInfo::default());
+    }
+}
@@ -0,0 +1,285 @@
+use crate::{Error, Result};
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// The biggest amount of bytes which fit into a stored deflate block.
+const MAX_STORED_BLOCK_SIZE: usize = 0xffff;
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+/// An RGB image, every pixel is stored as `0x00rrggbb` like in the window buffer.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u32>,
+}
+
+impl Image {
+    /// Creates an image of `pixels` where every pixel becomes a `scale` x `scale` square.
+    pub fn scaled(pixels: &[u32], width: usize, height: usize, scale: usize) -> Self {
+        let scale = scale.max(1);
+        let mut scaled = Vec::with_capacity(width * height * scale * scale);
+
+        for row in pixels.chunks(width).take(height) {
+            let scaled_row: Vec<u32> = row
+                .iter()
+                .flat_map(|&pixel| std::iter::repeat_n(pixel, scale))
+                .collect();
+
+            for _ in 0..scale {
+                scaled.extend_from_slice(&scaled_row);
+            }
+        }
+
+        Self {
+            width: width * scale,
+            height: height * scale,
+            pixels: scaled,
+        }
+    }
+
+    /// Writes the image as PPM if the extension of `path` is `ppm`, otherwise as PNG.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let is_ppm = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("ppm"));
+
+        let to_error = |source| Error::Write {
+            path: path.into(),
+            source,
+        };
+        let mut writer = BufWriter::new(File::create(path).map_err(to_error)?);
+
+        if is_ppm {
+            self.write_ppm(&mut writer).map_err(to_error)?;
+        } else {
+            self.write_png(&mut writer).map_err(to_error)?;
+        }
+
+        writer.flush().map_err(to_error)
+    }
+
+    /// Writes the image as binary PPM (`P6`).
+    pub fn write_ppm<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write!(writer, "P6\n{} {}\n255\n", self.width, self.height)?;
+        writer.write_all(&self.rgb())
+    }
+
+    /// Writes the image as 8-bit RGB PNG. The image data isn't compressed.
+    pub fn write_png<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&PNG_SIGNATURE)?;
+
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&(self.width as u32).to_be_bytes());
+        header.extend_from_slice(&(self.height as u32).to_be_bytes());
+        // bit depth 8, color type RGB, default compression, filter and no interlacing
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+        write_chunk(writer, b"IHDR", &header)?;
+
+        // every scanline starts with its filter type, which is always "none"
+        let rgb = self.rgb();
+        let mut scanlines = Vec::with_capacity(rgb.len() + self.height);
+        for row in rgb.chunks(self.width * 3) {
+            scanlines.push(0);
+            scanlines.extend_from_slice(row);
+        }
+        write_chunk(writer, b"IDAT", &zlib_stored(&scanlines))?;
+
+        write_chunk(writer, b"IEND", &[])
+    }
+
+    fn rgb(&self) -> Vec<u8> {
+        self.pixels
+            .iter()
+            .flat_map(|pixel| {
+                let [blue, green, red, _] = pixel.to_le_bytes();
+                [red, green, blue]
+            })
+            .collect()
+    }
+}
+
+fn write_chunk<W: Write>(writer: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(kind)?;
+    writer.write_all(data)?;
+
+    let crc = crc32(kind.iter().chain(data));
+    writer.write_all(&crc.to_be_bytes())
+}
+
+/// Wraps `data` into a zlib stream which consists of uncompressed deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut stream = vec![0x78, 0x01];
+
+    let mut blocks = data.chunks(MAX_STORED_BLOCK_SIZE).peekable();
+    if blocks.peek().is_none() {
+        stream.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+
+    while let Some(block) = blocks.next() {
+        let is_last = blocks.peek().is_none();
+        let size = block.len() as u16;
+
+        stream.push(u8::from(is_last));
+        stream.extend_from_slice(&size.to_le_bytes());
+        stream.extend_from_slice(&(!size).to_le_bytes());
+        stream.extend_from_slice(block);
+    }
+
+    stream.extend_from_slice(&adler32(data).to_be_bytes());
+    stream
+}
+
+fn crc32<'a>(bytes: impl IntoIterator<Item = &'a u8>) -> u32 {
+    let mut crc = u32::MAX;
+
+    for &byte in bytes {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb88320 & mask);
+        }
+    }
+
+    !crc
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    const MODULO: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+
+    for &byte in bytes {
+        a = (a + u32::from(byte)) % MODULO;
+        b = (b + a) % MODULO;
+    }
+
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the chunks of a PNG and checks their CRCs.
+    fn read_chunks(png: &[u8]) -> Vec<([u8; 4], Vec<u8>)> {
+        assert_eq!(png[..8], PNG_SIGNATURE);
+        let mut chunks = Vec::new();
+        let mut rest = &png[8..];
+
+        while !rest.is_empty() {
+            let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            let (kind, data) = rest[4..8 + len].split_at(4);
+            let crc = u32::from_be_bytes(rest[8 + len..12 + len].try_into().unwrap());
+            assert_eq!(crc, crc32(kind.iter().chain(data)));
+
+            chunks.push((kind.try_into().unwrap(), data.to_vec()));
+            rest = &rest[12 + len..];
+        }
+
+        chunks
+    }
+
+    /// Returns the data of a zlib stream of [zlib_stored] and the sizes of its blocks.
+    fn read_stored(stream: &[u8]) -> (Vec<u8>, Vec<usize>) {
+        assert_eq!(stream[..2], [0x78, 0x01]);
+        assert_eq!(u16::from_be_bytes([stream[0], stream[1]]) % 31, 0);
+        let mut data = Vec::new();
+        let mut sizes = Vec::new();
+        let mut pos = 2;
+
+        loop {
+            let is_last = stream[pos] == 1;
+            let size = u16::from_le_bytes([stream[pos + 1], stream[pos + 2]]);
+            let inverted = u16::from_le_bytes([stream[pos + 3], stream[pos + 4]]);
+            assert_eq!(!size, inverted);
+
+            data.extend_from_slice(&stream[pos + 5..][..usize::from(size)]);
+            sizes.push(usize::from(size));
+            pos += 5 + usize::from(size);
+            if is_last {
+                break;
+            }
+        }
+
+        assert_eq!(stream[pos..], adler32(&data).to_be_bytes());
+        (data, sizes)
+    }
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        assert_eq!(crc32(b"IEND"), 0xae426082);
+
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e60398);
+        // the sums wrap around at the modulo
+        assert_eq!(adler32(&[0xff; 6000]), 0xa49759ea);
+    }
+
+    #[test]
+    fn writes_ppm() {
+        let image = Image::scaled(&[0x102030, 0xffffff], 2, 1, 1);
+        let mut ppm = Vec::new();
+        image.write_ppm(&mut ppm).unwrap();
+
+        assert_eq!(ppm, b"P6\n2 1\n255\n\x10\x20\x30\xff\xff\xff");
+    }
+
+    #[test]
+    fn writes_png() {
+        let image = Image::scaled(&[0x102030, 0xffffff], 2, 1, 1);
+        let mut png = Vec::new();
+        image.write_png(&mut png).unwrap();
+
+        let chunks = read_chunks(&png);
+        let kinds: Vec<&[u8; 4]> = chunks.iter().map(|(kind, _)| kind).collect();
+        assert_eq!(kinds, [b"IHDR", b"IDAT", b"IEND"]);
+        assert_eq!(chunks[0].1, [0, 0, 0, 2, 0, 0, 0, 1, 8, 2, 0, 0, 0]);
+
+        let (data, sizes) = read_stored(&chunks[1].1);
+        assert_eq!(data, [0, 0x10, 0x20, 0x30, 0xff, 0xff, 0xff]);
+        assert_eq!(sizes, [7]);
+    }
+
+    #[test]
+    fn splits_big_images_into_blocks() {
+        let pixels: Vec<u32> = (0..200 * 120).map(|index| index * 0x010203).collect();
+        let image = Image::scaled(&pixels, 200, 120, 1);
+        let mut png = Vec::new();
+        image.write_png(&mut png).unwrap();
+
+        let chunks = read_chunks(&png);
+        let (data, sizes) = read_stored(&chunks[1].1);
+        let scanlines: Vec<u8> = image
+            .rgb()
+            .chunks(200 * 3)
+            .flat_map(|row| [&[0][..], row].concat())
+            .collect();
+        assert_eq!(data, scanlines);
+        assert_eq!(
+            sizes,
+            [
+                MAX_STORED_BLOCK_SIZE,
+                scanlines.len() - MAX_STORED_BLOCK_SIZE
+            ]
+        );
+
+        assert_eq!(read_stored(&zlib_stored(&[])), (Vec::new(), vec![0]));
+    }
+
+    #[test]
+    fn scales_pixels() {
+        let image = Image::scaled(&[1, 2, 3, 4], 2, 2, 2);
+
+        assert_eq!((image.width, image.height), (4, 4));
+        assert_eq!(
+            image.pixels,
+            [1, 1, 2, 2, 1, 1, 2, 2, 3, 3, 4, 4, 3, 3, 4, 4]
+        );
+    }
+}
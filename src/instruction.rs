@@ -0,0 +1,342 @@
+use crate::chip8::{self, INSTRUCTION_SIZE_BYTES};
+use crate::quirks::Quirks;
+use crate::Addr;
+
+use std::fmt;
+
+/// A decoded CHIP-8, SUPER-CHIP or XO-CHIP instruction. The fields are the register indices and
+/// immediates of the opcode, the behaviour of the quirks is left to the backends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Instruction {
+    /// `00CN`
+    Scd(u8),
+    /// `00DN`
+    Scu(u8),
+    /// `00E0`
+    Cls,
+    /// `00EE`
+    Ret,
+    /// `00FB`
+    Scr,
+    /// `00FC`
+    Scl,
+    /// `00FD`
+    Exit,
+    /// `00FE`
+    Low,
+    /// `00FF`
+    High,
+    /// `0NNN`
+    Sys(u16),
+    /// `1NNN`
+    Jp(u16),
+    /// `2NNN`
+    Call(u16),
+    /// `3XKK`
+    SeByte(u8, u8),
+    /// `4XKK`
+    SneByte(u8, u8),
+    /// `5XY0`
+    SeReg(u8, u8),
+    /// `5XY2`
+    SaveRange(u8, u8),
+    /// `5XY3`
+    LoadRange(u8, u8),
+    /// `6XKK`
+    LdByte(u8, u8),
+    /// `7XKK`
+    AddByte(u8, u8),
+    /// `8XY0`
+    LdReg(u8, u8),
+    /// `8XY1`
+    Or(u8, u8),
+    /// `8XY2`
+    And(u8, u8),
+    /// `8XY3`
+    Xor(u8, u8),
+    /// `8XY4`
+    AddReg(u8, u8),
+    /// `8XY5`
+    Sub(u8, u8),
+    /// `8XY6`
+    Shr(u8, u8),
+    /// `8XY7`
+    Subn(u8, u8),
+    /// `8XYE`
+    Shl(u8, u8),
+    /// `9XY0`
+    SneReg(u8, u8),
+    /// `ANNN` and `F000 NNNN`
+    LdI(u16),
+    /// `BNNN`
+    JpV0(u16),
+    /// `CXKK`
+    Rnd(u8, u8),
+    /// `DXYN`
+    Drw(u8, u8, u8),
+    /// `EX9E`
+    Skp(u8),
+    /// `EXA1`
+    Sknp(u8),
+    /// `FN01`
+    Plane(u8),
+    /// `F002`
+    Audio,
+    /// `FX07`
+    LdXDt(u8),
+    /// `FX0A`
+    LdK(u8),
+    /// `FX15`
+    LdDtX(u8),
+    /// `FX18`
+    LdSt(u8),
+    /// `FX1E`
+    AddI(u8),
+    /// `FX29`
+    LdF(u8),
+    /// `FX30`
+    LdHf(u8),
+    /// `FX33`
+    LdB(u8),
+    /// `FX3A`
+    Pitch(u8),
+    /// `FX55`
+    LdIX(u8),
+    /// `FX65`
+    LdXI(u8),
+    /// `FX75`
+    LdRX(u8),
+    /// `FX85`
+    LdXR(u8),
+}
+
+impl Instruction {
+    /// Decodes the instruction at `addr`, `None` if it's unknown.
+    pub fn decode(mem: &[u8], addr: Addr) -> Option<Self> {
+        let instruction = chip8::read_word(mem, addr);
+        let nibbles = [
+            ((instruction & 0xf000) >> 12) as u8,
+            ((instruction & 0x0f00) >> 8) as u8,
+            ((instruction & 0x00f0) >> 4) as u8,
+            (instruction & 0x000f) as u8,
+        ];
+
+        let x = nibbles[1];
+        let y = nibbles[2];
+        let kk = (instruction & 0x00ff) as u8;
+        let nnn = instruction & 0x0fff;
+
+        let decoded = match nibbles {
+            [0x0, 0x0, 0xc, n] => Self::Scd(n),
+            [0x0, 0x0, 0xd, n] => Self::Scu(n),
+            [0x0, 0x0, 0xe, 0x0] => Self::Cls,
+            [0x0, 0x0, 0xe, 0xe] => Self::Ret,
+            [0x0, 0x0, 0xf, 0xb] => Self::Scr,
+            [0x0, 0x0, 0xf, 0xc] => Self::Scl,
+            [0x0, 0x0, 0xf, 0xd] => Self::Exit,
+            [0x0, 0x0, 0xf, 0xe] => Self::Low,
+            [0x0, 0x0, 0xf, 0xf] => Self::High,
+            [0x0, _, _, _] => Self::Sys(nnn),
+            [0x1, _, _, _] => Self::Jp(nnn),
+            [0x2, _, _, _] => Self::Call(nnn),
+            [0x3, _, _, _] => Self::SeByte(x, kk),
+            [0x4, _, _, _] => Self::SneByte(x, kk),
+            [0x5, _, _, 0x0] => Self::SeReg(x, y),
+            [0x5, _, _, 0x2] => Self::SaveRange(x, y),
+            [0x5, _, _, 0x3] => Self::LoadRange(x, y),
+            [0x6, _, _, _] => Self::LdByte(x, kk),
+            [0x7, _, _, _] => Self::AddByte(x, kk),
+            [0x8, _, _, 0x0] => Self::LdReg(x, y),
+            [0x8, _, _, 0x1] => Self::Or(x, y),
+            [0x8, _, _, 0x2] => Self::And(x, y),
+            [0x8, _, _, 0x3] => Self::Xor(x, y),
+            [0x8, _, _, 0x4] => Self::AddReg(x, y),
+            [0x8, _, _, 0x5] => Self::Sub(x, y),
+            [0x8, _, _, 0x6] => Self::Shr(x, y),
+            [0x8, _, _, 0x7] => Self::Subn(x, y),
+            [0x8, _, _, 0xe] => Self::Shl(x, y),
+            [0x9, _, _, 0x0] => Self::SneReg(x, y),
+            [0xa, _, _, _] => Self::LdI(nnn),
+            [0xb, _, _, _] => Self::JpV0(nnn),
+            [0xc, _, _, _] => Self::Rnd(x, kk),
+            [0xd, _, _, n] => Self::Drw(x, y, n),
+            [0xe, _, 0x9, 0xe] => Self::Skp(x),
+            [0xe, _, 0xa, 0x1] => Self::Sknp(x),
+            [0xf, 0x0, 0x0, 0x0] => Self::LdI(chip8::read_word(mem, addr + INSTRUCTION_SIZE_BYTES)),
+            [0xf, n, 0x0, 0x1] => Self::Plane(n),
+            [0xf, 0x0, 0x0, 0x2] => Self::Audio,
+            [0xf, _, 0x0, 0x7] => Self::LdXDt(x),
+            [0xf, _, 0x0, 0xa] => Self::LdK(x),
+            [0xf, _, 0x1, 0x5] => Self::LdDtX(x),
+            [0xf, _, 0x1, 0x8] => Self::LdSt(x),
+            [0xf, _, 0x1, 0xe] => Self::AddI(x),
+            [0xf, _, 0x2, 0x9] => Self::LdF(x),
+            [0xf, _, 0x3, 0x0] => Self::LdHf(x),
+            [0xf, _, 0x3, 0x3] => Self::LdB(x),
+            [0xf, _, 0x3, 0xa] => Self::Pitch(x),
+            [0xf, _, 0x5, 0x5] => Self::LdIX(x),
+            [0xf, _, 0x6, 0x5] => Self::LdXI(x),
+            [0xf, _, 0x7, 0x5] => Self::LdRX(x),
+            [0xf, _, 0x8, 0x5] => Self::LdXR(x),
+            _ => return None,
+        };
+
+        Some(decoded)
+    }
+
+    /// Returns `true` if a compiled block ends after this instruction, because it changes pc or
+    /// has to wait for the next frame with the `display_wait` quirk.
+    pub fn ends_block(&self, quirks: &Quirks) -> bool {
+        match self {
+            Self::Ret
+            | Self::Exit
+            | Self::Sys(_)
+            | Self::Jp(_)
+            | Self::Call(_)
+            | Self::SeByte(..)
+            | Self::SneByte(..)
+            | Self::SeReg(..)
+            | Self::SneReg(..)
+            | Self::JpV0(_)
+            | Self::Skp(_)
+            | Self::Sknp(_)
+            | Self::LdK(_) => true,
+            Self::Drw(..) => quirks.display_wait,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    /// Formats the instruction in the mnemonics of Cowgod's reference.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Scd(n) => write!(f, "SCD {:#x}", n),
+            Self::Scu(n) => write!(f, "SCU {:#x}", n),
+            Self::Cls => write!(f, "CLS"),
+            Self::Ret => write!(f, "RET"),
+            Self::Scr => write!(f, "SCR"),
+            Self::Scl => write!(f, "SCL"),
+            Self::Exit => write!(f, "EXIT"),
+            Self::Low => write!(f, "LOW"),
+            Self::High => write!(f, "HIGH"),
+            Self::Sys(nnn) => write!(f, "SYS {:#05x}", nnn),
+            Self::Jp(nnn) => write!(f, "JP {:#05x}", nnn),
+            Self::Call(nnn) => write!(f, "CALL {:#05x}", nnn),
+            Self::SeByte(x, kk) => write!(f, "SE V{:X}, {:#04x}", x, kk),
+            Self::SneByte(x, kk) => write!(f, "SNE V{:X}, {:#04x}", x, kk),
+            Self::SeReg(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            Self::SaveRange(x, y) => write!(f, "LD [I], V{:X}-V{:X}", x, y),
+            Self::LoadRange(x, y) => write!(f, "LD V{:X}-V{:X}, [I]", x, y),
+            Self::LdByte(x, kk) => write!(f, "LD V{:X}, {:#04x}", x, kk),
+            Self::AddByte(x, kk) => write!(f, "ADD V{:X}, {:#04x}", x, kk),
+            Self::LdReg(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            Self::Or(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            Self::And(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            Self::Xor(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Self::AddReg(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Self::Sub(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Self::Shr(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Self::Subn(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Self::Shl(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Self::SneReg(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Self::LdI(nnn) => write!(f, "LD I, {:#05x}", nnn),
+            Self::JpV0(nnn) => write!(f, "JP V0, {:#05x}", nnn),
+            Self::Rnd(x, kk) => write!(f, "RND V{:X}, {:#04x}", x, kk),
+            Self::Drw(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {:#x}", x, y, n),
+            Self::Skp(x) => write!(f, "SKP V{:X}", x),
+            Self::Sknp(x) => write!(f, "SKNP V{:X}", x),
+            Self::Plane(n) => write!(f, "PLANE {:#x}", n),
+            Self::Audio => write!(f, "AUDIO"),
+            Self::LdXDt(x) => write!(f, "LD V{:X}, DT", x),
+            Self::LdK(x) => write!(f, "LD V{:X}, K", x),
+            Self::LdDtX(x) => write!(f, "LD DT, V{:X}", x),
+            Self::LdSt(x) => write!(f, "LD ST, V{:X}", x),
+            Self::AddI(x) => write!(f, "ADD I, V{:X}", x),
+            Self::LdF(x) => write!(f, "LD F, V{:X}", x),
+            Self::LdHf(x) => write!(f, "LD HF, V{:X}", x),
+            Self::LdB(x) => write!(f, "LD B, V{:X}", x),
+            Self::Pitch(x) => write!(f, "PITCH V{:X}", x),
+            Self::LdIX(x) => write!(f, "LD [I], V{:X}", x),
+            Self::LdXI(x) => write!(f, "LD V{:X}, [I]", x),
+            Self::LdRX(x) => write!(f, "LD R, V{:X}", x),
+            Self::LdXR(x) => write!(f, "LD V{:X}, R", x),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(words: &[u16]) -> Option<Instruction> {
+        let mut mem = vec![0; chip8::Chip8::MEM_SIZE];
+        for (i, word) in words.iter().enumerate() {
+            mem[0x200 + 2 * i..][..2].copy_from_slice(&word.to_be_bytes());
+        }
+        Instruction::decode(&mem, 0x200)
+    }
+
+    #[test]
+    fn decodes_chip8_instructions() {
+        assert_eq!(decode(&[0x00e0]), Some(Instruction::Cls));
+        assert_eq!(decode(&[0x00ee]), Some(Instruction::Ret));
+        assert_eq!(decode(&[0x0123]), Some(Instruction::Sys(0x123)));
+        assert_eq!(decode(&[0x1abc]), Some(Instruction::Jp(0xabc)));
+        assert_eq!(decode(&[0x3a42]), Some(Instruction::SeByte(0xa, 0x42)));
+        assert_eq!(decode(&[0x5120]), Some(Instruction::SeReg(1, 2)));
+        assert_eq!(decode(&[0x8ab6]), Some(Instruction::Shr(0xa, 0xb)));
+        assert_eq!(decode(&[0xd125]), Some(Instruction::Drw(1, 2, 5)));
+        assert_eq!(decode(&[0xe3a1]), Some(Instruction::Sknp(3)));
+        assert_eq!(decode(&[0xf40a]), Some(Instruction::LdK(4)));
+        assert_eq!(decode(&[0xf565]), Some(Instruction::LdXI(5)));
+    }
+
+    #[test]
+    fn decodes_schip_and_xochip_instructions() {
+        assert_eq!(decode(&[0x00c4]), Some(Instruction::Scd(4)));
+        assert_eq!(decode(&[0x00d4]), Some(Instruction::Scu(4)));
+        assert_eq!(decode(&[0x00ff]), Some(Instruction::High));
+        assert_eq!(decode(&[0x5132]), Some(Instruction::SaveRange(1, 3)));
+        assert_eq!(decode(&[0x5133]), Some(Instruction::LoadRange(1, 3)));
+        assert_eq!(decode(&[0xf201]), Some(Instruction::Plane(2)));
+        assert_eq!(decode(&[0xf002]), Some(Instruction::Audio));
+        assert_eq!(decode(&[0xf13a]), Some(Instruction::Pitch(1)));
+        assert_eq!(decode(&[0xf785]), Some(Instruction::LdXR(7)));
+    }
+
+    #[test]
+    fn decodes_long_loads() {
+        assert_eq!(decode(&[0xf000, 0xbeef]), Some(Instruction::LdI(0xbeef)));
+
+        let mut mem = vec![0; chip8::Chip8::MEM_SIZE];
+        mem[0x200..0x204].copy_from_slice(&[0xf0, 0x00, 0x12, 0x34]);
+        assert_eq!(chip8::instruction_size(&mem, 0x200), 4);
+        assert_eq!(chip8::instruction_size(&mem, 0x202), 2);
+
+        // the operand wraps around at the end of the memory
+        let end = chip8::Chip8::MEM_SIZE - 2;
+        mem[end..].copy_from_slice(&[0xf0, 0x00]);
+        mem[..2].copy_from_slice(&[0x0a, 0xbc]);
+        assert_eq!(
+            Instruction::decode(&mem, end as Addr),
+            Some(Instruction::LdI(0x0abc))
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_instructions() {
+        assert_eq!(decode(&[0x5121]), None);
+        assert_eq!(decode(&[0x8128]), None);
+        assert_eq!(decode(&[0x9121]), None);
+        assert_eq!(decode(&[0xe19f]), None);
+        assert_eq!(decode(&[0xf1ff]), None);
+    }
+
+    #[test]
+    fn formats_mnemonics() {
+        assert_eq!(Instruction::Drw(1, 2, 5).to_string(), "DRW V1, V2, 0x5");
+        assert_eq!(Instruction::SaveRange(1, 0xa).to_string(), "LD [I], V1-VA");
+        assert_eq!(Instruction::LdI(0x123).to_string(), "LD I, 0x123");
+    }
+}
@@ -1,124 +1,319 @@
 use bit_iter::BitIter;
-use minifb::Key;
 
 use crate::chip8::{
-    Chip8State, WINDOW_HEIGHTusize, WINDOW_WIDTHusize, INSTRUCTION_SIZE_BYTES, PIXEL_CLEAN, WINDOW_WIDTHu16, WINDOW_SIZEusize,
+    self, Chip8, Chip8State, AMOUNT_KEYS, AMOUNT_PLANES, AUDIO_PATTERN_SIZE, BIG_SPRITES_ADDRESS,
+    INSTRUCTION_SIZE_BYTES,
 };
 
-fn key_value(key: Key) -> u64 {
-    match key {
-        Key::Key1 => 0x1,
-        Key::Key2 => 0x2,
-        Key::Key3 => 0x3,
-        Key::Key4 => 0x4,
-        Key::Key5 => 0x5,
-        Key::Key6 => 0x6,
-        Key::Key7 => 0x7,
-        Key::Key8 => 0x8,
-        Key::Key9 => 0x9,
-        Key::A => 0xA,
-        Key::B => 0xB,
-        Key::C => 0xC,
-        Key::E => 0xE,
-        Key::F => 0xF,
-        _ => unreachable!(""),
+use std::cell::Cell;
+use std::hash::{BuildHasher, RandomState};
+
+/// The functions which compiled code calls. Their addresses are stored in
+/// [Chip8State::helpers], so the code doesn't depend on the address the binary is loaded at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Helper {
+    Cls,
+    Scd,
+    Scu,
+    Scr,
+    Scl,
+    Exit,
+    Low,
+    High,
+    Drw,
+    Skp,
+    Sknp,
+    LdK,
+    Rnd,
+    LdF,
+    LdHf,
+    LdB,
+    LdIRange,
+    LdRangeI,
+    Audio,
+    LdRX,
+    LdXR,
+}
+
+impl Helper {
+    pub const COUNT: usize = 21;
+
+    /// Returns the addresses of all helpers, indexed by the helper.
+    pub fn table() -> [u64; Self::COUNT] {
+        [
+            cls as *const () as u64,
+            scd as *const () as u64,
+            scu as *const () as u64,
+            scr as *const () as u64,
+            scl as *const () as u64,
+            exit as *const () as u64,
+            low as *const () as u64,
+            high as *const () as u64,
+            drw as *const () as u64,
+            skp as *const () as u64,
+            sknp as *const () as u64,
+            ld_k as *const () as u64,
+            rnd as *const () as u64,
+            ld_f as *const () as u64,
+            ld_hf as *const () as u64,
+            ld_b as *const () as u64,
+            ld_i_range as *const () as u64,
+            ld_range_i as *const () as u64,
+            audio as *const () as u64,
+            ld_r_x as *const () as u64,
+            ld_x_r as *const () as u64,
+        ]
+    }
+}
+
+/// Moves the content of the selected planes by `dx` pixels to the right and `dy` pixels down.
+fn scroll(state: &mut Chip8State, dx: isize, dy: isize) {
+    let (width, height) = state.resolution();
+    let planes = state.planes as u8;
+    let prev_fb = state.fb;
+
+    for y in 0..height {
+        for x in 0..width {
+            let src_x = x as isize - dx;
+            let src_y = y as isize - dy;
+
+            let src_pixel =
+                if (0..width as isize).contains(&src_x) && (0..height as isize).contains(&src_y) {
+                    prev_fb[src_x as usize + src_y as usize * width] & planes
+                } else {
+                    0
+                };
+
+            let pixel = &mut state.fb[x + y * width];
+            *pixel = (*pixel & !planes) | src_pixel;
+        }
     }
 }
 
 pub unsafe extern "C" fn cls(state: *mut Chip8State) {
     let state = &mut *state;
-    let buffer = [PIXEL_CLEAN; WINDOW_SIZEusize];
-    state
-        .window
-        .update_with_buffer(
-            &buffer,
-            WINDOW_WIDTHusize,
-            WINDOW_HEIGHTusize,
-        )
-        .unwrap();
+    let planes = state.planes as u8;
+
+    state.fb.iter_mut().for_each(|pixel| *pixel &= !planes);
+}
+
+pub unsafe extern "C" fn scd(state: *mut Chip8State, n: u64) {
+    scroll(&mut *state, 0, n as isize);
+}
+
+pub unsafe extern "C" fn scu(state: *mut Chip8State, n: u64) {
+    scroll(&mut *state, 0, -(n as isize));
+}
+
+pub unsafe extern "C" fn scr(state: *mut Chip8State) {
+    scroll(&mut *state, 4, 0);
+}
+
+pub unsafe extern "C" fn scl(state: *mut Chip8State) {
+    scroll(&mut *state, -4, 0);
+}
+
+pub unsafe extern "C" fn exit(state: *mut Chip8State) {
+    (*state).should_run = false;
+}
+
+pub unsafe extern "C" fn low(state: *mut Chip8State) {
+    let state = &mut *state;
+    state.hires = false;
+    state.fb.fill(0);
+}
+
+pub unsafe extern "C" fn high(state: *mut Chip8State) {
+    let state = &mut *state;
+    state.hires = true;
+    state.fb.fill(0);
 }
 
 pub unsafe extern "C" fn drw(state: *mut Chip8State, vx: u64, vy: u64, nibble: u64) {
     let state = &mut *state;
-    let vx_value = state.regs[vx as usize];
-    let vy_value = state.regs[vy as usize];
-    state.regs[0xf] = 0;
+    let (width, height) = state.resolution();
+    state.wait_for_frame = state.quirks.display_wait;
+
+    // the start coordinate always wraps around, only the rest of the sprite may get clipped
+    let x_start = state.regs[vx as usize] as usize % width;
+    let y_start = state.regs[vy as usize] as usize % height;
+    let mut collision = false;
 
-    let x_start = vx_value;
-    let y_start = vy_value;
+    // `DXY0` draws a 16x16 sprite
+    let (rows, bytes_per_row) = if nibble == 0 {
+        (16, 2)
+    } else {
+        (nibble as usize, 1)
+    };
+    let mut sprite_addr = state.i;
 
-    for offset in 0..nibble {
-        let byte = state.mem[(state.i + offset) as usize];
-        let y: u64 = if y_start + offset == 0 {
-            0
-        } else {
-            y_start + offset - 1
-        };
+    for plane in 0..AMOUNT_PLANES {
+        let plane_bit = 1u8 << plane;
+        if state.planes as u8 & plane_bit == 0 {
+            continue;
+        }
+
+        for row in 0..rows {
+            let y = y_start + row;
 
-        for bit in BitIter::from(byte.reverse_bits()) {
-            let x = x_start + bit as u64;
+            for column in 0..bytes_per_row {
+                let byte = state.mem[(sprite_addr & Chip8::ADDR_MASK) as usize];
+                sprite_addr += 1;
 
-            let addr: usize = (x + y * u64::from(WINDOW_WIDTHu16)) as usize;
-            let prev_value = state.fb[addr];
+                for bit in BitIter::from(byte.reverse_bits()) {
+                    let x = x_start + column * 8 + bit;
 
-            state.fb[addr] = !state.fb[addr];
+                    if (x >= width || y >= height) && state.quirks.clip_sprites {
+                        continue;
+                    }
 
-            if state.fb[addr] != prev_value {
-                state.regs[0xf] = 1;
+                    let pixel = &mut state.fb[x % width + (y % height) * width];
+                    collision |= *pixel & plane_bit != 0;
+                    *pixel ^= plane_bit;
+                }
             }
         }
     }
+
+    state.regs[0xf] = u64::from(collision);
 }
 
 pub unsafe extern "C" fn skp(state: *mut Chip8State, vx: u64) {
-    let vx = u8::try_from(vx & 0xff).unwrap();
     let state = &mut *state;
+    let key = state.regs[vx as usize] & 0xf;
 
-    if state.keys[usize::try_from(vx).unwrap()] {
-        state.pc += INSTRUCTION_SIZE_BYTES;
+    if state.keys[key as usize] {
+        state.pc += chip8::instruction_size(&state.mem, state.pc + INSTRUCTION_SIZE_BYTES);
     }
 }
 
 pub unsafe extern "C" fn sknp(state: *mut Chip8State, vx: u64) {
-    let vx = u8::try_from(vx & 0xff).unwrap();
     let state = &mut *state;
+    let key = state.regs[vx as usize] & 0xf;
 
-    if !state.keys[usize::try_from(vx).unwrap()] {
-        state.pc += INSTRUCTION_SIZE_BYTES;
+    if !state.keys[key as usize] {
+        state.pc += chip8::instruction_size(&state.mem, state.pc + INSTRUCTION_SIZE_BYTES);
     }
 }
 
+/// Stores a key which got pressed in the current frame in Vx. With the `key_release` quirk the
+/// key is only stored once it's released again, like on the COSMAC VIP. While there's none, pc
+/// stays at this instruction and the execution continues in the next frame.
 pub unsafe extern "C" fn ld_k(state: *mut Chip8State, vx: u64) {
-    let vx = u8::try_from(vx & 0xff).unwrap();
-    let mut found_key = false;
-    let state = &mut *state;
-    let vx = usize::try_from(vx).unwrap();
-
-    while !found_key {
-        state
-            .window
-            .get_keys_pressed(minifb::KeyRepeat::No)
-            .into_iter()
-            .for_each(|key: Key| {
-                state.regs[vx] = key_value(key);
-                found_key = true;
-            });
+    let state = &mut *state;
+
+    if state.pressed_key == Chip8::NO_KEY {
+        let pressed = (0..AMOUNT_KEYS).find(|&key| state.keys[key] && !state.previous_keys[key]);
+        if let Some(key) = pressed {
+            state.pressed_key = key as u64;
+        }
     }
+
+    let key = state.pressed_key;
+    let done = key != Chip8::NO_KEY && !(state.quirks.key_release && state.keys[key as usize]);
+    if done {
+        state.regs[vx as usize] = key;
+        state.pressed_key = Chip8::NO_KEY;
+        state.pc += INSTRUCTION_SIZE_BYTES;
+    } else {
+        state.wait_for_frame = true;
+    }
+}
+
+/// Sets Vx to a random byte which is masked with `kk`. The x86 backend uses `rdrand` instead.
+pub unsafe extern "C" fn rnd(state: *mut Chip8State, vx: u64, kk: u64) {
+    let state = &mut *state;
+    state.regs[vx as usize] = next_random() & kk & 0xff;
+}
+
+/// Returns the next number of a xorshift generator, which is seeded randomly on the first call.
+fn next_random() -> u64 {
+    thread_local! {
+        static SEED: Cell<u64> = Cell::new(RandomState::new().hash_one(0u64) | 1);
+    }
+
+    SEED.with(|seed| {
+        let mut x = seed.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        seed.set(x);
+        x >> 32
+    })
 }
 
 pub unsafe extern "C" fn ld_f(state: *mut Chip8State, vx: u64) {
-    let vx = u8::try_from(vx & 0xff).unwrap();
-    let index = u64::from(vx * 5);
-    (*state).i = index;
+    let state = &mut *state;
+    let digit = state.regs[vx as usize] & 0xf;
+    state.i = digit * 5;
+}
+
+pub unsafe extern "C" fn ld_hf(state: *mut Chip8State, vx: u64) {
+    let state = &mut *state;
+    let digit = state.regs[vx as usize] & 0xf;
+    state.i = BIG_SPRITES_ADDRESS + digit * 10;
 }
 
 pub unsafe extern "C" fn ld_b(state: *mut Chip8State, vx: u64) {
     let state = &mut *state;
 
     let vx_value = state.regs[vx as usize];
-    let start_index = usize::try_from(state.i).unwrap();
+    let digits = [vx_value / 100, (vx_value % 100) / 10, vx_value % 10];
+
+    for (offset, digit) in digits.into_iter().enumerate() {
+        let addr = (state.i + offset as u64) & Chip8::ADDR_MASK;
+        state.mem[addr as usize] = digit as u8;
+        state.mark_written(addr);
+    }
+}
+
+/// Returns the register indices from `vx` to `vy`, in reverse order if `vx > vy`.
+fn register_range(vx: u64, vy: u64) -> Vec<usize> {
+    if vx <= vy {
+        (vx as usize..=vy as usize).collect()
+    } else {
+        (vy as usize..=vx as usize).rev().collect()
+    }
+}
+
+pub unsafe extern "C" fn ld_i_range(state: *mut Chip8State, vx: u64, vy: u64) {
+    let state = &mut *state;
+
+    for (offset, reg) in register_range(vx, vy).into_iter().enumerate() {
+        let addr = (state.i + offset as u64) & Chip8::ADDR_MASK;
+        state.mem[addr as usize] = state.regs[reg] as u8;
+        state.mark_written(addr);
+    }
+}
+
+pub unsafe extern "C" fn ld_range_i(state: *mut Chip8State, vx: u64, vy: u64) {
+    let state = &mut *state;
+
+    for (offset, reg) in register_range(vx, vy).into_iter().enumerate() {
+        let addr = (state.i + offset as u64) & Chip8::ADDR_MASK;
+        state.regs[reg] = u64::from(state.mem[addr as usize]);
+    }
+}
+
+pub unsafe extern "C" fn audio(state: *mut Chip8State) {
+    let state = &mut *state;
+
+    for offset in 0..AUDIO_PATTERN_SIZE {
+        let addr = (state.i + offset as u64) & Chip8::ADDR_MASK;
+        state.audio_pattern[offset] = state.mem[addr as usize];
+    }
+}
+
+pub unsafe extern "C" fn ld_r_x(state: *mut Chip8State, vx: u64) {
+    let state = &mut *state;
+    let amount = vx as usize + 1;
+
+    state.flags[..amount].copy_from_slice(&state.regs[..amount]);
+}
+
+pub unsafe extern "C" fn ld_x_r(state: *mut Chip8State, vx: u64) {
+    let state = &mut *state;
+    let amount = vx as usize + 1;
 
-    state.mem[start_index] = u8::try_from(vx_value / 100).unwrap();
-    state.mem[start_index + 1] = u8::try_from((vx_value % 100) / 10).unwrap();
-    state.mem[start_index + 2] = u8::try_from(vx_value % 10).unwrap();
+    state.regs[..amount].copy_from_slice(&state.flags[..amount]);
 }
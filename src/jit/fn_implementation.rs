@@ -1,575 +1,829 @@
-use crate::chip8::{Chip8Field, Chip8State, INSTRUCTION_SIZE_BYTES, Chip8};
+use crate::chip8::{Chip8, Chip8Field};
+use crate::error::Fault;
+use crate::error::Result;
 
 use super::{
-    fn_extern,
     fn_traits::{ArgLd, ArgSe, ArgSne},
-    Nnn, Byte, Vx, Vy, JIT,
+    Byte, Helper, Nnn, Vx, Vy, JIT,
 };
 
 use iced_x86::code_asm::*;
 use log::debug;
 
-impl JIT {
-    fn function_call_prolog(&mut self) {
-        self.x86.push(rdi).unwrap();
-        self.x86.push(rbp).unwrap();
-        self.x86.mov(rbp, rsp).unwrap();
+impl JIT<'_> {
+    fn function_call_prolog(&mut self) -> Result<()> {
+        self.x86.push(rdi)?;
+        self.x86.push(rbp)?;
+        self.x86.mov(rbp, rsp)?;
+
+        Ok(())
     }
 
-    fn function_call_epilog(&mut self) {
-        self.x86.mov(rsp, rbp).unwrap();
-        self.x86.pop(rbp).unwrap();
-        self.x86.pop(rdi).unwrap();
+    fn function_call_epilog(&mut self) -> Result<()> {
+        self.x86.mov(rsp, rbp)?;
+        self.x86.pop(rbp)?;
+        self.x86.pop(rdi)?;
+
+        Ok(())
+    }
+
+    /// Calls `helper` with the state pointer in `rdi`. The other arguments have to be set
+    /// already. The address is read from the helper table of the state to keep the code position
+    /// independent.
+    fn call_extern(&mut self, helper: Helper) -> Result<()> {
+        let helper_addr = rdi + self.get_field_offset(Chip8Field::Helper(helper));
+
+        self.x86.mov(rax, qword_ptr(helper_addr))?;
+        self.x86.call(rax)?;
+
+        Ok(())
+    }
+
+    /// Sets Vf to zero if the `vf_reset` quirk is enabled.
+    fn reset_vf(&mut self) -> Result<()> {
+        if self.quirks().vf_reset {
+            let vf_addr = rdi + self.get_field_offset(Chip8Field::Reg(0xf));
+            self.x86.mov(qword_ptr(vf_addr), 0)?;
+        }
+
+        Ok(())
     }
 
-    fn increment_pc(&mut self) {
+    /// Adds `X + 1` to `I` if the `increment_i` quirk is enabled.
+    fn increment_i(&mut self, vx: Vx) -> Result<()> {
+        if self.quirks().increment_i {
+            let i_addr = rdi + self.get_field_offset(Chip8Field::I);
+
+            self.x86.mov(r8, qword_ptr(i_addr))?;
+            self.x86.add(r8, i32::from(vx.0) + 1)?;
+            self.x86.and(r8, Chip8::ADDR_MASK as i32)?;
+            self.x86.mov(qword_ptr(i_addr), r8)?;
+        }
+
+        Ok(())
+    }
+
+    fn increment_pc(&mut self) -> Result<()> {
         let pc_addr = rdi + self.get_field_offset(Chip8Field::PC);
+        let instruction_size = self.instruction_size(self.addr);
 
-        self.x86.mov(r8, qword_ptr(pc_addr)).unwrap();
-        self.x86.mov(r9, INSTRUCTION_SIZE_BYTES).unwrap();
-        self.x86.add(r8, r9).unwrap();
-        self.x86.mov(qword_ptr(pc_addr), r8).unwrap();
+        self.x86.mov(r8, qword_ptr(pc_addr))?;
+        self.x86.mov(r9, instruction_size)?;
+        self.x86.add(r8, r9)?;
+        self.x86.mov(qword_ptr(pc_addr), r8)?;
+
+        Ok(())
     }
 
-    pub fn cls(&mut self) -> bool {
+    pub fn cls(&mut self) -> Result<bool> {
         debug!("-> CLS");
 
-        self.function_call_prolog();
+        self.function_call_prolog()?;
 
-        let cls_addr = fn_extern::cls as unsafe extern "C" fn(state: *mut Chip8State) -> ();
-        self.x86.mov(rax, cls_addr as u64).unwrap();
-        self.x86.call(rax).unwrap();
+        self.call_extern(Helper::Cls)?;
 
-        self.function_call_epilog();
+        self.function_call_epilog()?;
 
-        self.increment_pc();
-        true
+        self.increment_pc()?;
+        Ok(true)
     }
 
-    pub fn ret(&mut self) -> bool {
+    pub fn ret(&mut self) -> Result<bool> {
         debug!("-> RET");
 
         let sp_addr = rdi + self.get_field_offset(Chip8Field::SP);
+        let pc_addr = rdi + self.get_field_offset(Chip8Field::PC);
+        let fault_addr = rdi + self.get_field_offset(Chip8Field::Fault);
         let stack_offset = self.get_field_offset(Chip8Field::Stack);
-        let pc_offset = self.get_field_offset(Chip8Field::PC);
+        let mut pop = self.x86.create_label();
+        let mut end = self.x86.create_label();
 
-        self.x86.mov(r8, stack_offset).unwrap();
-        self.x86.add(r8, rdi).unwrap();
-        self.x86.mov(r9, pc_offset).unwrap();
-        self.x86.add(r9, rdi).unwrap();
+        // report an underflow if the stack is empty, pc stays at this instruction
+        self.x86.mov(rax, qword_ptr(sp_addr))?;
+        self.x86.test(rax, rax)?;
+        self.x86.jnz(pop)?;
+        self.x86
+            .mov(qword_ptr(fault_addr), Fault::StackUnderflow as i32)?;
+        self.x86.jmp(end)?;
 
-        // calculate sp * INSTRUCTION_SIZE_BYTES
-        self.x86.mov(r10, INSTRUCTION_SIZE_BYTES).unwrap();
-        self.x86.mov(rax, qword_ptr(sp_addr)).unwrap();
-        self.x86.mul(r10).unwrap();
-        self.x86.add(r8, rax).unwrap();
+        // decrement sp
+        self.x86.set_label(&mut pop)?;
+        self.x86.dec(rax)?;
+        self.x86.mov(qword_ptr(sp_addr), rax)?;
 
-        // mov pc, ptr(stack_addr) + sp * INSTRUCTION_SIZE_BYTES
-        self.x86.mov(r10, qword_ptr(r8)).unwrap();
-        self.x86.mov(qword_ptr(r9), r10).unwrap();
+        // mov pc, [stack_addr + sp * QUAD_WORD]
+        self.x86
+            .mov(r8, qword_ptr(rdi + rax * Self::QUAD_WORD + stack_offset))?;
+        self.x86.mov(qword_ptr(pc_addr), r8)?;
 
-        // decrement sp
-        self.x86.dec(qword_ptr(sp_addr)).unwrap();
-        false
+        // the epilog of the block follows
+        self.x86.set_label(&mut end)?;
+        Ok(false)
     }
 
-    pub fn sys(&mut self, _: Nnn) -> bool {
+    pub fn sys(&mut self, _: Nnn) -> Result<bool> {
         debug!("-> SYS");
         // our jit is a modern jit, so we're ignoring this one
-        self.increment_pc();
-        false
+        self.increment_pc()?;
+        Ok(false)
     }
 
-    pub fn jp(&mut self, addr: Nnn) -> bool {
+    pub fn jp(&mut self, addr: Nnn) -> Result<bool> {
         debug!("-> JP L{:X}", addr.0);
 
         let pc_addr = rdi + self.get_field_offset(Chip8Field::PC);
-        self.x86.mov(r8, addr.0 as u64).unwrap();
-        self.x86.mov(qword_ptr(pc_addr), r8).unwrap();
-        false
+        self.x86.mov(r8, addr.0 as u64)?;
+        self.x86.mov(qword_ptr(pc_addr), r8)?;
+        Ok(false)
     }
 
-    pub fn call(&mut self, addr: Nnn) -> bool {
+    pub fn call(&mut self, addr: Nnn) -> Result<bool> {
         debug!("-> CALL L{:X}", addr.0);
 
         let sp_addr = rdi + self.get_field_offset(Chip8Field::SP);
         let pc_addr = rdi + self.get_field_offset(Chip8Field::PC);
+        let fault_addr = rdi + self.get_field_offset(Chip8Field::Fault);
         let stack_offset = self.get_field_offset(Chip8Field::Stack);
-
-        self.x86.mov(rax, qword_ptr(sp_addr)).unwrap();
-        self.x86.mov(r9, qword_ptr(pc_addr)).unwrap();
-        self.x86.mov(r10, stack_offset).unwrap();
-        self.x86.add(r10, rdi).unwrap();
-        self.x86.mov(r11, INSTRUCTION_SIZE_BYTES).unwrap();
+        let return_addr = self.addr + self.instruction_size(self.addr);
+        let stack_limit = self.quirks().stack_limit();
+        let mut push = self.x86.create_label();
+        let mut end = self.x86.create_label();
+
+        // report an overflow if the stack is full, pc stays at this instruction
+        self.x86.mov(rax, qword_ptr(sp_addr))?;
+        self.x86.cmp(rax, stack_limit as i32)?;
+        self.x86.jb(push)?;
+        self.x86
+            .mov(qword_ptr(fault_addr), Fault::StackOverflow as i32)?;
+        self.x86.jmp(end)?;
+
+        // mov [stack_addr + sp * QUAD_WORD], <address of the next instruction>
+        self.x86.set_label(&mut push)?;
+        self.x86.mov(r8, return_addr)?;
+        self.x86
+            .mov(qword_ptr(rdi + rax * Self::QUAD_WORD + stack_offset), r8)?;
 
         // increment stack pointer
-        self.x86.inc(rax).unwrap();
-        self.x86.mov(qword_ptr(sp_addr), rax).unwrap();
-        // rax =  sp * INSTRUCTION_SIZE_BYTES
-        self.x86.mul(r11).unwrap();
-        // stack_addr + (sp * INSTRUCTION_SIZE_BYTES)
-        self.x86.add(r10, rax).unwrap();
-
-        // move pc value to stack
-        self.x86.mov(qword_ptr(r10), r9).unwrap();
+        self.x86.inc(rax)?;
+        self.x86.mov(qword_ptr(sp_addr), rax)?;
 
         // set pc to `addr`
-        self.x86.mov(r8, u64::from(addr.0)).unwrap();
-        self.x86.mov(qword_ptr(pc_addr), r8).unwrap();
+        self.x86.mov(r8, u64::from(addr.0))?;
+        self.x86.mov(qword_ptr(pc_addr), r8)?;
 
-        false
+        // the epilog of the block follows
+        self.x86.set_label(&mut end)?;
+        Ok(false)
     }
 
-    pub fn se<T>(&mut self, vx: Vx, arg2: T) -> bool
+    pub fn se<T>(&mut self, vx: Vx, arg2: T) -> Result<bool>
     where
         Self: ArgSe<T>,
     {
-        <Self as ArgSe<T>>::se(self, vx, arg2);
-        self.increment_pc();
-        false
+        <Self as ArgSe<T>>::se(self, vx, arg2)?;
+        self.increment_pc()?;
+        Ok(false)
     }
 
-    pub fn sne<T>(&mut self, vx: Vx, arg2: T) -> bool
+    pub fn sne<T>(&mut self, vx: Vx, arg2: T) -> Result<bool>
     where
         Self: ArgSne<T>,
     {
-        <Self as ArgSne<T>>::sne(self, vx, arg2);
-        self.increment_pc();
-        false
+        <Self as ArgSne<T>>::sne(self, vx, arg2)?;
+        self.increment_pc()?;
+        Ok(false)
     }
 
-    pub fn ld<T>(&mut self, vx: Vx, arg2: T) -> bool
+    pub fn ld<T>(&mut self, vx: Vx, arg2: T) -> Result<bool>
     where
         Self: ArgLd<T>,
     {
-        <Self as ArgLd<T>>::ld(self, vx, arg2);
-        self.increment_pc();
-        true
+        <Self as ArgLd<T>>::ld(self, vx, arg2)?;
+        self.increment_pc()?;
+        Ok(true)
     }
 
-    pub fn add_kk(&mut self, vx: Vx, kk: Byte) -> bool {
+    pub fn add_kk(&mut self, vx: Vx, kk: Byte) -> Result<bool> {
         debug!("--> ADD_KK {:?}, {:#x}", vx, kk.0);
 
         let vx_addr = rdi + self.get_field_offset(Chip8Field::Reg(vx.0));
 
-        self.x86.mov(r8, u64::from(kk.0)).unwrap();
-        self.x86.mov(r9, qword_ptr(vx_addr)).unwrap();
-        self.x86.add(r9, r8).unwrap();
-        self.x86.and(r9, Chip8::REG_MAX_VALUE).unwrap();
-        self.x86.mov(qword_ptr(vx_addr), r9).unwrap();
+        self.x86.mov(r8, u64::from(kk.0))?;
+        self.x86.mov(r9, qword_ptr(vx_addr))?;
+        self.x86.add(r9, r8)?;
+        self.x86.and(r9, Chip8::REG_MAX_VALUE)?;
+        self.x86.mov(qword_ptr(vx_addr), r9)?;
 
-        self.increment_pc();
-        true
+        self.increment_pc()?;
+        Ok(true)
     }
 
-    pub fn add_y(&mut self, vx: Vx, vy: Vy) -> bool {
+    pub fn add_y(&mut self, vx: Vx, vy: Vy) -> Result<bool> {
         debug!("--> ADD_Y {:?} {:?}", vx, vy);
 
         let vx_addr = rdi + self.get_field_offset(Chip8Field::Reg(vx.0));
         let vy_addr = rdi + self.get_field_offset(Chip8Field::Reg(vy.0));
-        let vf_offset = self.get_field_offset(Chip8Field::Reg(0xf));
-
-        self.x86.mov(r8, qword_ptr(vx_addr)).unwrap();
-        self.x86.mov(r9, qword_ptr(vy_addr)).unwrap();
-        self.x86.mov(r10, vf_offset).unwrap();
-        self.x86.add(r10, rdi).unwrap();
+        let vf_addr = rdi + self.get_field_offset(Chip8Field::Reg(0xf));
 
         // add Vx, Vy
-        self.x86.add(r8, r9).unwrap();
+        self.x86.mov(r8, qword_ptr(vx_addr))?;
+        self.x86.add(r8, qword_ptr(vy_addr))?;
 
-        // set Vf
-        self.x86.setc(qword_ptr(r10)).unwrap();
+        // the carry is the ninth bit
+        self.x86.mov(r9, r8)?;
+        self.x86.shr(r9, 8u32)?;
 
-        // mask r8
-        self.x86.and(r8, Chip8::REG_MAX_VALUE).unwrap();
-        self.x86.mov(qword_ptr(vx_addr), r8).unwrap();
+        // mask r8 and store Vf last, in case Vx is Vf
+        self.x86.and(r8, Chip8::REG_MAX_VALUE)?;
+        self.x86.mov(qword_ptr(vx_addr), r8)?;
+        self.x86.mov(qword_ptr(vf_addr), r9)?;
 
-        self.increment_pc();
-        true
+        self.increment_pc()?;
+        Ok(true)
     }
 
-    pub fn or(&mut self, vx: Vx, vy: Vy) -> bool {
+    pub fn or(&mut self, vx: Vx, vy: Vy) -> Result<bool> {
         debug!("-> OR V{:X}, V{:X}", vx.0, vy.0);
 
         let vx_addr = rdi + self.get_field_offset(Chip8Field::Reg(vx.0));
         let vy_addr = rdi + self.get_field_offset(Chip8Field::Reg(vy.0));
 
         // do bitwise or
-        self.x86.mov(r8, qword_ptr(vx_addr)).unwrap();
-        self.x86.or(r8, qword_ptr(vy_addr)).unwrap();
-        self.x86.mov(qword_ptr(vx_addr), r8).unwrap();
+        self.x86.mov(r8, qword_ptr(vx_addr))?;
+        self.x86.or(r8, qword_ptr(vy_addr))?;
+        self.x86.mov(qword_ptr(vx_addr), r8)?;
 
-        self.increment_pc();
-        true
+        self.reset_vf()?;
+
+        self.increment_pc()?;
+        Ok(true)
     }
 
-    pub fn and(&mut self, vx: Vx, vy: Vy) -> bool {
-        debug!("-> AND V{:X}, {:X}", vx.0, vy.0);
+    pub fn and(&mut self, vx: Vx, vy: Vy) -> Result<bool> {
+        debug!("-> AND V{:X}, V{:X}", vx.0, vy.0);
 
         let vx_addr = rdi + self.get_field_offset(Chip8Field::Reg(vx.0));
         let vy_addr = rdi + self.get_field_offset(Chip8Field::Reg(vy.0));
 
         // do bitwise and
-        self.x86.mov(r8, qword_ptr(vx_addr)).unwrap();
-        self.x86.and(r8, qword_ptr(vy_addr)).unwrap();
-        self.x86.mov(qword_ptr(vx_addr), r8).unwrap();
+        self.x86.mov(r8, qword_ptr(vx_addr))?;
+        self.x86.and(r8, qword_ptr(vy_addr))?;
+        self.x86.mov(qword_ptr(vx_addr), r8)?;
+
+        self.reset_vf()?;
 
-        self.increment_pc();
-        true
+        self.increment_pc()?;
+        Ok(true)
     }
 
-    pub fn xor(&mut self, vx: Vx, vy: Vy) -> bool {
+    pub fn xor(&mut self, vx: Vx, vy: Vy) -> Result<bool> {
         debug!("-> XOR V{:X}, V{:X}", vx.0, vy.0);
 
         let vx_addr = rdi + self.get_field_offset(Chip8Field::Reg(vx.0));
         let vy_addr = rdi + self.get_field_offset(Chip8Field::Reg(vy.0));
 
-        // do bitwise and
-        self.x86.mov(r8, qword_ptr(vx_addr)).unwrap();
-        self.x86.xor(r8, qword_ptr(vy_addr)).unwrap();
-        self.x86.mov(qword_ptr(vx_addr), r8).unwrap();
+        // do bitwise xor
+        self.x86.mov(r8, qword_ptr(vx_addr))?;
+        self.x86.xor(r8, qword_ptr(vy_addr))?;
+        self.x86.mov(qword_ptr(vx_addr), r8)?;
+
+        self.reset_vf()?;
 
-        self.increment_pc();
-        true
+        self.increment_pc()?;
+        Ok(true)
     }
 
-    pub fn sub(&mut self, vx: Vx, vy: Vy) -> bool {
+    pub fn sub(&mut self, vx: Vx, vy: Vy) -> Result<bool> {
         debug!("-> SUB {:?}, {:?}", vx, vy);
 
         let vx_addr = rdi + self.get_field_offset(Chip8Field::Reg(vx.0));
         let vy_addr = rdi + self.get_field_offset(Chip8Field::Reg(vy.0));
-        let vf_offset = self.get_field_offset(Chip8Field::Reg(0xf));
+        let vf_addr = rdi + self.get_field_offset(Chip8Field::Reg(0xf));
+
+        // clear the register of Vf before the flags are set
+        self.x86.xor(r10, r10)?;
 
-        // sub
-        self.x86.mov(r8, qword_ptr(vx_addr)).unwrap();
-        self.x86.mov(r9, qword_ptr(vy_addr)).unwrap();
-        self.x86.sub(r8, r9).unwrap();
+        // sub Vx, Vy
+        self.x86.mov(r8, qword_ptr(vx_addr))?;
+        self.x86.sub(r8, qword_ptr(vy_addr))?;
 
-        // set Vf
-        self.x86.mov(r8, vf_offset).unwrap();
-        self.x86.add(r8, rdi).unwrap();
-        self.x86.setnc(qword_ptr(r8)).unwrap();
+        // Vf = NOT borrow
+        self.x86.setae(r10b)?;
 
-        self.increment_pc();
-        true
+        // mask r8 and store Vf last, in case Vx is Vf
+        self.x86.and(r8, Chip8::REG_MAX_VALUE)?;
+        self.x86.mov(qword_ptr(vx_addr), r8)?;
+        self.x86.mov(qword_ptr(vf_addr), r10)?;
+
+        self.increment_pc()?;
+        Ok(true)
     }
 
-    pub fn shr(&mut self, vx: Vx, _: Vy) -> bool {
-        debug!("-> SHR {:?}", vx);
+    pub fn shr(&mut self, vx: Vx, vy: Vy) -> Result<bool> {
+        debug!("-> SHR V{:X}, V{:X}", vx.0, vy.0);
 
+        let src = if self.quirks().shift_vy { vy.0 } else { vx.0 };
+        let src_addr = rdi + self.get_field_offset(Chip8Field::Reg(src));
         let vx_addr = rdi + self.get_field_offset(Chip8Field::Reg(vx.0));
         let vf_addr = rdi + self.get_field_offset(Chip8Field::Reg(0xf));
 
-        // set Vf
-        self.x86.mov(r8, qword_ptr(vx_addr)).unwrap();
-        self.x86.shr(r8, 1u32).unwrap();
-        self.x86.setb(qword_ptr(vf_addr)).unwrap();
+        // Vf = the bit which gets shifted out
+        self.x86.mov(r8, qword_ptr(src_addr))?;
+        self.x86.mov(r9, r8)?;
+        self.x86.and(r9, 1)?;
 
-        // save shr
-        self.x86.mov(qword_ptr(vx_addr), r8).unwrap();
+        // save shr and store Vf last, in case Vx is Vf
+        self.x86.shr(r8, 1u32)?;
+        self.x86.mov(qword_ptr(vx_addr), r8)?;
+        self.x86.mov(qword_ptr(vf_addr), r9)?;
 
-        self.increment_pc();
-        true
+        self.increment_pc()?;
+        Ok(true)
     }
 
-    pub fn subn(&mut self, vx: Vx, vy: Vy) -> bool {
+    pub fn subn(&mut self, vx: Vx, vy: Vy) -> Result<bool> {
         debug!("-> SUBN V{:X}, V{:X}", vx.0, vy.0);
 
         let vx_addr = rdi + self.get_field_offset(Chip8Field::Reg(vx.0));
         let vy_addr = rdi + self.get_field_offset(Chip8Field::Reg(vy.0));
-        let vf_offset = self.get_field_offset(Chip8Field::Reg(0xf));
+        let vf_addr = rdi + self.get_field_offset(Chip8Field::Reg(0xf));
 
-        // store vf_address
-        self.x86.mov(r10, vf_offset).unwrap();
-        self.x86.add(r10, rdi).unwrap();
+        // clear the register of Vf before the flags are set
+        self.x86.xor(r10, r10)?;
 
         // sub Vy, Vx
-        self.x86.mov(r8, qword_ptr(vx_addr)).unwrap();
-        self.x86.mov(r9, qword_ptr(vy_addr)).unwrap();
-        self.x86.sub(r9, r8).unwrap();
+        self.x86.mov(r8, qword_ptr(vy_addr))?;
+        self.x86.sub(r8, qword_ptr(vx_addr))?;
 
-        // set Vf
-        self.x86.setnc(qword_ptr(r10)).unwrap();
+        // Vf = NOT borrow
+        self.x86.setae(r10b)?;
 
-        self.x86.mov(qword_ptr(vx_addr), r9).unwrap();
+        // mask r8 and store Vf last, in case Vx is Vf
+        self.x86.and(r8, Chip8::REG_MAX_VALUE)?;
+        self.x86.mov(qword_ptr(vx_addr), r8)?;
+        self.x86.mov(qword_ptr(vf_addr), r10)?;
 
-        self.increment_pc();
-        true
+        self.increment_pc()?;
+        Ok(true)
     }
 
-    pub fn shl(&mut self, vx: Vx, _: Vy) -> bool {
-        debug!("-> SHL V{:X}", vx.0);
+    pub fn shl(&mut self, vx: Vx, vy: Vy) -> Result<bool> {
+        debug!("-> SHL V{:X}, V{:X}", vx.0, vy.0);
 
+        let src = if self.quirks().shift_vy { vy.0 } else { vx.0 };
+        let src_addr = rdi + self.get_field_offset(Chip8Field::Reg(src));
         let vx_addr = rdi + self.get_field_offset(Chip8Field::Reg(vx.0));
         let vf_addr = rdi + self.get_field_offset(Chip8Field::Reg(0xf));
 
-        // set Vf
-        self.x86.mov(r8, qword_ptr(vx_addr)).unwrap();
-        self.x86.shl(r8, 1u32).unwrap();
-        self.x86.setb(qword_ptr(vf_addr)).unwrap();
+        // Vf = the bit which gets shifted out
+        self.x86.mov(r8, qword_ptr(src_addr))?;
+        self.x86.mov(r9, r8)?;
+        self.x86.shr(r9, 7u32)?;
+        self.x86.and(r9, 1)?;
 
-        // mask and save
-        self.x86.and(r8, Chip8::REG_MAX_VALUE).unwrap();
-        self.x86.mov(qword_ptr(vx_addr), r8).unwrap();
+        // mask and save, store Vf last, in case Vx is Vf
+        self.x86.shl(r8, 1u32)?;
+        self.x86.and(r8, Chip8::REG_MAX_VALUE)?;
+        self.x86.mov(qword_ptr(vx_addr), r8)?;
+        self.x86.mov(qword_ptr(vf_addr), r9)?;
 
-        self.increment_pc();
-        true
+        self.increment_pc()?;
+        Ok(true)
     }
 
-    pub fn ld_i(&mut self, addr: Nnn) -> bool {
+    pub fn ld_i(&mut self, addr: Nnn) -> Result<bool> {
         debug!("-> LD [I], {:#X}", addr.0);
 
         let i_addr = rdi + self.get_field_offset(Chip8Field::I);
 
-        self.x86.mov(r8, u64::from(addr.0)).unwrap();
-        self.x86.mov(qword_ptr(i_addr), r8).unwrap();
+        self.x86.mov(r8, u64::from(addr.0))?;
+        self.x86.mov(qword_ptr(i_addr), r8)?;
 
-        self.increment_pc();
-        true
+        self.increment_pc()?;
+        Ok(true)
     }
 
-    pub fn jp_v0(&mut self, addr: Nnn) -> bool {
-        debug!("-> JP V0, {:#X}", addr.0);
+    pub fn jp_v0(&mut self, addr: Nnn) -> Result<bool> {
+        let reg = if self.quirks().jump_vx {
+            ((addr.0 >> 8) & 0xf) as u8
+        } else {
+            0
+        };
+        debug!("-> JP V{:X}, {:#X}", reg, addr.0);
 
-        let i_addr = rdi + self.get_field_offset(Chip8Field::I);
+        let reg_addr = rdi + self.get_field_offset(Chip8Field::Reg(reg));
+        let pc_addr = rdi + self.get_field_offset(Chip8Field::PC);
 
-        self.x86.mov(r8, u64::from(addr.0)).unwrap();
-        self.x86.mov(qword_ptr(i_addr), r8).unwrap();
+        self.x86.mov(r8, qword_ptr(reg_addr))?;
+        self.x86.add(r8, i32::from(addr.0))?;
+        self.x86.mov(qword_ptr(pc_addr), r8)?;
 
-        self.increment_pc();
-        true
+        Ok(false)
     }
 
-    pub fn rnd(&mut self, vx: Vx, kk: Byte) -> bool {
+    pub fn rnd(&mut self, vx: Vx, kk: Byte) -> Result<bool> {
         debug!("-> RND V{:X}, {:#x}", vx.0, kk.0);
 
         let vx_value = rdi + self.get_field_offset(Chip8Field::Reg(vx.0));
 
-        self.x86.mov(r8, kk.0 as u64).unwrap();
-        self.x86.rdrand(r9).unwrap();
-        self.x86.and(r8, r9).unwrap();
-        self.x86.mov(qword_ptr(vx_value), r8).unwrap();
+        self.x86.mov(r8, kk.0 as u64)?;
+        self.x86.rdrand(r9)?;
+        self.x86.and(r8, r9)?;
+        self.x86.mov(qword_ptr(vx_value), r8)?;
 
-        self.increment_pc();
-        true
+        self.increment_pc()?;
+        Ok(true)
     }
 
-    pub fn drw(&mut self, vx: Vx, vy: Vy, nibble: u64) -> bool {
+    pub fn drw(&mut self, vx: Vx, vy: Vy, nibble: u64) -> Result<bool> {
         debug!("-> DRW V{:X}, V{:X}, {:#x}", vx.0, vy.0, nibble);
 
-        self.function_call_prolog();
+        self.function_call_prolog()?;
 
-        self.x86.mov(rsi, u64::from(vx.0)).unwrap();
-        self.x86.mov(rdx, u64::from(vy.0)).unwrap();
-        self.x86.mov(rcx, u64::from(nibble)).unwrap();
+        self.x86.mov(rsi, u64::from(vx.0))?;
+        self.x86.mov(rdx, u64::from(vy.0))?;
+        self.x86.mov(rcx, nibble)?;
 
-        let drw_addr = fn_extern::drw
-            as unsafe extern "C" fn(state: *mut Chip8State, vx: u64, vy: u64, nibble: u64) -> ();
-        self.x86.call(drw_addr as u64).unwrap();
+        self.call_extern(Helper::Drw)?;
 
-        self.function_call_epilog();
+        self.function_call_epilog()?;
 
-        self.increment_pc();
-        true
+        self.increment_pc()?;
+        // the block has to end to be able to wait for the next frame
+        Ok(!self.quirks().display_wait)
     }
 
-    pub fn skp(&mut self, vx: Vx) -> bool {
+    pub fn skp(&mut self, vx: Vx) -> Result<bool> {
         debug!("-> SKP V{:X}", vx.0);
 
-        self.function_call_prolog();
+        self.function_call_prolog()?;
 
-        self.x86.mov(rsi, u64::from(vx.0)).unwrap();
+        self.x86.mov(rsi, u64::from(vx.0))?;
 
-        let skp_addr =
-            fn_extern::skp as unsafe extern "C" fn(state: *mut Chip8State, vx: u64) -> ();
-        self.x86.call(skp_addr as u64).unwrap();
+        self.call_extern(Helper::Skp)?;
 
-        self.function_call_epilog();
+        self.function_call_epilog()?;
 
-        self.increment_pc();
-        false
+        self.increment_pc()?;
+        Ok(false)
     }
 
-    pub fn sknp(&mut self, vx: Vx) -> bool {
+    pub fn sknp(&mut self, vx: Vx) -> Result<bool> {
         debug!("-> SKNP V{:X}", vx.0);
 
-        self.function_call_prolog();
+        self.function_call_prolog()?;
 
-        self.x86.mov(rsi, u64::from(vx.0)).unwrap();
+        self.x86.mov(rsi, u64::from(vx.0))?;
 
-        let sknp_addr =
-            fn_extern::sknp as unsafe extern "C" fn(state: *mut Chip8State, vx: u64) -> ();
-        self.x86.call(sknp_addr as u64).unwrap();
+        self.call_extern(Helper::Sknp)?;
 
-        self.function_call_epilog();
+        self.function_call_epilog()?;
 
-        self.increment_pc();
-        false
+        self.increment_pc()?;
+        Ok(false)
     }
 
-    pub fn ld_x_dt(&mut self, vx: Vx) -> bool {
+    pub fn ld_x_dt(&mut self, vx: Vx) -> Result<bool> {
         debug!("-> LD V{:X}, DT", vx.0);
 
         let vx_addr = rdi + self.get_field_offset(Chip8Field::Reg(vx.0));
         let delay_timer_addr = rdi + self.get_field_offset(Chip8Field::Delay);
 
-        self.x86.mov(r8, qword_ptr(delay_timer_addr)).unwrap();
-        self.x86.mov(qword_ptr(vx_addr), r8).unwrap();
+        self.x86.mov(r8, qword_ptr(delay_timer_addr))?;
+        self.x86.mov(qword_ptr(vx_addr), r8)?;
 
-        self.increment_pc();
-        true
+        self.increment_pc()?;
+        Ok(true)
     }
 
-    pub fn ld_k(&mut self, vx: Vx) -> bool {
+    pub fn ld_k(&mut self, vx: Vx) -> Result<bool> {
         debug!("-> LD V{:X}, K", vx.0);
 
-        self.function_call_prolog();
+        self.function_call_prolog()?;
 
-        self.x86.mov(rsi, u64::from(vx.0)).unwrap();
+        self.x86.mov(rsi, u64::from(vx.0))?;
 
-        let ld_k_addr =
-            fn_extern::ld_k as unsafe extern "C" fn(state: *mut Chip8State, vx: u64) -> ();
-        self.x86.call(ld_k_addr as u64).unwrap();
+        self.call_extern(Helper::LdK)?;
 
-        self.function_call_epilog();
+        self.function_call_epilog()?;
 
-        self.increment_pc();
-        true
+        // ld_k sets pc itself, it stays here while no key is pressed
+        Ok(false)
     }
 
-    pub fn ld_dt_x(&mut self, vx: Vx) -> bool {
+    pub fn ld_dt_x(&mut self, vx: Vx) -> Result<bool> {
         debug!("-> LD DT, V{:X}", vx.0);
 
         let vx_addr = rdi + self.get_field_offset(Chip8Field::Reg(vx.0));
         let delay_timer_addr = rdi + self.get_field_offset(Chip8Field::Delay);
 
-        self.x86.mov(r8, qword_ptr(vx_addr)).unwrap();
-        self.x86.mov(qword_ptr(delay_timer_addr), r8).unwrap();
+        self.x86.mov(r8, qword_ptr(vx_addr))?;
+        self.x86.mov(qword_ptr(delay_timer_addr), r8)?;
 
-        self.increment_pc();
-        true
+        self.increment_pc()?;
+        Ok(true)
     }
 
-    pub fn ld_st(&mut self, vx: Vx) -> bool {
+    pub fn ld_st(&mut self, vx: Vx) -> Result<bool> {
         debug!("-> LD ST, V{:X}", vx.0);
 
         let vx_addr = rdi + self.get_field_offset(Chip8Field::Reg(vx.0));
         let sound_addr = rdi + self.get_field_offset(Chip8Field::Sound);
 
-        self.x86.mov(r8, qword_ptr(vx_addr)).unwrap();
-        self.x86.mov(qword_ptr(sound_addr), r8).unwrap();
+        self.x86.mov(r8, qword_ptr(vx_addr))?;
+        self.x86.mov(qword_ptr(sound_addr), r8)?;
 
-        self.increment_pc();
-        true
+        self.increment_pc()?;
+        Ok(true)
     }
 
-    pub fn add_i(&mut self, vx: Vx) -> bool {
+    pub fn add_i(&mut self, vx: Vx) -> Result<bool> {
         debug!("-> ADD I, V{:X}", vx.0);
 
         let i_addr = rdi + self.get_field_offset(Chip8Field::I);
         let vx_addr = rdi + self.get_field_offset(Chip8Field::Reg(vx.0));
 
-        self.x86.mov(r8, qword_ptr(vx_addr)).unwrap();
-        self.x86.mov(r9, qword_ptr(i_addr)).unwrap();
-        self.x86.add(r9, r8).unwrap();
-        self.x86.and(r9, Chip8::REG_MAX_VALUE).unwrap();
-        self.x86.mov(qword_ptr(i_addr), r9).unwrap();
+        self.x86.mov(r8, qword_ptr(vx_addr))?;
+        self.x86.mov(r9, qword_ptr(i_addr))?;
+        self.x86.add(r9, r8)?;
+        self.x86.and(r9, Chip8::ADDR_MASK as i32)?;
+        self.x86.mov(qword_ptr(i_addr), r9)?;
 
-        self.increment_pc();
-        true
+        self.increment_pc()?;
+        Ok(true)
     }
 
-    pub fn ld_f(&mut self, vx: Vx) -> bool {
+    pub fn ld_f(&mut self, vx: Vx) -> Result<bool> {
         debug!("-> LD F, V{:X}", vx.0);
 
-        self.function_call_prolog();
+        self.function_call_prolog()?;
 
-        self.x86.mov(rsi, u64::from(vx.0)).unwrap();
+        self.x86.mov(rsi, u64::from(vx.0))?;
 
-        let ld_f_addr =
-            fn_extern::ld_f as unsafe extern "C" fn(state: *mut Chip8State, vx: u64) -> ();
-        self.x86.call(ld_f_addr as u64).unwrap();
+        self.call_extern(Helper::LdF)?;
 
-        self.function_call_epilog();
+        self.function_call_epilog()?;
 
-        self.increment_pc();
-        true
+        self.increment_pc()?;
+        Ok(true)
     }
 
-    pub fn ld_b(&mut self, vx: Vx) -> bool {
+    pub fn ld_b(&mut self, vx: Vx) -> Result<bool> {
         debug!("-> LD B, V{:X}", vx.0);
 
-        self.function_call_prolog();
+        self.function_call_prolog()?;
 
-        self.x86.mov(rsi, u64::from(vx.0)).unwrap();
+        self.x86.mov(rsi, u64::from(vx.0))?;
 
-        let ld_b_addr =
-            fn_extern::ld_b as unsafe extern "C" fn(state: *mut Chip8State, vx: u64) -> ();
-        self.x86.call(ld_b_addr as u64).unwrap();
+        self.call_extern(Helper::LdB)?;
 
-        self.function_call_epilog();
+        self.function_call_epilog()?;
 
-        self.increment_pc();
-        true
+        self.increment_pc()?;
+        Ok(true)
     }
 
-    pub fn ld_i_x(&mut self, vx: Vx) -> bool {
+    pub fn ld_i_x(&mut self, vx: Vx) -> Result<bool> {
         debug!("-> LD [I], V{:X}", vx.0);
 
-        let vx_addr = rdi + self.get_field_offset(Chip8Field::Reg(vx.0));
-        let v0_addr = rdi + self.get_field_offset(Chip8Field::Reg(0));
-        let i_addr = rdi + self.get_field_offset(Chip8Field::I);
+        self.function_call_prolog()?;
 
-        self.x86.mov(r8, v0_addr).unwrap(); // source ptr
-        self.x86.mov(r9, i_addr).unwrap(); // destination ptr
-        self.x86.mov(r10, vx_addr).unwrap(); // end-addr
+        self.x86.mov(rsi, 0u64)?;
+        self.x86.mov(rdx, u64::from(vx.0))?;
 
-        // -- while loop --
-        self.x86.anonymous_label().unwrap();
-        // mov <reg>, <I>
-        self.x86.mov(r11, qword_ptr(r8)).unwrap();
-        self.x86.mov(qword_ptr(r9), r11).unwrap();
+        self.call_extern(Helper::LdIRange)?;
 
-        // increment ptr
-        self.x86.inc(r8).unwrap();
-        self.x86.inc(r9).unwrap();
+        self.function_call_epilog()?;
 
-        // while <source ptr> <= end-addr
-        self.x86.cmp(r8, r10).unwrap();
-        let jump_addr = self.x86.bwd().unwrap();
-        self.x86.jle(jump_addr).unwrap();
-
-        self.increment_pc();
-        true
+        self.increment_i(vx)?;
+        self.increment_pc()?;
+        Ok(true)
     }
 
-    pub fn ld_x_i(&mut self, vx: Vx) -> bool {
+    pub fn ld_x_i(&mut self, vx: Vx) -> Result<bool> {
         debug!("-> LD V{:X}, [I]", vx.0);
 
+        self.function_call_prolog()?;
+
+        self.x86.mov(rsi, 0u64)?;
+        self.x86.mov(rdx, u64::from(vx.0))?;
+
+        self.call_extern(Helper::LdRangeI)?;
+
+        self.function_call_epilog()?;
+
+        self.increment_i(vx)?;
+        self.increment_pc()?;
+        Ok(true)
+    }
+
+    pub fn scd(&mut self, n: u8) -> Result<bool> {
+        debug!("-> SCD {:#x}", n);
+
+        self.function_call_prolog()?;
+
+        self.x86.mov(rsi, u64::from(n))?;
+
+        self.call_extern(Helper::Scd)?;
+
+        self.function_call_epilog()?;
+
+        self.increment_pc()?;
+        Ok(true)
+    }
+
+    pub fn scu(&mut self, n: u8) -> Result<bool> {
+        debug!("-> SCU {:#x}", n);
+
+        self.function_call_prolog()?;
+
+        self.x86.mov(rsi, u64::from(n))?;
+
+        self.call_extern(Helper::Scu)?;
+
+        self.function_call_epilog()?;
+
+        self.increment_pc()?;
+        Ok(true)
+    }
+
+    pub fn scr(&mut self) -> Result<bool> {
+        debug!("-> SCR");
+
+        self.function_call_prolog()?;
+
+        self.call_extern(Helper::Scr)?;
+
+        self.function_call_epilog()?;
+
+        self.increment_pc()?;
+        Ok(true)
+    }
+
+    pub fn scl(&mut self) -> Result<bool> {
+        debug!("-> SCL");
+
+        self.function_call_prolog()?;
+
+        self.call_extern(Helper::Scl)?;
+
+        self.function_call_epilog()?;
+
+        self.increment_pc()?;
+        Ok(true)
+    }
+
+    pub fn exit(&mut self) -> Result<bool> {
+        debug!("-> EXIT");
+
+        self.function_call_prolog()?;
+
+        self.call_extern(Helper::Exit)?;
+
+        self.function_call_epilog()?;
+
+        self.increment_pc()?;
+        Ok(false)
+    }
+
+    pub fn low(&mut self) -> Result<bool> {
+        debug!("-> LOW");
+
+        self.function_call_prolog()?;
+
+        self.call_extern(Helper::Low)?;
+
+        self.function_call_epilog()?;
+
+        self.increment_pc()?;
+        Ok(true)
+    }
+
+    pub fn high(&mut self) -> Result<bool> {
+        debug!("-> HIGH");
+
+        self.function_call_prolog()?;
+
+        self.call_extern(Helper::High)?;
+
+        self.function_call_epilog()?;
+
+        self.increment_pc()?;
+        Ok(true)
+    }
+
+    pub fn ld_i_range(&mut self, vx: Vx, vy: Vy) -> Result<bool> {
+        debug!("-> LD [I], V{:X}-V{:X}", vx.0, vy.0);
+
+        self.function_call_prolog()?;
+
+        self.x86.mov(rsi, u64::from(vx.0))?;
+        self.x86.mov(rdx, u64::from(vy.0))?;
+
+        self.call_extern(Helper::LdIRange)?;
+
+        self.function_call_epilog()?;
+
+        self.increment_pc()?;
+        Ok(true)
+    }
+
+    pub fn ld_range_i(&mut self, vx: Vx, vy: Vy) -> Result<bool> {
+        debug!("-> LD V{:X}-V{:X}, [I]", vx.0, vy.0);
+
+        self.function_call_prolog()?;
+
+        self.x86.mov(rsi, u64::from(vx.0))?;
+        self.x86.mov(rdx, u64::from(vy.0))?;
+
+        self.call_extern(Helper::LdRangeI)?;
+
+        self.function_call_epilog()?;
+
+        self.increment_pc()?;
+        Ok(true)
+    }
+
+    pub fn plane(&mut self, n: u8) -> Result<bool> {
+        debug!("-> PLANE {:#x}", n);
+
+        let planes_addr = rdi + self.get_field_offset(Chip8Field::Planes);
+
+        self.x86.mov(r8, u64::from(n & 0x3))?;
+        self.x86.mov(qword_ptr(planes_addr), r8)?;
+
+        self.increment_pc()?;
+        Ok(true)
+    }
+
+    pub fn audio(&mut self) -> Result<bool> {
+        debug!("-> AUDIO");
+
+        self.function_call_prolog()?;
+
+        self.call_extern(Helper::Audio)?;
+
+        self.function_call_epilog()?;
+
+        self.increment_pc()?;
+        Ok(true)
+    }
+
+    pub fn ld_hf(&mut self, vx: Vx) -> Result<bool> {
+        debug!("-> LD HF, V{:X}", vx.0);
+
+        self.function_call_prolog()?;
+
+        self.x86.mov(rsi, u64::from(vx.0))?;
+
+        self.call_extern(Helper::LdHf)?;
+
+        self.function_call_epilog()?;
+
+        self.increment_pc()?;
+        Ok(true)
+    }
+
+    pub fn pitch(&mut self, vx: Vx) -> Result<bool> {
+        debug!("-> PITCH V{:X}", vx.0);
+
         let vx_addr = rdi + self.get_field_offset(Chip8Field::Reg(vx.0));
-        let v0_addr = rdi + self.get_field_offset(Chip8Field::Reg(0));
-        let i_addr = rdi + self.get_field_offset(Chip8Field::I);
+        let pitch_addr = rdi + self.get_field_offset(Chip8Field::Pitch);
+
+        self.x86.mov(r8, qword_ptr(vx_addr))?;
+        self.x86.mov(qword_ptr(pitch_addr), r8)?;
+
+        self.increment_pc()?;
+        Ok(true)
+    }
+
+    pub fn ld_r_x(&mut self, vx: Vx) -> Result<bool> {
+        debug!("-> LD R, V{:X}", vx.0);
+
+        self.function_call_prolog()?;
+
+        self.x86.mov(rsi, u64::from(vx.0))?;
+
+        self.call_extern(Helper::LdRX)?;
+
+        self.function_call_epilog()?;
+
+        self.increment_pc()?;
+        Ok(true)
+    }
+
+    pub fn ld_x_r(&mut self, vx: Vx) -> Result<bool> {
+        debug!("-> LD V{:X}, R", vx.0);
 
-        self.x86.mov(r8, i_addr).unwrap(); // source ptr
-        self.x86.mov(r9, v0_addr).unwrap(); // destination ptr
-        self.x86.mov(r10, vx_addr).unwrap(); // end-addr
+        self.function_call_prolog()?;
 
-        // -- while loop --
-        self.x86.anonymous_label().unwrap();
-        // mov <reg>, <I>
-        self.x86.mov(r11, qword_ptr(r8)).unwrap();
-        self.x86.mov(qword_ptr(r9), r11).unwrap();
+        self.x86.mov(rsi, u64::from(vx.0))?;
 
-        // increment ptr
-        self.x86.inc(r8).unwrap();
-        self.x86.inc(r9).unwrap();
+        self.call_extern(Helper::LdXR)?;
 
-        // while <dest-ptr> <= <end-addr>
-        self.x86.cmp(r9, r10).unwrap();
-        let jump_addr = self.x86.bwd().unwrap();
-        self.x86.jle(jump_addr).unwrap();
+        self.function_call_epilog()?;
 
-        self.increment_pc();
-        true
+        self.increment_pc()?;
+        Ok(true)
     }
 }
@@ -1,146 +1,151 @@
-use crate::chip8::{Chip8Field, INSTRUCTION_SIZE_BYTES};
+use crate::chip8::Chip8Field;
+use crate::error::Result;
 
 use super::{
     fn_traits::{ArgLd, ArgSe, ArgSne},
-    Vx, Vy, JIT, Byte,
+    Byte, Vx, Vy, JIT,
 };
 
 use iced_x86::code_asm::*;
 use log::debug;
 
-impl ArgSe<Byte> for JIT {
-    fn se(&mut self, vx: Vx, arg2: Byte) -> bool {
+impl ArgSe<Byte> for JIT<'_> {
+    fn se(&mut self, vx: Vx, arg2: Byte) -> Result<bool> {
         debug!("--> SE V{:X}, {:#X}", vx.0, arg2.0);
         let vx_addr = rdi + self.get_field_offset(Chip8Field::Reg(vx.0));
         let pc_addr = rdi + self.get_field_offset(Chip8Field::PC);
 
-        self.x86.mov(r8, qword_ptr(pc_addr)).unwrap();
-        // prepare `pc + 2`
-        self.x86.mov(r11, INSTRUCTION_SIZE_BYTES).unwrap();
-        self.x86.mov(r9, qword_ptr(pc_addr)).unwrap();
-        self.x86.add(r9, r11).unwrap();
+        self.x86.mov(r8, qword_ptr(pc_addr))?;
+        // prepare `pc + <size of next instruction>`
+        let skip_size = self.skip_size();
+        self.x86.mov(r11, skip_size)?;
+        self.x86.mov(r9, qword_ptr(pc_addr))?;
+        self.x86.add(r9, r11)?;
 
         // cmp vx, kk
-        self.x86.mov(r11, u64::from(arg2.0)).unwrap();
-        self.x86.mov(r10, qword_ptr(vx_addr)).unwrap();
-        self.x86.cmp(r10, r11).unwrap();
+        self.x86.mov(r11, u64::from(arg2.0))?;
+        self.x86.mov(r10, qword_ptr(vx_addr))?;
+        self.x86.cmp(r10, r11)?;
 
         // set pc if vx == kk (update r8 if needed)
-        self.x86.cmove(r8, r9).unwrap();
+        self.x86.cmove(r8, r9)?;
 
-        self.x86.mov(qword_ptr(pc_addr), r8).unwrap();
+        self.x86.mov(qword_ptr(pc_addr), r8)?;
 
-        false
+        Ok(false)
     }
 }
 
-impl ArgSe<Vy> for JIT {
-    fn se(&mut self, vx: Vx, arg2: Vy) -> bool {
+impl ArgSe<Vy> for JIT<'_> {
+    fn se(&mut self, vx: Vx, arg2: Vy) -> Result<bool> {
         debug!("--> SE V{:X}, V{:X}", vx.0, arg2.0);
         let vx_addr = rdi + self.get_field_offset(Chip8Field::Reg(vx.0));
         let vy_addr = rdi + self.get_field_offset(Chip8Field::Reg(arg2.0));
         let pc_addr = rdi + self.get_field_offset(Chip8Field::PC);
 
         // store vx and vy in registers
-        self.x86.mov(r8, qword_ptr(vx_addr)).unwrap();
-        self.x86.mov(r9, qword_ptr(vy_addr)).unwrap();
+        self.x86.mov(r8, qword_ptr(vx_addr))?;
+        self.x86.mov(r9, qword_ptr(vy_addr))?;
 
-        self.x86.mov(r10, qword_ptr(pc_addr)).unwrap();
-        // prepare `pc + 2`
-        self.x86.mov(r12, INSTRUCTION_SIZE_BYTES).unwrap();
-        self.x86.mov(r11, qword_ptr(pc_addr)).unwrap();
-        self.x86.add(r11, r12).unwrap();
+        self.x86.mov(r10, qword_ptr(pc_addr))?;
+        // prepare `pc + <size of next instruction>`
+        let skip_size = self.skip_size();
+        self.x86.mov(rax, skip_size)?;
+        self.x86.mov(r11, qword_ptr(pc_addr))?;
+        self.x86.add(r11, rax)?;
 
         // cmp vx, vy
-        self.x86.cmp(r8, r9).unwrap();
+        self.x86.cmp(r8, r9)?;
 
         // set pc if vx == kk (update r10 if needed)
-        self.x86.cmove(r10, r11).unwrap();
+        self.x86.cmove(r10, r11)?;
 
-        self.x86.mov(qword_ptr(pc_addr), r10).unwrap();
+        self.x86.mov(qword_ptr(pc_addr), r10)?;
 
-        false
+        Ok(false)
     }
 }
 
-impl ArgSne<Byte> for JIT {
-    fn sne(&mut self, vx: Vx, arg2: Byte) -> bool {
+impl ArgSne<Byte> for JIT<'_> {
+    fn sne(&mut self, vx: Vx, arg2: Byte) -> Result<bool> {
         debug!("--> SNE V{:X}, {:#X}", vx.0, arg2.0);
         let vx_addr = rdi + self.get_field_offset(Chip8Field::Reg(vx.0));
         let pc_addr = rdi + self.get_field_offset(Chip8Field::PC);
 
-        self.x86.mov(r8, qword_ptr(pc_addr)).unwrap();
-        // prepare `pc + 2`
-        self.x86.mov(r11, INSTRUCTION_SIZE_BYTES).unwrap();
-        self.x86.mov(r9, qword_ptr(pc_addr)).unwrap();
-        self.x86.add(r9, r11).unwrap();
+        self.x86.mov(r8, qword_ptr(pc_addr))?;
+        // prepare `pc + <size of next instruction>`
+        let skip_size = self.skip_size();
+        self.x86.mov(r11, skip_size)?;
+        self.x86.mov(r9, qword_ptr(pc_addr))?;
+        self.x86.add(r9, r11)?;
 
         // cmp vx, kk
-        self.x86.mov(r11, u64::from(arg2.0)).unwrap();
-        self.x86.mov(r10, qword_ptr(vx_addr)).unwrap();
-        self.x86.cmp(r10, r11).unwrap();
+        self.x86.mov(r11, u64::from(arg2.0))?;
+        self.x86.mov(r10, qword_ptr(vx_addr))?;
+        self.x86.cmp(r10, r11)?;
 
         // set pc if vx != kk (update r8 if needed)
-        self.x86.cmovne(r8, r9).unwrap();
+        self.x86.cmovne(r8, r9)?;
 
-        self.x86.mov(qword_ptr(pc_addr), r8).unwrap();
+        self.x86.mov(qword_ptr(pc_addr), r8)?;
 
-        false
+        Ok(false)
     }
 }
 
-impl ArgSne<Vy> for JIT {
+impl ArgSne<Vy> for JIT<'_> {
     // IDEA: r8most the same as `se` maybe putting the same lines together.unwrap()
-    fn sne(&mut self, vx: Vx, arg2: Vy) -> bool {
+    fn sne(&mut self, vx: Vx, arg2: Vy) -> Result<bool> {
         debug!("--> SNE V{:X}, V{:X}", vx.0, arg2.0);
         let vx_addr = rdi + self.get_field_offset(Chip8Field::Reg(vx.0));
         let vy_addr = rdi + self.get_field_offset(Chip8Field::Reg(arg2.0));
         let pc_addr = rdi + self.get_field_offset(Chip8Field::PC);
 
         // store vx and vy in registers
-        self.x86.mov(r8, qword_ptr(vx_addr)).unwrap();
-        self.x86.mov(r9, qword_ptr(vy_addr)).unwrap();
+        self.x86.mov(r8, qword_ptr(vx_addr))?;
+        self.x86.mov(r9, qword_ptr(vy_addr))?;
 
-        self.x86.mov(r10, qword_ptr(pc_addr)).unwrap();
+        self.x86.mov(r10, qword_ptr(pc_addr))?;
 
-        // prepare `pc + 2`
-        self.x86.mov(r12, INSTRUCTION_SIZE_BYTES).unwrap();
-        self.x86.mov(r11, qword_ptr(pc_addr)).unwrap();
-        self.x86.add(r11, r12).unwrap();
+        // prepare `pc + <size of next instruction>`
+        let skip_size = self.skip_size();
+        self.x86.mov(rax, skip_size)?;
+        self.x86.mov(r11, qword_ptr(pc_addr))?;
+        self.x86.add(r11, rax)?;
 
         // cmp vx, vy
-        self.x86.cmp(r8, r9).unwrap();
+        self.x86.cmp(r8, r9)?;
 
         // set pc if vx != kk (update r10 if needed)
-        self.x86.cmovne(r10, r11).unwrap();
+        self.x86.cmovne(r10, r11)?;
 
-        self.x86.mov(qword_ptr(pc_addr), r10).unwrap();
+        self.x86.mov(qword_ptr(pc_addr), r10)?;
 
-        false
+        Ok(false)
     }
 }
 
-impl ArgLd<Byte> for JIT {
-    fn ld(&mut self, vx: Vx, arg2: Byte) -> bool {
+impl ArgLd<Byte> for JIT<'_> {
+    fn ld(&mut self, vx: Vx, arg2: Byte) -> Result<bool> {
         debug!("--> LD V{:X}, {:#X}", vx.0, arg2.0);
         let vx_addr = rdi + self.get_field_offset(Chip8Field::Reg(vx.0));
 
-        self.x86.mov(r8, u64::from(arg2.0)).unwrap();
-        self.x86.mov(qword_ptr(vx_addr), r8).unwrap();
+        self.x86.mov(r8, u64::from(arg2.0))?;
+        self.x86.mov(qword_ptr(vx_addr), r8)?;
 
-        true
+        Ok(true)
     }
 }
 
-impl ArgLd<Vy> for JIT {
-    fn ld(&mut self, vx: Vx, arg2: Vy) -> bool {
+impl ArgLd<Vy> for JIT<'_> {
+    fn ld(&mut self, vx: Vx, arg2: Vy) -> Result<bool> {
         debug!("--> LD V{:X}, {:#x}", vx.0, arg2.0);
         let vx_addr = rdi + self.get_field_offset(Chip8Field::Reg(vx.0));
         let vy_addr = rdi + self.get_field_offset(Chip8Field::Reg(arg2.0));
 
-        self.x86.mov(r8, qword_ptr(vy_addr)).unwrap();
-        self.x86.mov(qword_ptr(vx_addr), r8).unwrap();
+        self.x86.mov(r8, qword_ptr(vy_addr))?;
+        self.x86.mov(qword_ptr(vx_addr), r8)?;
 
-        true
+        Ok(true)
     }
 }
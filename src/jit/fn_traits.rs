@@ -1,13 +1,14 @@
 use super::Vx;
+use crate::error::Result;
 
 pub trait ArgSe<T> {
-    fn se(&mut self, vx: Vx, arg2: T) -> bool;
+    fn se(&mut self, vx: Vx, arg2: T) -> Result<bool>;
 }
 
 pub trait ArgSne<T> {
-    fn sne(&mut self, vx: Vx, arg2: T) -> bool;
+    fn sne(&mut self, vx: Vx, arg2: T) -> Result<bool>;
 }
 
 pub trait ArgLd<T> {
-    fn ld(&mut self, vx: Vx, arg2: T) -> bool;
+    fn ld(&mut self, vx: Vx, arg2: T) -> Result<bool>;
 }
@@ -1,22 +1,24 @@
-mod fn_extern;
+pub(crate) mod fn_extern;
 mod fn_implementation;
 mod fn_trait_impl;
 mod fn_traits;
 mod frames;
 
+pub use fn_extern::Helper;
 use frames::StackFrame;
 use log::debug;
 
-use std::cell::RefCell;
 use std::convert::From;
-use std::rc::Rc;
 
+use crate::arena::CodeArena;
 use crate::cache::CompileBlock;
-use crate::chip8::{Chip8Field, Chip8State, INSTRUCTION_SIZE_BYTES};
+use crate::chip8::{self, Chip8Field, Chip8State, INSTRUCTION_SIZE_BYTES};
+use crate::error::{Error, Result};
+use crate::instruction::Instruction;
+use crate::quirks::Quirks;
 use crate::Addr;
 
 use iced_x86::code_asm::CodeAssembler;
-use memmap2::MmapMut;
 
 #[repr(C)]
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -34,169 +36,184 @@ pub struct Byte(pub u8);
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Nnn(pub u16);
 
-pub fn compile(state: Rc<RefCell<Chip8State>>) -> CompileBlock {
-    let mut jit = JIT::new(state);
+/// Compiles the block which starts at the pc of `state`. The state is only read, the compiled code
+/// gets it passed when it's executed.
+pub fn compile(state: &Chip8State, arena: &mut CodeArena) -> Result<CompileBlock> {
+    let mut jit = JIT::new(state)?;
 
-    jit.compile()
+    jit.compile(arena)
 }
 
 pub trait Frame {
-    fn prolog(&self, jit: &mut JIT);
+    fn prolog(&self, jit: &mut JIT<'_>) -> Result<()>;
 
-    fn epilog(&self, jit: &mut JIT);
+    fn epilog(&self, jit: &mut JIT<'_>) -> Result<()>;
 }
 
 #[repr(C)]
-pub struct JIT {
+pub struct JIT<'a> {
     start_pc: u64,
-    pub chip_state: Rc<RefCell<Chip8State>>,
+    /// The amount of recompiled instructions.
+    instructions: u64,
+    /// The address of the instruction which is currently recompiled.
+    addr: Addr,
+    /// The memory from which the instructions are read.
+    mem: &'a [u8],
+    quirks: Quirks,
     pub x86: CodeAssembler,
 }
 
-impl JIT {
+impl<'a> JIT<'a> {
     pub const QUAD_WORD: i32 = 8;
 
     const BITNESS: u32 = 64;
     const STEPS: [&'static dyn Frame; 1] = [&StackFrame as &dyn Frame];
 
-    fn new(chip_state: Rc<RefCell<Chip8State>>) -> Self {
-        let start_pc = chip_state.borrow().pc;
-        Self {
-            start_pc,
-            chip_state,
-            x86: CodeAssembler::new(Self::BITNESS).unwrap(),
-        }
+    fn new(state: &'a Chip8State) -> Result<Self> {
+        Ok(Self {
+            start_pc: state.pc,
+            instructions: 0,
+            addr: state.pc,
+            mem: &state.mem,
+            quirks: state.quirks,
+            x86: CodeAssembler::new(Self::BITNESS)?,
+        })
     }
 
-    fn compile(&mut self) -> CompileBlock {
-        self.prolog();
+    fn compile(&mut self, arena: &mut CodeArena) -> Result<CompileBlock> {
+        self.prolog()?;
 
-        self.recompile_chip8();
+        self.recompile_chip8()?;
 
-        self.epilog();
+        self.epilog()?;
 
         debug!("Finished compiled block!");
-        self.get_compiled_block()
+        self.get_compiled_block(arena)
     }
 
-    fn get_compiled_block(&mut self) -> CompileBlock {
-        let pc = self.chip_state.borrow().pc;
-        let bytes = self.x86.assemble(u64::from(pc)).unwrap();
-        let mut code = MmapMut::map_anon(bytes.len()).unwrap();
-        code.copy_from_slice(&bytes);
-        let code = code.make_exec().unwrap();
+    fn get_compiled_block(&mut self, arena: &mut CodeArena) -> Result<CompileBlock> {
+        let bytes = self.x86.assemble(self.start_pc)?;
+        let end_addr = self.addr + self.instruction_size(self.addr);
 
-        CompileBlock {
-            code,
-            start_addr: self.start_pc,
-        }
+        CompileBlock::new(arena, &bytes, self.start_pc..end_addr, self.instructions)
     }
 
-    fn prolog(&mut self) {
+    fn prolog(&mut self) -> Result<()> {
         for step in Self::STEPS.into_iter() {
-            step.prolog(self);
+            step.prolog(self)?;
         }
+
+        Ok(())
     }
 
-    fn epilog(&mut self) {
+    fn epilog(&mut self) -> Result<()> {
         for step in Self::STEPS.into_iter().rev() {
-            step.epilog(self);
+            step.epilog(self)?;
         }
+
+        Ok(())
     }
 
-    fn recompile_chip8(&mut self) {
-        let mut pc: Addr = self.chip_state.borrow().pc;
+    fn recompile_chip8(&mut self) -> Result<()> {
+        let mut pc: Addr = self.start_pc;
 
-        while self.compile_next_instruction(pc) {
+        while self.compile_next_instruction(pc)? {
             debug!("Recompiling instruction next at {:#x}", pc);
-            pc += INSTRUCTION_SIZE_BYTES;
+            pc += self.instruction_size(pc);
         }
+
+        Ok(())
     }
 
-    fn compile_next_instruction(&mut self, addr: Addr) -> bool {
-        let start_addr = addr;
-        let end_addr = start_addr + INSTRUCTION_SIZE_BYTES;
-        let mem = self.chip_state.borrow().mem;
+    fn compile_next_instruction(&mut self, addr: Addr) -> Result<bool> {
+        self.addr = addr;
+        self.instructions += 1;
 
-        let slice: [u8; 2] = mem[start_addr as usize..end_addr as usize]
-            .try_into()
-            .unwrap();
-        let wordbyte = u16::from_be_bytes(slice);
+        match Instruction::decode(self.mem, addr) {
+            Some(instruction) => self.compile_instruction(instruction),
+            None => Err(Error::UnknownInstruction {
+                addr,
+                instruction: self.read_word(addr),
+            }),
+        }
+    }
+
+    fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    fn read_word(&self, addr: Addr) -> u16 {
+        chip8::read_word(self.mem, addr)
+    }
+
+    fn instruction_size(&self, addr: Addr) -> u64 {
+        chip8::instruction_size(self.mem, addr)
+    }
 
-        self.compile_instruction(wordbyte)
+    /// Returns the amount of bytes which a skip instruction at the current address has to skip.
+    fn skip_size(&self) -> u64 {
+        self.instruction_size(self.addr + INSTRUCTION_SIZE_BYTES)
     }
 
-    fn compile_instruction(&mut self, instruction: u16) -> bool {
-        debug!("Recompiling '{:#x}'", instruction);
-        let nibbles: [u8; 4] = [
-            u8::try_from((instruction & 0xf000) >> 12).unwrap(),
-            u8::try_from((instruction & 0x0f00) >> 8).unwrap(),
-            u8::try_from((instruction & 0x00f0) >> 4).unwrap(),
-            u8::try_from(instruction & 0x000f).unwrap(),
-        ];
-
-        let x: Vx = Vx(nibbles[1]);
-        let y: Vy = Vy(nibbles[2]);
-        let kk: Byte = Byte(u8::try_from(instruction & 0x00ff).unwrap());
-        let nnn: Nnn = Nnn(instruction & 0x0fff);
-        match (nibbles[0], nibbles[1], nibbles[2], nibbles[3]) {
-            (0x0, 0x0, 0xe, 0x0) => self.cls(),
-            (0x0, 0x0, 0xe, 0xe) => self.ret(),
-            (0x0, _, _, _) => self.sys(nnn),
-            (0x1, _, _, _) => self.jp(nnn),
-            (0x2, _, _, _) => self.call(nnn),
-            (0x3, _, _, _) => self.se(x, kk),
-            (0x4, _, _, _) => self.sne(x, kk),
-            (0x5, _, _, _) => self.se(x, y),
-            (0x6, _, _, _) => self.ld(x, kk),
-            (0x7, _, _, _) => self.add_kk(x, kk),
-            (0x8, _, _, 0) => self.ld(x, y),
-            (0x8, _, _, 1) => self.or(x, y),
-            (0x8, _, _, 2) => self.and(x, y),
-            (0x8, _, _, 3) => self.xor(x, y),
-            (0x8, _, _, 4) => self.add_y(x, y),
-            (0x8, _, _, 5) => self.sub(x, y),
-            (0x8, _, _, 6) => self.shr(x, y),
-            (0x8, _, _, 7) => self.subn(x, y),
-            (0x8, _, _, 0xe) => self.shl(x, y),
-            (0x9, _, _, 0) => self.sne(x, y),
-            (0xa, _, _, _) => self.ld_i(nnn),
-            (0xb, _, _, _) => self.jp_v0(nnn),
-            (0xc, _, _, _) => self.rnd(x, Byte(kk.0)),
-            (0xd, _, _, nibble) => self.drw(x, y, nibble as u64),
-            (0xe, _, 0x9, 0xe) => self.skp(x),
-            (0xe, _, 0xa, 0x1) => self.sknp(x),
-            (0xf, _, 0x0, 0x7) => self.ld_x_dt(x),
-            (0xf, _, 0x0, 0xa) => self.ld_k(x),
-            (0xf, _, 0x1, 0x5) => self.ld_dt_x(x),
-            (0xf, _, 0x1, 0x8) => self.ld_st(x),
-            (0xf, _, 0x1, 0xe) => self.add_i(x),
-            (0xf, _, 0x2, 0x9) => self.ld_f(x),
-            (0xf, _, 0x3, 0x3) => self.ld_b(x),
-            (0xf, _, 0x5, 0x5) => self.ld_i_x(x),
-            (0xf, _, 0x6, 0x5) => self.ld_x_i(x),
-            _ => unreachable!("Reached unknown instruction: {:#x}", instruction),
+    fn compile_instruction(&mut self, instruction: Instruction) -> Result<bool> {
+        debug!("Recompiling '{}'", instruction);
+
+        match instruction {
+            Instruction::Scd(n) => self.scd(n),
+            Instruction::Scu(n) => self.scu(n),
+            Instruction::Cls => self.cls(),
+            Instruction::Ret => self.ret(),
+            Instruction::Scr => self.scr(),
+            Instruction::Scl => self.scl(),
+            Instruction::Exit => self.exit(),
+            Instruction::Low => self.low(),
+            Instruction::High => self.high(),
+            Instruction::Sys(nnn) => self.sys(Nnn(nnn)),
+            Instruction::Jp(nnn) => self.jp(Nnn(nnn)),
+            Instruction::Call(nnn) => self.call(Nnn(nnn)),
+            Instruction::SeByte(x, kk) => self.se(Vx(x), Byte(kk)),
+            Instruction::SneByte(x, kk) => self.sne(Vx(x), Byte(kk)),
+            Instruction::SeReg(x, y) => self.se(Vx(x), Vy(y)),
+            Instruction::SaveRange(x, y) => self.ld_i_range(Vx(x), Vy(y)),
+            Instruction::LoadRange(x, y) => self.ld_range_i(Vx(x), Vy(y)),
+            Instruction::LdByte(x, kk) => self.ld(Vx(x), Byte(kk)),
+            Instruction::AddByte(x, kk) => self.add_kk(Vx(x), Byte(kk)),
+            Instruction::LdReg(x, y) => self.ld(Vx(x), Vy(y)),
+            Instruction::Or(x, y) => self.or(Vx(x), Vy(y)),
+            Instruction::And(x, y) => self.and(Vx(x), Vy(y)),
+            Instruction::Xor(x, y) => self.xor(Vx(x), Vy(y)),
+            Instruction::AddReg(x, y) => self.add_y(Vx(x), Vy(y)),
+            Instruction::Sub(x, y) => self.sub(Vx(x), Vy(y)),
+            Instruction::Shr(x, y) => self.shr(Vx(x), Vy(y)),
+            Instruction::Subn(x, y) => self.subn(Vx(x), Vy(y)),
+            Instruction::Shl(x, y) => self.shl(Vx(x), Vy(y)),
+            Instruction::SneReg(x, y) => self.sne(Vx(x), Vy(y)),
+            Instruction::LdI(nnn) => self.ld_i(Nnn(nnn)),
+            Instruction::JpV0(nnn) => self.jp_v0(Nnn(nnn)),
+            Instruction::Rnd(x, kk) => self.rnd(Vx(x), Byte(kk)),
+            Instruction::Drw(x, y, n) => self.drw(Vx(x), Vy(y), u64::from(n)),
+            Instruction::Skp(x) => self.skp(Vx(x)),
+            Instruction::Sknp(x) => self.sknp(Vx(x)),
+            Instruction::Plane(n) => self.plane(n),
+            Instruction::Audio => self.audio(),
+            Instruction::LdXDt(x) => self.ld_x_dt(Vx(x)),
+            Instruction::LdK(x) => self.ld_k(Vx(x)),
+            Instruction::LdDtX(x) => self.ld_dt_x(Vx(x)),
+            Instruction::LdSt(x) => self.ld_st(Vx(x)),
+            Instruction::AddI(x) => self.add_i(Vx(x)),
+            Instruction::LdF(x) => self.ld_f(Vx(x)),
+            Instruction::LdHf(x) => self.ld_hf(Vx(x)),
+            Instruction::LdB(x) => self.ld_b(Vx(x)),
+            Instruction::Pitch(x) => self.pitch(Vx(x)),
+            Instruction::LdIX(x) => self.ld_i_x(Vx(x)),
+            Instruction::LdXI(x) => self.ld_x_i(Vx(x)),
+            Instruction::LdRX(x) => self.ld_r_x(Vx(x)),
+            Instruction::LdXR(x) => self.ld_x_r(Vx(x)),
         }
     }
 
+    /// Returns the offset of `field` relative to the state pointer in `rdi`.
     fn get_field_offset(&self, field: Chip8Field) -> Addr {
-        let state_addr = &self.chip_state.borrow().mem as *const u8 as Addr;
-
-        let field_addr = match field {
-            Chip8Field::I => &self.chip_state.borrow().i as *const u64 as Addr,
-            Chip8Field::PC => &self.chip_state.borrow().pc as *const u64 as Addr,
-            Chip8Field::SP => &self.chip_state.borrow().sp as *const u64 as Addr,
-            Chip8Field::Stack => &self.chip_state.borrow().stack as *const u64 as Addr,
-            Chip8Field::Reg(index) => self
-                .chip_state
-                .borrow()
-                .regs
-                .get(usize::from(index))
-                .unwrap() as *const u64 as Addr,
-            Chip8Field::Delay => &self.chip_state.borrow().delay as *const u64 as Addr,
-            Chip8Field::Sound => &self.chip_state.borrow().sound as *const u64 as Addr,
-        };
-
-        field_addr - state_addr
+        field.offset() as Addr
     }
 }
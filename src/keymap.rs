@@ -0,0 +1,409 @@
+use fnv::FnvHashMap;
+use minifb::Key;
+use serde::Deserialize;
+
+use crate::chip8::AMOUNT_KEYS;
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+/// The names of the keyboard keys which can be bound, they're matched ignoring the case.
+const KEYS: [(&str, Key); 106] = [
+    ("Key0", Key::Key0),
+    ("Key1", Key::Key1),
+    ("Key2", Key::Key2),
+    ("Key3", Key::Key3),
+    ("Key4", Key::Key4),
+    ("Key5", Key::Key5),
+    ("Key6", Key::Key6),
+    ("Key7", Key::Key7),
+    ("Key8", Key::Key8),
+    ("Key9", Key::Key9),
+    ("A", Key::A),
+    ("B", Key::B),
+    ("C", Key::C),
+    ("D", Key::D),
+    ("E", Key::E),
+    ("F", Key::F),
+    ("G", Key::G),
+    ("H", Key::H),
+    ("I", Key::I),
+    ("J", Key::J),
+    ("K", Key::K),
+    ("L", Key::L),
+    ("M", Key::M),
+    ("N", Key::N),
+    ("O", Key::O),
+    ("P", Key::P),
+    ("Q", Key::Q),
+    ("R", Key::R),
+    ("S", Key::S),
+    ("T", Key::T),
+    ("U", Key::U),
+    ("V", Key::V),
+    ("W", Key::W),
+    ("X", Key::X),
+    ("Y", Key::Y),
+    ("Z", Key::Z),
+    ("F1", Key::F1),
+    ("F2", Key::F2),
+    ("F3", Key::F3),
+    ("F4", Key::F4),
+    ("F5", Key::F5),
+    ("F6", Key::F6),
+    ("F7", Key::F7),
+    ("F8", Key::F8),
+    ("F9", Key::F9),
+    ("F10", Key::F10),
+    ("F11", Key::F11),
+    ("F12", Key::F12),
+    ("F13", Key::F13),
+    ("F14", Key::F14),
+    ("F15", Key::F15),
+    ("Down", Key::Down),
+    ("Left", Key::Left),
+    ("Right", Key::Right),
+    ("Up", Key::Up),
+    ("Apostrophe", Key::Apostrophe),
+    ("Backquote", Key::Backquote),
+    ("Backslash", Key::Backslash),
+    ("Comma", Key::Comma),
+    ("Equal", Key::Equal),
+    ("LeftBracket", Key::LeftBracket),
+    ("Minus", Key::Minus),
+    ("Period", Key::Period),
+    ("RightBracket", Key::RightBracket),
+    ("Semicolon", Key::Semicolon),
+    ("Slash", Key::Slash),
+    ("Backspace", Key::Backspace),
+    ("Delete", Key::Delete),
+    ("End", Key::End),
+    ("Enter", Key::Enter),
+    ("Escape", Key::Escape),
+    ("Home", Key::Home),
+    ("Insert", Key::Insert),
+    ("Menu", Key::Menu),
+    ("PageDown", Key::PageDown),
+    ("PageUp", Key::PageUp),
+    ("Pause", Key::Pause),
+    ("Space", Key::Space),
+    ("Tab", Key::Tab),
+    ("NumLock", Key::NumLock),
+    ("CapsLock", Key::CapsLock),
+    ("ScrollLock", Key::ScrollLock),
+    ("LeftShift", Key::LeftShift),
+    ("RightShift", Key::RightShift),
+    ("LeftCtrl", Key::LeftCtrl),
+    ("RightCtrl", Key::RightCtrl),
+    ("NumPad0", Key::NumPad0),
+    ("NumPad1", Key::NumPad1),
+    ("NumPad2", Key::NumPad2),
+    ("NumPad3", Key::NumPad3),
+    ("NumPad4", Key::NumPad4),
+    ("NumPad5", Key::NumPad5),
+    ("NumPad6", Key::NumPad6),
+    ("NumPad7", Key::NumPad7),
+    ("NumPad8", Key::NumPad8),
+    ("NumPad9", Key::NumPad9),
+    ("NumPadDot", Key::NumPadDot),
+    ("NumPadSlash", Key::NumPadSlash),
+    ("NumPadAsterisk", Key::NumPadAsterisk),
+    ("NumPadMinus", Key::NumPadMinus),
+    ("NumPadPlus", Key::NumPadPlus),
+    ("NumPadEnter", Key::NumPadEnter),
+    ("LeftAlt", Key::LeftAlt),
+    ("RightAlt", Key::RightAlt),
+    ("LeftSuper", Key::LeftSuper),
+    ("RightSuper", Key::RightSuper),
+];
+
+/// The layout of the hex keypad of the COSMAC VIP, row by row.
+const KEYPAD: [u8; AMOUNT_KEYS] = [
+    0x1, 0x2, 0x3, 0xC, //
+    0x4, 0x5, 0x6, 0xD, //
+    0x7, 0x8, 0x9, 0xE, //
+    0xA, 0x0, 0xB, 0xF, //
+];
+
+/// Keeps the shape of the keypad on the left side of the keyboard.
+const STANDARD_LAYOUT: [Key; AMOUNT_KEYS] = [
+    Key::Key1,
+    Key::Key2,
+    Key::Key3,
+    Key::Key4,
+    Key::Q,
+    Key::W,
+    Key::E,
+    Key::R,
+    Key::A,
+    Key::S,
+    Key::D,
+    Key::F,
+    Key::Z,
+    Key::X,
+    Key::C,
+    Key::V,
+];
+
+/// Maps the digits to their numpad keys and A to F to the keys around them.
+const NUMPAD_LAYOUT: [(u8, Key); AMOUNT_KEYS] = [
+    (0x0, Key::NumPad0),
+    (0x1, Key::NumPad1),
+    (0x2, Key::NumPad2),
+    (0x3, Key::NumPad3),
+    (0x4, Key::NumPad4),
+    (0x5, Key::NumPad5),
+    (0x6, Key::NumPad6),
+    (0x7, Key::NumPad7),
+    (0x8, Key::NumPad8),
+    (0x9, Key::NumPad9),
+    (0xA, Key::NumPadSlash),
+    (0xB, Key::NumPadAsterisk),
+    (0xC, Key::NumPadMinus),
+    (0xD, Key::NumPadPlus),
+    (0xE, Key::NumPadEnter),
+    (0xF, Key::NumPadDot),
+];
+
+/// Returns the keyboard key with the given name, digits may be written without the `Key` prefix.
+pub fn parse_key(name: &str) -> Option<Key> {
+    let name = if name.len() == 1 && name.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("Key{}", name)
+    } else {
+        name.to_string()
+    };
+
+    KEYS.into_iter()
+        .find(|(key_name, _)| key_name.eq_ignore_ascii_case(&name))
+        .map(|(_, key)| key)
+}
+
+/// A keyboard key, deserialized from its name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub struct KeyName(pub Key);
+
+impl TryFrom<String> for KeyName {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        parse_key(&name)
+            .map(Self)
+            .ok_or_else(|| format!("unknown key '{}'", name))
+    }
+}
+
+/// A key of the CHIP-8 keypad, deserialized from a hex digit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub struct HexKey(pub u8);
+
+impl TryFrom<String> for HexKey {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        match u8::from_str_radix(&name, 16) {
+            Ok(value) if name.len() == 1 => Ok(Self(value)),
+            _ => Err(format!("'{}' isn't a CHIP-8 key, expected 0 to F", name)),
+        }
+    }
+}
+
+/// The keyboard keys of CHIP-8 keys, they replace the ones which the preset binds to them.
+pub type KeyBindings = BTreeMap<HexKey, Vec<KeyName>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyPreset {
+    /// `1234`, `QWER`, `ASDF` and `ZXCV`.
+    #[default]
+    Standard,
+    Numpad,
+}
+
+impl KeyPreset {
+    pub const NAMES: [&'static str; 2] = ["standard", "numpad"];
+
+    fn bindings(&self) -> Vec<(u8, Key)> {
+        match self {
+            Self::Standard => KEYPAD.into_iter().zip(STANDARD_LAYOUT).collect(),
+            Self::Numpad => NUMPAD_LAYOUT.to_vec(),
+        }
+    }
+}
+
+impl FromStr for KeyPreset {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_lowercase().as_str() {
+            "standard" | "qwerty" => Ok(Self::Standard),
+            "numpad" => Ok(Self::Numpad),
+            _ => Err(format!("unknown key preset '{}'", name)),
+        }
+    }
+}
+
+/// Keys which control the emulator. They are never passed to the emulated program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Hotkeys {
+    pub quit: Key,
+    pub screenshot: Key,
+    /// Starts or stops recording a GIF.
+    pub record: Key,
+}
+
+impl Default for Hotkeys {
+    fn default() -> Self {
+        Self {
+            quit: Key::Escape,
+            screenshot: Key::F12,
+            record: Key::F9,
+        }
+    }
+}
+
+/// Hotkeys which should be changed, see [Hotkeys].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HotkeyOverrides {
+    pub quit: Option<KeyName>,
+    pub screenshot: Option<KeyName>,
+    pub record: Option<KeyName>,
+}
+
+impl HotkeyOverrides {
+    pub fn apply(&self, hotkeys: &mut Hotkeys) {
+        let keys = [
+            (self.quit, &mut hotkeys.quit),
+            (self.screenshot, &mut hotkeys.screenshot),
+            (self.record, &mut hotkeys.record),
+        ];
+
+        for (key, hotkey) in keys {
+            if let Some(KeyName(key)) = key {
+                *hotkey = key;
+            }
+        }
+    }
+}
+
+/// Translates keyboard keys to CHIP-8 keys.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keymap {
+    keys: FnvHashMap<Key, u8>,
+    pub hotkeys: Hotkeys,
+}
+
+impl Keymap {
+    pub fn new(preset: KeyPreset) -> Self {
+        Self {
+            keys: preset
+                .bindings()
+                .into_iter()
+                .map(|(value, key)| (key, value))
+                .collect(),
+            hotkeys: Hotkeys::default(),
+        }
+    }
+
+    /// Binds `key` to the CHIP-8 key `value`. A CHIP-8 key can be bound to multiple keys.
+    pub fn bind(&mut self, value: u8, key: Key) {
+        self.keys.insert(key, value & 0xf);
+    }
+
+    /// Binds the CHIP-8 key `value` to `keys` only, the keys which were bound to it before are
+    /// released.
+    pub fn rebind(&mut self, value: u8, keys: &[Key]) {
+        let value = value & 0xf;
+        self.keys.retain(|_, bound| *bound != value);
+        for &key in keys {
+            self.bind(value, key);
+        }
+    }
+
+    /// Rebinds every CHIP-8 key of `bindings`, the other ones keep their keys.
+    pub fn bind_all(&mut self, bindings: &KeyBindings) {
+        for (&HexKey(value), keys) in bindings {
+            let keys: Vec<Key> = keys.iter().map(|&KeyName(key)| key).collect();
+            self.rebind(value, &keys);
+        }
+    }
+
+    /// Returns the CHIP-8 key of `key`. Hotkeys don't have one, even if they're bound.
+    pub fn value(&self, key: Key) -> Option<u8> {
+        if self.is_hotkey(key) {
+            return None;
+        }
+
+        self.keys.get(&key).copied()
+    }
+
+    pub fn is_hotkey(&self, key: Key) -> bool {
+        let hotkeys = self.hotkeys;
+        [hotkeys.quit, hotkeys.screenshot, hotkeys.record].contains(&key)
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::new(KeyPreset::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_key_names() {
+        assert_eq!(parse_key("tab"), Some(Key::Tab));
+        assert_eq!(parse_key("NumPad5"), Some(Key::NumPad5));
+        assert_eq!(parse_key("7"), Some(Key::Key7));
+        assert_eq!(parse_key("key7"), Some(Key::Key7));
+        assert_eq!(parse_key("Unknown"), None);
+        assert_eq!(parse_key(""), None);
+    }
+
+    #[test]
+    fn every_key_has_a_unique_name() {
+        for (i, (name, key)) in KEYS.into_iter().enumerate() {
+            assert_eq!(parse_key(name), Some(key));
+            assert!(KEYS[i + 1..].iter().all(|&(other, _)| other != name));
+        }
+    }
+
+    #[test]
+    fn parses_hex_keys() {
+        assert_eq!(HexKey::try_from("a".to_string()), Ok(HexKey(0xa)));
+        assert_eq!(HexKey::try_from("F".to_string()), Ok(HexKey(0xf)));
+        assert!(HexKey::try_from("10".to_string()).is_err());
+        assert!(HexKey::try_from("G".to_string()).is_err());
+    }
+
+    #[test]
+    fn bindings_replace_the_preset() {
+        let bindings: KeyBindings = serde_json::from_str(r#"{"5": ["Up", "K"]}"#).unwrap();
+        let mut keymap = Keymap::default();
+        keymap.bind_all(&bindings);
+
+        assert_eq!(keymap.value(Key::Up), Some(0x5));
+        assert_eq!(keymap.value(Key::K), Some(0x5));
+        assert_eq!(keymap.value(Key::W), None);
+        assert_eq!(keymap.value(Key::Q), Some(0x4));
+    }
+
+    #[test]
+    fn rejects_unknown_bindings() {
+        assert!(serde_json::from_str::<KeyBindings>(r#"{"5": ["Nope"]}"#).is_err());
+        assert!(serde_json::from_str::<KeyBindings>(r#"{"X": ["Up"]}"#).is_err());
+    }
+
+    #[test]
+    fn hotkeys_have_no_value() {
+        let mut keymap = Keymap::default();
+        keymap.bind(0x1, Key::Escape);
+        assert_eq!(keymap.value(Key::Escape), None);
+    }
+}
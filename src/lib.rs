@@ -1,14 +1,295 @@
+pub mod aot;
+pub mod arena;
+pub mod backend;
+pub mod block_cache;
+pub mod builder;
 pub mod cache;
+pub mod cfg;
 pub mod chip8;
+pub mod config;
+#[cfg(feature = "cranelift")]
+pub mod cranelift;
+pub mod display;
+mod error;
+pub mod ffi;
+pub mod filter;
+pub mod frontend;
+pub mod gif;
+pub mod idle;
+pub mod image;
+pub mod instruction;
 pub mod jit;
+pub mod keymap;
+pub mod palette;
+pub mod quirks;
+pub mod rom_db;
+pub mod save_state;
+pub mod threaded;
+pub mod transpile;
+
+use log::info;
 
 use std::fs::read;
+use std::path::{Path, PathBuf};
 
 pub type Addr = u64;
 
-use chip8::Chip8;
+pub use error::{Error, Fault, Result};
+
+use aot::{Game, LinkedGame};
+use backend::Backend;
+use cache::CompileBlock;
+use cfg::Cfg;
+use chip8::{Chip8, Keys};
+use config::Config;
+use display::{Display, Terminal, TerminalMode};
+use filter::Filter;
+use frontend::Frontend;
+use keymap::{KeyPreset, Keymap};
+use palette::PaletteOverrides;
+use quirks::{Platform, QuirkOverrides, Quirks};
+use rom_db::{RomDatabase, RomInfo};
+use transpile::runtime::Program;
+
+/// Settings which take precedence over the ones from the ROM database.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    pub platform: Option<Platform>,
+    /// Compiles the blocks, [Backend::X86] if it isn't set.
+    pub backend: Option<Backend>,
+    pub quirks: QuirkOverrides,
+    pub speed: Option<u64>,
+    /// A JSON file with additional entries for the ROM database.
+    pub rom_database: Option<PathBuf>,
+    pub key_preset: Option<KeyPreset>,
+    /// The size of a low resolution pixel in the window.
+    pub scale: Option<usize>,
+    pub palette: PaletteOverrides,
+    pub filter: Option<Filter>,
+    /// Runs without a window and as fast as possible.
+    pub headless: bool,
+    /// Shows the screen in the terminal instead of a window.
+    pub terminal: Option<TerminalMode>,
+    /// Saves a screenshot after the given amount of frames and stops, implies `headless`.
+    pub screenshot_at_frame: Option<(u64, PathBuf)>,
+    /// Records the shown frames into an animated GIF.
+    pub record: Option<PathBuf>,
+    /// Stops after the given amount of frames.
+    pub frames: Option<u64>,
+    /// Keeps the compiled blocks in this directory for the next run.
+    pub block_cache: Option<PathBuf>,
+    /// The config file which is used instead of [Config::default_path].
+    pub config: Option<PathBuf>,
+}
+
+pub fn run(path: &str, options: Options) -> Result<()> {
+    let binary_content = read_rom(path)?;
+    play(binary_content, options, |_| ())
+}
+
+/// Runs a game which was linked into the executable by [compile_executable]. Its blocks are
+/// executed instead of compiling them, the ones which weren't found ahead of time are
+/// interpreted by [Backend::Threaded]. The quirks can't be changed because they're part of the
+/// code.
+///
+/// # Safety
+/// `game` has to be written by [Game::to_object].
+pub unsafe fn run_linked(game: &LinkedGame, mut options: Options) -> Result<()> {
+    game.check_build()?;
+    options.quirks = game.quirks().into();
+    options.speed = options.speed.or(Some(game.speed));
+    options.backend = Some(Backend::Threaded);
+
+    play(game.rom().to_vec(), options, |chip8| {
+        for block in game.blocks() {
+            chip8.insert_block(block);
+        }
+    })
+}
+
+/// Runs a ROM which was translated into Rust by [transpile_rom]. Its blocks are executed instead
+/// of compiling them, the quirks can't be changed because they're part of the code.
+pub fn run_program(program: &Program, mut options: Options) -> Result<()> {
+    options.quirks = program.quirks.into();
+    options.speed = options.speed.or(Some(program.speed));
+
+    play(program.rom.to_vec(), options, |chip8| {
+        for block in program.blocks {
+            chip8.insert_block(CompileBlock::transpiled(
+                block.function,
+                block.addrs.clone(),
+                block.instructions,
+            ));
+        }
+    })
+}
+
+/// Compiles the reachable blocks of the ROM at `path` and links them together with the ROM and
+/// the static rip8 library `runtime` into the standalone executable `output`, see [aot].
+pub fn compile_executable<P: AsRef<Path>, R: AsRef<Path>>(
+    path: &str,
+    output: P,
+    runtime: R,
+    options: &Options,
+) -> Result<Game> {
+    let binary_content = read_rom(path)?;
+    let rom_info = rom_info(&binary_content, options)?;
+    let quirks = quirks(&rom_info, options);
+    let speed = options
+        .speed
+        .or(rom_info.speed)
+        .unwrap_or(Chip8::DEFAULT_SPEED);
+
+    let game = Game::compile(binary_content, quirks, speed)?;
+    game.link(output, runtime)?;
+
+    Ok(game)
+}
+
+/// Translates the reachable blocks of the ROM at `path` into the source code of a Rust program,
+/// see [transpile].
+pub fn transpile_rom(path: &str, options: &Options) -> Result<String> {
+    let binary_content = read_rom(path)?;
+    let rom_info = rom_info(&binary_content, options)?;
+    let quirks = quirks(&rom_info, options);
+    let speed = options
+        .speed
+        .or(rom_info.speed)
+        .unwrap_or(Chip8::DEFAULT_SPEED);
+
+    let name = Path::new(path)
+        .file_name()
+        .map_or_else(|| path.into(), |name| name.to_string_lossy());
+    transpile::transpile(&binary_content, quirks, speed, &name)
+}
+
+/// Analyzes the control flow of the ROM at `path`, the quirks of the options decide where the
+/// blocks end.
+pub fn analyze_rom(path: &str, options: &Options) -> Result<Cfg> {
+    let binary_content = read_rom(path)?;
+    let rom_info = rom_info(&binary_content, options)?;
+    let quirks = quirks(&rom_info, options);
+
+    let rom_len = binary_content.len();
+    let chip8 = Chip8::new(binary_content, quirks)?;
+    Ok(Cfg::analyze(chip8.memory(), rom_len, &quirks))
+}
+
+fn read_rom(path: &str) -> Result<Vec<u8>> {
+    read(path).map_err(|source| Error::Io {
+        path: path.into(),
+        source,
+    })
+}
+
+/// Looks up the ROM in the builtin database and the one of the options.
+fn rom_info(binary_content: &[u8], options: &Options) -> Result<RomInfo> {
+    let mut database = RomDatabase::builtin();
+    if let Some(database_path) = &options.rom_database {
+        database.extend(RomDatabase::load(database_path)?);
+    }
+
+    let rom_info = database.lookup(binary_content).cloned().unwrap_or_default();
+    if let Some(title) = &rom_info.title {
+        info!(
+            "Detected '{}' by {}",
+            title,
+            rom_info.author.as_deref().unwrap_or("an unknown author")
+        );
+    }
+
+    Ok(rom_info)
+}
+
+fn quirks(rom_info: &RomInfo, options: &Options) -> Quirks {
+    let platform = options.platform.or(rom_info.platform).unwrap_or_default();
+    let mut quirks = platform.quirks();
+    rom_info.quirks.apply(&mut quirks);
+    options.quirks.apply(&mut quirks);
+
+    quirks
+}
+
+/// Runs the ROM, `prepare` is called with the emulator before the first frame.
+fn play(binary_content: Vec<u8>, options: Options, prepare: impl FnOnce(&mut Chip8)) -> Result<()> {
+    let config = match &options.config {
+        Some(config_path) => Config::load(config_path)?,
+        None => Config::load_default()?,
+    };
+
+    let rom_info = rom_info(&binary_content, &options)?;
+    let quirks = quirks(&rom_info, &options);
+
+    let mut keymap = Keymap::new(
+        options
+            .key_preset
+            .or(config.keys.preset)
+            .unwrap_or_default(),
+    );
+    // the user's bindings win over the ones which the ROM recommends
+    keymap.bind_all(&rom_info.keys);
+    keymap.bind_all(&config.keys.bindings);
+    config.keys.hotkeys.apply(&mut keymap.hotkeys);
+
+    let mut palette = chip8::PALETTE;
+    config.display.palette.apply(&mut palette);
+    PaletteOverrides::from_colors(&rom_info.colors).apply(&mut palette);
+    options.palette.apply(&mut palette);
+
+    let mut builder = Chip8::builder()
+        .rom(binary_content)
+        .quirks(quirks)
+        .backend(options.backend.unwrap_or_default())
+        .speed(
+            options
+                .speed
+                .or(rom_info.speed)
+                .unwrap_or(Chip8::DEFAULT_SPEED),
+        )
+        .scale(
+            options
+                .scale
+                .or(config.display.scale)
+                .unwrap_or(Chip8::DEFAULT_SCALE),
+        )
+        .palette(palette)
+        .filter(options.filter.or(config.display.filter).unwrap_or_default());
+    if let Some(block_cache) = &options.block_cache {
+        builder = builder.block_cache(block_cache);
+    }
+    let mut chip8 = builder.build()?;
+    prepare(&mut chip8);
+
+    if let Some(record_path) = &options.record {
+        chip8.start_recording(record_path)?;
+    }
+
+    let last_frame = options
+        .screenshot_at_frame
+        .as_ref()
+        .map(|(frame, _)| *frame)
+        .into_iter()
+        .chain(options.frames)
+        .min();
+
+    if options.headless || options.screenshot_at_frame.is_some() {
+        let is_last_frame = |frames| last_frame.is_some_and(|last_frame| frames >= last_frame);
+        while chip8.is_running() && !is_last_frame(chip8.frames()) {
+            chip8.step_frame(Keys::NONE)?;
+        }
+    } else {
+        let display: Box<dyn Display> = match options.terminal {
+            Some(mode) => Box::new(Terminal::new(mode)?),
+            None => Box::new(display::open_window(chip8.scale())?),
+        };
+
+        chip8 = Frontend::new(display, keymap).run(chip8, last_frame)?;
+    }
+
+    if let Some((_, screenshot_path)) = &options.screenshot_at_frame {
+        chip8.save_screenshot(screenshot_path)?;
+    }
 
-pub fn run(path: &str) {
-    let binary_content = read(path).unwrap();
-    Chip8::new(binary_content).run();
+    chip8.save_block_cache()?;
+    chip8.stop_recording()
 }
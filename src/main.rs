@@ -1,21 +1,464 @@
-use clap::{command, Arg};
+use clap::{command, value_parser, Arg, ArgMatches, Command, ErrorKind};
 
 use log::debug;
-use rip8::run;
+use rip8::aot::Game;
+use rip8::backend::Backend;
+use rip8::block_cache::BlockCache;
+use rip8::display::TerminalMode;
+use rip8::filter::Filter;
+use rip8::keymap::KeyPreset;
+use rip8::palette::{Color, PaletteOverrides};
+use rip8::quirks::{Platform, QuirkOverrides};
+use rip8::{analyze_rom, compile_executable, run, transpile_rom, Error, Options};
+
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+type QuirkField = fn(&mut QuirkOverrides) -> &mut Option<bool>;
+
+/// The CLI flags which override a single quirk.
+const QUIRK_FLAGS: [(&str, &str, QuirkField); 7] = [
+    (
+        "shift-vy",
+        "8XY6/8XYE shift Vy into Vx instead of shifting Vx in place",
+        |quirks| &mut quirks.shift_vy,
+    ),
+    ("increment-i", "FX55/FX65 increment I", |quirks| {
+        &mut quirks.increment_i
+    }),
+    (
+        "jump-vx",
+        "BNNN behaves like BXNN and jumps to XNN + VX",
+        |quirks| &mut quirks.jump_vx,
+    ),
+    ("vf-reset", "8XY1, 8XY2 and 8XY3 reset VF", |quirks| {
+        &mut quirks.vf_reset
+    }),
+    (
+        "clip-sprites",
+        "sprites get clipped at the edges instead of wrapping around",
+        |quirks| &mut quirks.clip_sprites,
+    ),
+    ("display-wait", "DXYN waits for the next frame", |quirks| {
+        &mut quirks.display_wait
+    }),
+    (
+        "key-release",
+        "FX0A stores the key once it's released instead of when it's pressed",
+        |quirks| &mut quirks.key_release,
+    ),
+];
 
 fn main() {
     env_logger::init();
     debug!("RIP");
 
-    let app = command!().about("A CHIP-8 Emulator written in rust.").arg(
-        Arg::new("rom")
-            .required(true)
-            .short('r')
-            .long("rom")
-            .long_help("the path to the ROM file")
-            .takes_value(true),
-    );
-
-    let matches = app.get_matches();
-    run(&matches.get_one::<String>("rom").unwrap());
+    let mut app = command!()
+        .about("A CHIP-8 Emulator written in rust.")
+        .arg(
+            Arg::new("rom")
+                .required(true)
+                .short('r')
+                .long("rom")
+                .long_help("the path to the ROM file")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("backend")
+                .long("backend")
+                .long_help(
+                    "the backend which compiles the ROM, 'cranelift' requires the 'cranelift' feature",
+                )
+                .takes_value(true)
+                .value_parser(Backend::NAMES),
+        )
+        .arg(
+            Arg::new("keys")
+                .short('k')
+                .long("keys")
+                .long_help("the keyboard layout of the keypad, overrides the config file")
+                .takes_value(true)
+                .value_parser(KeyPreset::NAMES),
+        )
+        .arg(
+            Arg::new("config")
+                .short('c')
+                .long("config")
+                .long_help("the config file, defaults to $XDG_CONFIG_HOME/rip8/config.json")
+                .takes_value(true)
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("block-cache")
+                .long("block-cache")
+                .value_name("DIR")
+                .long_help(
+                    "keep the compiled blocks for the next run, DIR defaults to $XDG_CACHE_HOME/rip8/blocks",
+                )
+                .takes_value(true)
+                .min_values(0)
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("scale")
+                .long("scale")
+                .long_help("the size of a low resolution pixel in the window, overrides the config file")
+                .takes_value(true)
+                .value_parser(value_parser!(u64).range(1..=64)),
+        )
+        .arg(
+            Arg::new("background")
+                .long("background")
+                .long_help("the background color as #rrggbb")
+                .takes_value(true)
+                .value_name("COLOR")
+                .value_parser(value_parser!(Color)),
+        )
+        .arg(
+            Arg::new("foreground")
+                .long("foreground")
+                .long_help("the foreground color as #rrggbb")
+                .takes_value(true)
+                .value_name("COLOR")
+                .value_parser(value_parser!(Color)),
+        )
+        .arg(
+            Arg::new("palette")
+                .long("palette")
+                .long_help(
+                    "up to four comma separated colors of the pixel values, starting with the background",
+                )
+                .takes_value(true)
+                .value_name("COLORS")
+                .use_value_delimiter(true)
+                .max_values(4)
+                .value_parser(value_parser!(Color))
+                .conflicts_with_all(&["background", "foreground"]),
+        )
+        .arg(
+            Arg::new("decay")
+                .long("decay")
+                .long_help(
+                    "let turned off pixels fade out, their brightness halves every HALF_LIFE milliseconds",
+                )
+                .takes_value(true)
+                .value_name("HALF_LIFE")
+                .value_parser(value_parser!(u64).range(1..)),
+        )
+        .arg(
+            Arg::new("blend")
+                .long("blend")
+                .long_help("show every pixel which was set in one of the last FRAMES frames")
+                .takes_value(true)
+                .value_name("FRAMES")
+                .value_parser(value_parser!(u64).range(1..=60))
+                .conflicts_with("decay"),
+        )
+        .arg(
+            Arg::new("headless")
+                .long("headless")
+                .long_help("run without a window and as fast as possible"),
+        )
+        .arg(
+            Arg::new("terminal")
+                .short('t')
+                .long("terminal")
+                .long_help("show the screen in the terminal instead of a window, Ctrl-C quits")
+                .takes_value(true)
+                .value_name("MODE")
+                .min_values(0)
+                .default_missing_value("halfblocks")
+                .value_parser(TerminalMode::NAMES),
+        )
+        .arg(
+            Arg::new("screenshot-at-frame")
+                .long("screenshot-at-frame")
+                .long_help(
+                    "run headless, save a PNG or PPM screenshot after FRAME frames and exit",
+                )
+                .takes_value(true)
+                .number_of_values(2)
+                .value_names(&["FRAME", "PATH"]),
+        )
+        .arg(
+            Arg::new("record")
+                .long("record")
+                .long_help("record the shown frames into an animated GIF")
+                .takes_value(true)
+                .value_name("PATH")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("frames")
+                .long("frames")
+                .long_help("exit after the given amount of frames")
+                .takes_value(true)
+                .value_parser(value_parser!(u64)),
+        )
+        .args(emulation_args())
+        .subcommand_negates_reqs(true)
+        .subcommand(
+            Command::new("aot")
+                .about("Compiles a ROM into a standalone executable")
+                .arg(
+                    Arg::new("rom")
+                        .required(true)
+                        .long_help("the path to the ROM file")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("output")
+                        .required(true)
+                        .short('o')
+                        .long("output")
+                        .long_help("the path of the executable")
+                        .takes_value(true)
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("runtime")
+                        .long("runtime")
+                        .long_help(
+                            "the static rip8 library which is linked into the executable, defaults to librip8.a next to rip8",
+                        )
+                        .takes_value(true)
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .args(emulation_args()),
+        )
+        .subcommand(
+            Command::new("transpile")
+                .about("Translates a ROM into a Rust program which uses rip8 as its runtime")
+                .arg(
+                    Arg::new("rom")
+                        .required(true)
+                        .long_help("the path to the ROM file")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .long_help("the path of the Rust file, the code is printed if it's missing")
+                        .takes_value(true)
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .args(emulation_args()),
+        )
+        .subcommand(
+            Command::new("cfg")
+                .about("Prints the control flow graph of a ROM in the DOT language of Graphviz")
+                .arg(
+                    Arg::new("rom")
+                        .required(true)
+                        .long_help("the path to the ROM file")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .long_help("the path of the DOT file, the graph is printed if it's missing")
+                        .takes_value(true)
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .args(emulation_args()),
+        );
+    let matches = app.get_matches_mut();
+
+    let result = if let Some(matches) = matches.subcommand_matches("aot") {
+        let output = matches.get_one::<PathBuf>("output").unwrap();
+        let runtime = matches
+            .get_one::<PathBuf>("runtime")
+            .cloned()
+            .or_else(Game::default_runtime)
+            .unwrap_or_else(|| PathBuf::from("librip8.a"));
+        compile_executable(
+            matches.get_one::<String>("rom").unwrap(),
+            output,
+            runtime,
+            &emulation_options(matches),
+        )
+        .map(|game| {
+            println!(
+                "Compiled {} blocks into '{}'",
+                game.blocks.len(),
+                output.display()
+            );
+        })
+    } else if let Some(matches) = matches.subcommand_matches("transpile") {
+        transpile_rom(
+            matches.get_one::<String>("rom").unwrap(),
+            &emulation_options(matches),
+        )
+        .and_then(|source| write_output(matches, &source))
+    } else if let Some(matches) = matches.subcommand_matches("cfg") {
+        analyze_rom(
+            matches.get_one::<String>("rom").unwrap(),
+            &emulation_options(matches),
+        )
+        .and_then(|cfg| write_output(matches, &cfg.to_dot()))
+    } else {
+        let options = get_options(&matches, &mut app);
+        debug!("Using options: {:?}", options);
+
+        run(matches.get_one::<String>("rom").unwrap(), options)
+    };
+
+    if let Err(err) = result {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
+}
+
+/// Writes `content` to the file of the "output" argument or prints it if there's none.
+fn write_output(matches: &ArgMatches, content: &str) -> Result<(), Error> {
+    match matches.get_one::<PathBuf>("output") {
+        Some(path) => fs::write(path, content).map_err(|source| Error::Write {
+            path: path.clone(),
+            source,
+        }),
+        None => {
+            print!("{}", content);
+            Ok(())
+        }
+    }
+}
+
+/// The arguments which change how the ROM is emulated, they're also needed to compile it.
+fn emulation_args() -> Vec<Arg<'static>> {
+    let mut args = vec![
+        Arg::new("platform")
+            .short('p')
+            .long("platform")
+            .long_help("the platform whose quirks should be emulated, overrides the ROM database")
+            .takes_value(true)
+            .value_parser(Platform::NAMES),
+        Arg::new("speed")
+            .short('s')
+            .long("speed")
+            .long_help("the amount of instructions per frame, overrides the ROM database")
+            .takes_value(true)
+            .value_parser(value_parser!(u64).range(1..)),
+        Arg::new("stack-depth")
+            .long("stack-depth")
+            .long_help("the amount of nested subroutine calls, 12 on the COSMAC VIP")
+            .takes_value(true)
+            .value_parser(value_parser!(u64).range(1..=16)),
+        Arg::new("rom-db")
+            .long("rom-db")
+            .long_help("a JSON file with additional entries for the ROM database")
+            .takes_value(true)
+            .value_parser(value_parser!(PathBuf)),
+    ];
+
+    for (name, help, _) in QUIRK_FLAGS {
+        args.push(
+            Arg::new(name)
+                .long(name)
+                .long_help(help)
+                .takes_value(true)
+                .value_name("BOOL")
+                .value_parser(value_parser!(bool)),
+        );
+    }
+
+    args
+}
+
+/// Reads the arguments of [emulation_args].
+fn emulation_options(matches: &ArgMatches) -> Options {
+    let mut quirks = QuirkOverrides::default();
+    for (name, _, field) in QUIRK_FLAGS {
+        *field(&mut quirks) = matches.get_one::<bool>(name).copied();
+    }
+    quirks.stack_depth = matches.get_one::<u64>("stack-depth").copied();
+
+    Options {
+        platform: matches
+            .get_one::<String>("platform")
+            .map(|name| name.parse().unwrap()),
+        quirks,
+        speed: matches.get_one::<u64>("speed").copied(),
+        rom_database: matches.get_one::<PathBuf>("rom-db").cloned(),
+        ..Options::default()
+    }
+}
+
+fn get_options(matches: &ArgMatches, app: &mut Command) -> Options {
+    let mut palette = match matches.get_many::<Color>("palette") {
+        Some(colors) => PaletteOverrides::from_colors(&colors.copied().collect::<Vec<_>>()),
+        None => PaletteOverrides::default(),
+    };
+    if let Some(&background) = matches.get_one::<Color>("background") {
+        palette.background = Some(background);
+    }
+    if let Some(&foreground) = matches.get_one::<Color>("foreground") {
+        palette.foreground = Some(foreground);
+    }
+
+    let filter = if let Some(&half_life) = matches.get_one::<u64>("decay") {
+        Some(Filter::Decay { half_life })
+    } else {
+        matches
+            .get_one::<u64>("blend")
+            .map(|&frames| Filter::Blend {
+                frames: frames as usize,
+            })
+    };
+
+    let screenshot_at_frame =
+        matches
+            .get_many::<String>("screenshot-at-frame")
+            .map(|mut values| {
+                let frame = values.next().unwrap();
+                let path = values.next().unwrap();
+
+                match frame.parse::<u64>() {
+                    Ok(frame) => (frame, PathBuf::from(path)),
+                    Err(_) => app
+                        .error(
+                            ErrorKind::InvalidValue,
+                            format!("'{}' isn't a valid frame number", frame),
+                        )
+                        .exit(),
+                }
+            });
+
+    let block_cache = matches.contains_id("block-cache").then(|| {
+        matches
+            .get_one::<PathBuf>("block-cache")
+            .cloned()
+            .or_else(BlockCache::default_dir)
+            .unwrap_or_else(|| {
+                app.error(
+                    ErrorKind::InvalidValue,
+                    "no cache directory found, pass one to --block-cache",
+                )
+                .exit()
+            })
+    });
+
+    Options {
+        backend: matches
+            .get_one::<String>("backend")
+            .map(|name| name.parse().unwrap()),
+        key_preset: matches
+            .get_one::<String>("keys")
+            .map(|name| name.parse().unwrap()),
+        config: matches.get_one::<PathBuf>("config").cloned(),
+        block_cache,
+        scale: matches.get_one::<u64>("scale").map(|&scale| scale as usize),
+        palette,
+        filter,
+        headless: matches.contains_id("headless"),
+        terminal: matches
+            .get_one::<String>("terminal")
+            .map(|name| name.parse().unwrap()),
+        screenshot_at_frame,
+        record: matches.get_one::<PathBuf>("record").cloned(),
+        frames: matches.get_one::<u64>("frames").copied(),
+        ..emulation_options(matches)
+    }
 }
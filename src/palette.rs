@@ -0,0 +1,109 @@
+use serde::Deserialize;
+
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+/// The colors of the four pixel values. Only the first two are used unless both XO-CHIP planes
+/// are drawn on.
+pub type Palette = [u32; 4];
+
+/// A color in the format `#rrggbb`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Color(pub u32);
+
+impl FromStr for Color {
+    type Err = String;
+
+    fn from_str(color: &str) -> Result<Self, Self::Err> {
+        parse_color(color)
+            .map(Self)
+            .ok_or_else(|| format!("invalid color '{}', expected #rrggbb", color))
+    }
+}
+
+impl TryFrom<String> for Color {
+    type Error = String;
+
+    fn try_from(color: String) -> Result<Self, Self::Error> {
+        color.parse()
+    }
+}
+
+/// Parses colors in the format `#rrggbb`.
+pub fn parse_color(color: &str) -> Option<u32> {
+    let hex = color.strip_prefix('#')?;
+
+    // from_str_radix also accepts a sign
+    if hex.len() != 6 || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    u32::from_str_radix(hex, 16).ok()
+}
+
+/// Colors which should be changed, independent of the default palette.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PaletteOverrides {
+    pub background: Option<Color>,
+    pub foreground: Option<Color>,
+    /// The color of pixels which are only set in the second XO-CHIP plane.
+    pub plane2: Option<Color>,
+    /// The color of pixels which are set in both XO-CHIP planes.
+    pub overlap: Option<Color>,
+}
+
+impl PaletteOverrides {
+    /// Overrides the colors from the background to the overlap color, in that order.
+    pub fn from_colors(colors: &[Color]) -> Self {
+        let color = |index: usize| colors.get(index).copied();
+
+        Self {
+            background: color(0),
+            foreground: color(1),
+            plane2: color(2),
+            overlap: color(3),
+        }
+    }
+
+    pub fn apply(&self, palette: &mut Palette) {
+        let colors = [self.background, self.foreground, self.plane2, self.overlap];
+
+        for (color, value) in colors.into_iter().zip(palette.iter_mut()) {
+            if let Some(Color(color)) = color {
+                *value = color;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_colors() {
+        assert_eq!(parse_color("#ff8000"), Some(0xff8000));
+        assert_eq!(parse_color("#FF8000"), Some(0xff8000));
+        assert_eq!(parse_color("ff8000"), None);
+        assert_eq!(parse_color("#fff"), None);
+        assert_eq!(parse_color("#ff80001"), None);
+        assert_eq!(parse_color("#gg8000"), None);
+        assert_eq!(parse_color("#+f8000"), None);
+        assert!("#12345".parse::<Color>().is_err());
+    }
+
+    #[test]
+    fn overrides_the_given_colors() {
+        let mut palette = [0, 1, 2, 3];
+        PaletteOverrides::from_colors(&[Color(0x10), Color(0x11)]).apply(&mut palette);
+        assert_eq!(palette, [0x10, 0x11, 2, 3]);
+
+        let overrides: PaletteOverrides =
+            serde_json::from_str(r##"{"overlap": "#abcdef"}"##).unwrap();
+        overrides.apply(&mut palette);
+        assert_eq!(palette, [0x10, 0x11, 2, 0xabcdef]);
+        assert!(serde_json::from_str::<PaletteOverrides>(r##"{"other": "#abcdef"}"##).is_err());
+    }
+}
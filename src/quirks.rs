@@ -0,0 +1,272 @@
+use serde::Deserialize;
+
+use crate::chip8::Chip8;
+
+use std::str::FromStr;
+
+/// Behaviour of opcodes in which the different CHIP-8 interpreters disagree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Quirks {
+    /// `8XY6`/`8XYE` shift `Vy` and store the result in `Vx` instead of shifting `Vx` in place.
+    pub shift_vy: bool,
+    /// `FX55`/`FX65` increment `I` by `X + 1`.
+    pub increment_i: bool,
+    /// `BNNN` is treated as `BXNN` and jumps to `XNN + VX` instead of `NNN + V0`.
+    pub jump_vx: bool,
+    /// `8XY1`, `8XY2` and `8XY3` reset `VF` to zero.
+    pub vf_reset: bool,
+    /// Sprites are clipped at the edges of the screen instead of wrapping around.
+    pub clip_sprites: bool,
+    /// `DXYN` waits for the next frame before the execution continues.
+    pub display_wait: bool,
+    /// `FX0A` stores the key once it's released again instead of when it gets pressed.
+    pub key_release: bool,
+    /// The amount of nested subroutine calls, the COSMAC VIP supports 12, most others 16.
+    pub stack_depth: u64,
+}
+
+impl Quirks {
+    /// The behaviour of the original interpreter of the COSMAC VIP.
+    pub const VIP: Self = Self {
+        shift_vy: true,
+        increment_i: true,
+        jump_vx: false,
+        vf_reset: true,
+        clip_sprites: true,
+        display_wait: true,
+        key_release: true,
+        stack_depth: 12,
+    };
+
+    /// The behaviour of SUPER-CHIP 1.1.
+    pub const SCHIP: Self = Self {
+        shift_vy: false,
+        increment_i: false,
+        jump_vx: true,
+        vf_reset: false,
+        clip_sprites: true,
+        display_wait: false,
+        key_release: false,
+        stack_depth: 16,
+    };
+
+    /// The behaviour of Octo, the reference implementation of XO-CHIP.
+    pub const XOCHIP: Self = Self {
+        shift_vy: true,
+        increment_i: true,
+        jump_vx: false,
+        vf_reset: false,
+        clip_sprites: false,
+        display_wait: false,
+        key_release: true,
+        stack_depth: 16,
+    };
+
+    /// The amount of nested subroutine calls which the backends allow, `stack_depth` can't be
+    /// bigger than the stack of [Chip8State](crate::chip8::Chip8State).
+    pub fn stack_limit(&self) -> u64 {
+        self.stack_depth.clamp(1, Chip8::MAX_AMOUNT_STACK as u64)
+    }
+
+    /// The size of [Quirks::to_bytes].
+    pub const ENCODED_SIZE: usize = 15;
+
+    /// Encodes the quirks for the files of the block cache and `rip8 aot`.
+    pub fn to_bytes(&self) -> [u8; Self::ENCODED_SIZE] {
+        let mut bytes = [0; Self::ENCODED_SIZE];
+        let flags = [
+            self.shift_vy,
+            self.increment_i,
+            self.jump_vx,
+            self.vf_reset,
+            self.clip_sprites,
+            self.display_wait,
+            self.key_release,
+        ];
+
+        for (byte, flag) in bytes.iter_mut().zip(flags) {
+            *byte = u8::from(flag);
+        }
+        bytes[7..].copy_from_slice(&self.stack_depth.to_le_bytes());
+
+        bytes
+    }
+
+    /// Decodes the quirks of [Quirks::to_bytes].
+    pub fn from_bytes(bytes: &[u8; Self::ENCODED_SIZE]) -> Self {
+        Self {
+            shift_vy: bytes[0] != 0,
+            increment_i: bytes[1] != 0,
+            jump_vx: bytes[2] != 0,
+            vf_reset: bytes[3] != 0,
+            clip_sprites: bytes[4] != 0,
+            display_wait: bytes[5] != 0,
+            key_release: bytes[6] != 0,
+            stack_depth: u64::from_le_bytes(bytes[7..].try_into().unwrap()),
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Platform::default().quirks()
+    }
+}
+
+/// Quirks which should be changed, independent of the chosen platform.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuirkOverrides {
+    pub shift_vy: Option<bool>,
+    pub increment_i: Option<bool>,
+    pub jump_vx: Option<bool>,
+    pub vf_reset: Option<bool>,
+    pub clip_sprites: Option<bool>,
+    pub display_wait: Option<bool>,
+    pub key_release: Option<bool>,
+    pub stack_depth: Option<u64>,
+}
+
+impl From<Quirks> for QuirkOverrides {
+    /// Overrides every quirk with the one of `quirks`.
+    fn from(quirks: Quirks) -> Self {
+        Self {
+            shift_vy: Some(quirks.shift_vy),
+            increment_i: Some(quirks.increment_i),
+            jump_vx: Some(quirks.jump_vx),
+            vf_reset: Some(quirks.vf_reset),
+            clip_sprites: Some(quirks.clip_sprites),
+            display_wait: Some(quirks.display_wait),
+            key_release: Some(quirks.key_release),
+            stack_depth: Some(quirks.stack_depth),
+        }
+    }
+}
+
+impl QuirkOverrides {
+    pub fn apply(&self, quirks: &mut Quirks) {
+        let fields = [
+            (self.shift_vy, &mut quirks.shift_vy),
+            (self.increment_i, &mut quirks.increment_i),
+            (self.jump_vx, &mut quirks.jump_vx),
+            (self.vf_reset, &mut quirks.vf_reset),
+            (self.clip_sprites, &mut quirks.clip_sprites),
+            (self.display_wait, &mut quirks.display_wait),
+            (self.key_release, &mut quirks.key_release),
+        ];
+
+        for (value, quirk) in fields {
+            if let Some(value) = value {
+                *quirk = value;
+            }
+        }
+
+        if let Some(stack_depth) = self.stack_depth {
+            quirks.stack_depth = stack_depth.clamp(1, Chip8::MAX_AMOUNT_STACK as u64);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Platform {
+    #[default]
+    Vip,
+    Schip,
+    XoChip,
+}
+
+impl Platform {
+    pub const NAMES: [&'static str; 3] = ["vip", "schip", "xochip"];
+
+    pub fn quirks(&self) -> Quirks {
+        match self {
+            Self::Vip => Quirks::VIP,
+            Self::Schip => Quirks::SCHIP,
+            Self::XoChip => Quirks::XOCHIP,
+        }
+    }
+}
+
+impl FromStr for Platform {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_lowercase().as_str() {
+            "vip" | "chip8" | "chip-8" => Ok(Self::Vip),
+            "schip" | "superchip" | "super-chip" => Ok(Self::Schip),
+            "xochip" | "xo-chip" => Ok(Self::XoChip),
+            _ => Err(format!("unknown platform '{}'", name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_platforms() {
+        assert_eq!("CHIP-8".parse(), Ok(Platform::Vip));
+        assert_eq!("superchip".parse(), Ok(Platform::Schip));
+        assert_eq!("XO-CHIP".parse(), Ok(Platform::XoChip));
+        assert!("megachip".parse::<Platform>().is_err());
+
+        for name in Platform::NAMES {
+            assert!(name.parse::<Platform>().is_ok());
+        }
+        assert_eq!(Quirks::default(), Quirks::VIP);
+        assert_eq!(Platform::Schip.quirks(), Quirks::SCHIP);
+    }
+
+    #[test]
+    fn applies_only_the_given_overrides() {
+        let overrides = QuirkOverrides {
+            jump_vx: Some(true),
+            clip_sprites: Some(false),
+            ..Default::default()
+        };
+        let mut quirks = Quirks::VIP;
+        overrides.apply(&mut quirks);
+
+        assert_eq!(
+            quirks,
+            Quirks {
+                jump_vx: true,
+                clip_sprites: false,
+                ..Quirks::VIP
+            }
+        );
+
+        let mut quirks = Quirks::VIP;
+        QuirkOverrides::from(Quirks::SCHIP).apply(&mut quirks);
+        assert_eq!(quirks, Quirks::SCHIP);
+    }
+
+    #[test]
+    fn clamps_the_stack_depth() {
+        let mut quirks = Quirks::VIP;
+        let overrides = |stack_depth| QuirkOverrides {
+            stack_depth: Some(stack_depth),
+            ..Default::default()
+        };
+
+        overrides(0).apply(&mut quirks);
+        assert_eq!(quirks.stack_depth, 1);
+        overrides(1000).apply(&mut quirks);
+        assert_eq!(quirks.stack_depth, Chip8::MAX_AMOUNT_STACK as u64);
+
+        // quirks which are built directly are only limited by the backends
+        quirks.stack_depth = 1000;
+        assert_eq!(quirks.stack_limit(), Chip8::MAX_AMOUNT_STACK as u64);
+        quirks.stack_depth = 0;
+        assert_eq!(quirks.stack_limit(), 1);
+    }
+
+    #[test]
+    fn round_trips_bytes() {
+        for quirks in [Quirks::VIP, Quirks::SCHIP, Quirks::XOCHIP] {
+            assert_eq!(Quirks::from_bytes(&quirks.to_bytes()), quirks);
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use fnv::FnvHashMap;
+use serde::Deserialize;
+use sha1::{Digest, Sha1};
+
+use crate::keymap::KeyBindings;
+use crate::palette::Color;
+use crate::quirks::{Platform, QuirkOverrides};
+use crate::{Error, Result};
+
+use std::fs::read_to_string;
+use std::path::Path;
+
+/// The database which is shipped with the emulator.
+const BUILTIN_DATABASE: &str = include_str!("rom_db.json");
+
+/// Metadata of a ROM which is needed to run it properly.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RomInfo {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub platform: Option<Platform>,
+    /// Quirks which differ from the ones of the platform.
+    pub quirks: QuirkOverrides,
+    /// The recommended amount of instructions per frame.
+    pub speed: Option<u64>,
+    /// Maps the CHIP-8 keys (`"0"` to `"F"`) to the names of keyboard keys, which replace the
+    /// ones of the preset.
+    pub keys: KeyBindings,
+    /// The colors of the pixel values as `#rrggbb`, starting with the background.
+    pub colors: Vec<Color>,
+}
+
+/// ROM metadata keyed by the SHA-1 hash of the ROM.
+///
+/// The JSON representation is an object which maps the hex encoded hashes to the [RomInfo]s.
+#[derive(Debug, Clone, Default)]
+pub struct RomDatabase {
+    entries: FnvHashMap<String, RomInfo>,
+}
+
+impl RomDatabase {
+    pub fn builtin() -> Self {
+        Self::from_json(BUILTIN_DATABASE).expect("The builtin ROM database is invalid")
+    }
+
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        let entries: FnvHashMap<String, RomInfo> = serde_json::from_str(json)?;
+
+        Ok(Self {
+            entries: entries
+                .into_iter()
+                .map(|(hash, info)| (hash.to_lowercase(), info))
+                .collect(),
+        })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let json = read_to_string(path).map_err(|source| Error::Io {
+            path: path.into(),
+            source,
+        })?;
+
+        Self::from_json(&json).map_err(|source| Error::RomDatabase {
+            path: path.into(),
+            source,
+        })
+    }
+
+    /// Adds the entries of `other`. Entries which exist in both databases are replaced.
+    pub fn extend(&mut self, other: Self) {
+        self.entries.extend(other.entries);
+    }
+
+    pub fn lookup(&self, rom: &[u8]) -> Option<&RomInfo> {
+        self.entries.get(&hash(rom))
+    }
+}
+
+/// Returns the hex encoded SHA-1 hash of `rom`.
+pub fn hash(rom: &[u8]) -> String {
+    Sha1::digest(rom)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keymap::{HexKey, KeyName};
+
+    const MAZE: &[u8] = include_bytes!("../Maze.ch8");
+    const PONG: &[u8] = include_bytes!("../pong.rom");
+    const TEST_OPCODE: &[u8] = include_bytes!("../test_opcode.ch8");
+
+    #[test]
+    fn finds_the_bundled_roms() {
+        let database = RomDatabase::builtin();
+
+        let maze = database.lookup(MAZE).unwrap();
+        assert_eq!(maze.title.as_deref(), Some("Maze"));
+        assert_eq!(maze.platform, Some(Platform::Vip));
+        assert_eq!(maze.speed, Some(15));
+
+        assert_eq!(
+            database.lookup(PONG).unwrap().title.as_deref(),
+            Some("Pong")
+        );
+        assert_eq!(database.lookup(TEST_OPCODE).unwrap().speed, Some(30));
+        assert!(database.lookup(&[0x12, 0x00]).is_none());
+    }
+
+    #[test]
+    fn hashes_roms() {
+        assert_eq!(hash(b""), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(hash(MAZE), "8b70080adbac44513ec60005734a816372b845ec");
+    }
+
+    #[test]
+    fn user_entries_replace_builtin_ones() {
+        let json = r##"{
+            "8B70080ADBAC44513EC60005734A816372B845EC": {
+                "platform": "schip",
+                "quirks": { "vf_reset": true },
+                "keys": { "a": ["Space", "Enter"] },
+                "colors": ["#000000", "#ffffff"]
+            },
+            "da39a3ee5e6b4b0d3255bfef95601890afd80709": { "title": "Nothing" }
+        }"##;
+        let mut database = RomDatabase::builtin();
+        database.extend(RomDatabase::from_json(json).unwrap());
+
+        // the entry is replaced as a whole, it isn't merged
+        let maze = database.lookup(MAZE).unwrap();
+        assert_eq!(maze.title, None);
+        assert_eq!(maze.platform, Some(Platform::Schip));
+        assert_eq!(maze.quirks.vf_reset, Some(true));
+        assert_eq!(
+            maze.keys[&HexKey(0xa)],
+            [KeyName(minifb::Key::Space), KeyName(minifb::Key::Enter)]
+        );
+        assert_eq!(maze.colors.len(), 2);
+
+        assert_eq!(
+            database.lookup(b"").unwrap().title.as_deref(),
+            Some("Nothing")
+        );
+        assert!(database.lookup(PONG).is_some());
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        assert!(RomDatabase::from_json(r#"{ "00": { "speeed": 10 } }"#).is_err());
+        assert!(RomDatabase::from_json(r#"{ "00": { "platform": "megachip" } }"#).is_err());
+    }
+}
@@ -0,0 +1,230 @@
+use crate::chip8::{
+    Chip8, Chip8State, FB_SIZEusize, AMOUNT_KEYS, AMOUNT_PLANES, AUDIO_PATTERN_SIZE,
+};
+use crate::{Error, Result};
+
+const MAGIC: &[u8; 4] = b"RIP8";
+/// Has to be increased whenever the layout changes.
+const VERSION: u8 = 3;
+/// Stored instead of [Chip8::NO_KEY].
+const NO_KEY: u8 = u8::MAX;
+/// The bits of all XO-CHIP planes.
+const ALL_PLANES: u8 = (1 << AMOUNT_PLANES) - 1;
+
+/// Serializes everything of `state` which the emulated program can observe. The configuration,
+/// for example the quirks, isn't part of it.
+pub fn save(state: &Chip8State) -> Vec<u8> {
+    let mut data = Vec::with_capacity(Chip8::MEM_SIZE + FB_SIZEusize + 256);
+
+    data.extend_from_slice(MAGIC);
+    data.push(VERSION);
+    data.extend_from_slice(&state.mem);
+    data.extend(state.regs.iter().map(|&reg| reg as u8));
+    data.extend_from_slice(&(state.i as u16).to_le_bytes());
+    data.extend_from_slice(&(state.pc as u16).to_le_bytes());
+    data.push(state.sp as u8);
+    for &addr in &state.stack {
+        data.extend_from_slice(&(addr as u16).to_le_bytes());
+    }
+    data.push(state.delay as u8);
+    data.push(state.sound as u8);
+    data.extend_from_slice(&state.fb);
+    data.push(state.planes as u8);
+    data.push(u8::from(state.hires));
+    data.extend_from_slice(&state.audio_pattern);
+    data.push(state.pitch as u8);
+    data.extend(state.flags.iter().map(|&flag| flag as u8));
+    data.extend(state.keys.iter().map(|&key| u8::from(key)));
+    data.extend(state.previous_keys.iter().map(|&key| u8::from(key)));
+    data.push(state.pressed_key as u8);
+
+    data
+}
+
+/// Restores a state which got created by [save]. `state` is only changed if `data` is valid.
+pub fn load(state: &mut Chip8State, data: &[u8]) -> Result<()> {
+    let mut reader = Reader { data };
+
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err(Error::SaveState("not a save state"));
+    }
+    if reader.byte()? != VERSION {
+        return Err(Error::SaveState("unsupported version"));
+    }
+
+    let mem = reader.take(Chip8::MEM_SIZE)?;
+    let regs = reader.take(Chip8::AMOUNT_REGISTERS)?;
+    let i = reader.word()?;
+    let pc = reader.word()?;
+    let sp = reader.byte()?;
+    let mut stack = [0; Chip8::MAX_AMOUNT_STACK];
+    for addr in stack.iter_mut() {
+        *addr = reader.word()?;
+    }
+    let delay = reader.byte()?;
+    let sound = reader.byte()?;
+    let fb = reader.take(FB_SIZEusize)?;
+    let planes = reader.byte()?;
+    let hires = reader.byte()?;
+    let audio_pattern = reader.take(AUDIO_PATTERN_SIZE)?;
+    let pitch = reader.byte()?;
+    let flags = reader.take(Chip8::AMOUNT_REGISTERS)?;
+    let keys = reader.take(AMOUNT_KEYS)?;
+    let previous_keys = reader.take(AMOUNT_KEYS)?;
+    let pressed_key = match reader.byte()? {
+        NO_KEY => Chip8::NO_KEY,
+        key if usize::from(key) < AMOUNT_KEYS => u64::from(key),
+        _ => return Err(Error::SaveState("invalid pressed key")),
+    };
+
+    if !reader.data.is_empty() {
+        return Err(Error::SaveState("trailing data"));
+    }
+    if usize::from(sp) > Chip8::MAX_AMOUNT_STACK {
+        return Err(Error::SaveState("invalid stack pointer"));
+    }
+    // the pixels store one bit per plane and index the palette
+    if planes > ALL_PLANES || fb.iter().any(|&pixel| pixel > ALL_PLANES) {
+        return Err(Error::SaveState("invalid planes"));
+    }
+
+    state.mem.copy_from_slice(mem);
+    for (reg, &value) in state.regs.iter_mut().zip(regs) {
+        *reg = u64::from(value);
+    }
+    state.i = i;
+    state.pc = pc;
+    state.sp = u64::from(sp);
+    state.stack = stack;
+    state.delay = u64::from(delay);
+    state.sound = u64::from(sound);
+    state.fb.copy_from_slice(fb);
+    state.planes = u64::from(planes);
+    state.hires = hires != 0;
+    state.audio_pattern.copy_from_slice(audio_pattern);
+    state.pitch = u64::from(pitch);
+    for (flag, &value) in state.flags.iter_mut().zip(flags) {
+        *flag = u64::from(value);
+    }
+    for (key, &value) in state.keys.iter_mut().zip(keys) {
+        *key = value != 0;
+    }
+    for (key, &value) in state.previous_keys.iter_mut().zip(previous_keys) {
+        *key = value != 0;
+    }
+    state.pressed_key = pressed_key;
+    state.wait_for_frame = false;
+    state.fault = 0;
+    state.should_run = true;
+
+    Ok(())
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, amount: usize) -> Result<&'a [u8]> {
+        if self.data.len() < amount {
+            return Err(Error::SaveState("truncated data"));
+        }
+
+        let (taken, rest) = self.data.split_at(amount);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn word(&mut self) -> Result<u64> {
+        let bytes = self.take(2)?;
+        Ok(u64::from(u16::from_le_bytes([bytes[0], bytes[1]])))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Keys;
+    use crate::quirks::Quirks;
+
+    #[rustfmt::skip]
+    const ROM: [u8; 10] = [
+        0x6a, 0x42, // LD VA, 0x42
+        0xd0, 0x05, // DRW V0, V0, 5, continues in the next frame
+        0xf0, 0x0a, // LD V0, K
+        0xd0, 0x05, // DRW V0, V0, 5
+        0x12, 0x08, // JP 0x208
+    ];
+
+    fn chip8() -> Chip8 {
+        Chip8::builder()
+            .rom(ROM)
+            .quirks(Quirks::VIP)
+            .build()
+            .unwrap()
+    }
+
+    fn error(data: &[u8]) -> &'static str {
+        match chip8().load_state(data) {
+            Err(Error::SaveState(reason)) => reason,
+            result => panic!("loaded an invalid state: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let held = |keys: &[u8]| Keys(keys.iter().map(|key| 1 << key).sum());
+        // key 5 is held across the first save, so FX0A ignores it. Key 3 gets pressed next and
+        // is stored once it's released, the second save happens while FX0A waits for that.
+        let frames = [held(&[5]), held(&[5, 3]), held(&[5]), held(&[])];
+
+        let mut original = chip8();
+        original.step_frame(frames[0]).unwrap();
+        for &keys in &frames[1..] {
+            let state = original.save_state();
+            let mut restored = chip8();
+            restored.load_state(&state).unwrap();
+            assert_eq!(restored.save_state(), state);
+
+            original.step_frame(keys).unwrap();
+            restored.step_frame(keys).unwrap();
+            assert_eq!(restored.registers(), original.registers());
+            assert_eq!(restored.framebuffer(), original.framebuffer());
+        }
+
+        assert_eq!(original.registers().v[0], 3);
+        assert_eq!(original.registers().v[0xa], 0x42);
+    }
+
+    #[test]
+    fn rejects_invalid_states() {
+        let state = chip8().save_state();
+
+        assert_eq!(error(b"RIP9"), "not a save state");
+        assert_eq!(error(&state[..state.len() - 1]), "truncated data");
+        assert_eq!(error(&[&state[..], &[0]].concat()), "trailing data");
+
+        let mut version = state.clone();
+        version[MAGIC.len()] = VERSION + 1;
+        assert_eq!(error(&version), "unsupported version");
+
+        let mut pressed_key = state.clone();
+        *pressed_key.last_mut().unwrap() = AMOUNT_KEYS as u8;
+        assert_eq!(error(&pressed_key), "invalid pressed key");
+
+        // the pixels follow the framebuffer, the planes follow the pixels
+        let fb = MAGIC.len() + 1 + Chip8::MEM_SIZE + Chip8::AMOUNT_REGISTERS + 5;
+        let fb = fb + 2 * Chip8::MAX_AMOUNT_STACK + 2;
+        let mut pixel = state.clone();
+        pixel[fb + 10] = 7;
+        assert_eq!(error(&pixel), "invalid planes");
+
+        let mut planes = state.clone();
+        planes[fb + FB_SIZEusize] = 4;
+        assert_eq!(error(&planes), "invalid planes");
+    }
+}
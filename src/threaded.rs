@@ -0,0 +1,389 @@
+//! Pre-decodes blocks into closures which are called one after another. It's slower than the
+//! compiling backends but doesn't need executable memory, which some hardened systems forbid.
+
+use log::debug;
+
+use crate::cache::CompileBlock;
+use crate::chip8::{self, Chip8, Chip8State};
+use crate::error::{Error, Fault, Result};
+use crate::instruction::Instruction;
+use crate::jit::fn_extern;
+use crate::quirks::Quirks;
+use crate::Addr;
+
+/// A single pre-decoded instruction, which also updates pc.
+pub type Operation = Box<dyn Fn(&mut Chip8State) + Send>;
+
+/// Decodes the block which starts at the pc of `state`, the block ends at the same instructions
+/// as the ones of the compiling backends.
+pub fn compile(state: &Chip8State) -> Result<CompileBlock> {
+    let mut operations = Vec::new();
+    let mut addr = state.pc;
+
+    loop {
+        let instruction =
+            Instruction::decode(&state.mem, addr).ok_or_else(|| Error::UnknownInstruction {
+                addr,
+                instruction: chip8::read_word(&state.mem, addr),
+            })?;
+        debug!("Decoding '{}' at {:#x}", instruction, addr);
+
+        let next = addr + chip8::instruction_size(&state.mem, addr);
+        let skipped = next + chip8::instruction_size(&state.mem, next);
+        let (operation, continues) = decode(instruction, next, skipped, state.quirks);
+        operations.push(operation);
+
+        addr = next;
+        if !continues {
+            break;
+        }
+    }
+
+    Ok(CompileBlock::threaded(operations, state.pc..addr))
+}
+
+/// Returns the operation of `instruction` and whether the block continues after it. `next` is the
+/// address of the following instruction and `skipped` the one after it.
+fn decode(
+    instruction: Instruction,
+    next: Addr,
+    skipped: Addr,
+    quirks: Quirks,
+) -> (Operation, bool) {
+    // sets pc to the next instruction after `f`
+    fn then_next(next: Addr, f: impl Fn(&mut Chip8State) + Send + 'static) -> Operation {
+        Box::new(move |state| {
+            f(state);
+            state.pc = next;
+        })
+    }
+
+    // skips the next instruction if `condition` holds
+    fn skip_if(
+        next: Addr,
+        skipped: Addr,
+        condition: impl Fn(&Chip8State) -> bool + Send + 'static,
+    ) -> Operation {
+        Box::new(move |state| {
+            state.pc = if condition(state) { skipped } else { next };
+        })
+    }
+
+    // sets Vx and Vf, Vf is stored last in case Vx is Vf
+    fn set_with_flag(state: &mut Chip8State, x: u8, value: u64, flag: bool) {
+        state.regs[usize::from(x)] = value & 0xff;
+        state.regs[0xf] = u64::from(flag);
+    }
+
+    let logic_result = move |state: &mut Chip8State, x: u8, value: u64| {
+        state.regs[usize::from(x)] = value;
+        if quirks.vf_reset {
+            state.regs[0xf] = 0;
+        }
+    };
+
+    let increment_i = move |state: &mut Chip8State, x: u8| {
+        if quirks.increment_i {
+            state.i = (state.i + u64::from(x) + 1) & Chip8::ADDR_MASK;
+        }
+    };
+
+    let shift_source = move |x: u8, y: u8| usize::from(if quirks.shift_vy { y } else { x });
+
+    match instruction {
+        Instruction::Scd(n) => (
+            then_next(next, move |state| unsafe {
+                fn_extern::scd(state, n.into())
+            }),
+            true,
+        ),
+        Instruction::Scu(n) => (
+            then_next(next, move |state| unsafe {
+                fn_extern::scu(state, n.into())
+            }),
+            true,
+        ),
+        Instruction::Cls => (
+            then_next(next, |state| unsafe { fn_extern::cls(state) }),
+            true,
+        ),
+        Instruction::Ret => (
+            Box::new(|state| {
+                // pc stays at this instruction if the stack is empty
+                if state.sp == 0 {
+                    state.fault = Fault::StackUnderflow as u64;
+                    return;
+                }
+                state.sp -= 1;
+                state.pc = state.stack[state.sp as usize];
+            }),
+            false,
+        ),
+        Instruction::Scr => (
+            then_next(next, |state| unsafe { fn_extern::scr(state) }),
+            true,
+        ),
+        Instruction::Scl => (
+            then_next(next, |state| unsafe { fn_extern::scl(state) }),
+            true,
+        ),
+        Instruction::Exit => (
+            then_next(next, |state| unsafe { fn_extern::exit(state) }),
+            false,
+        ),
+        Instruction::Low => (
+            then_next(next, |state| unsafe { fn_extern::low(state) }),
+            true,
+        ),
+        Instruction::High => (
+            then_next(next, |state| unsafe { fn_extern::high(state) }),
+            true,
+        ),
+        // machine code routines of the COSMAC VIP can't be executed
+        Instruction::Sys(_) => (then_next(next, |_| ()), false),
+        Instruction::Jp(nnn) => (Box::new(move |state| state.pc = u64::from(nnn)), false),
+        Instruction::Call(nnn) => (
+            Box::new(move |state| {
+                // pc stays at this instruction if the stack is full
+                if state.sp >= quirks.stack_limit() {
+                    state.fault = Fault::StackOverflow as u64;
+                    return;
+                }
+                state.stack[state.sp as usize] = next;
+                state.sp += 1;
+                state.pc = u64::from(nnn);
+            }),
+            false,
+        ),
+        Instruction::SeByte(x, kk) => (
+            skip_if(next, skipped, move |state| {
+                state.regs[usize::from(x)] == u64::from(kk)
+            }),
+            false,
+        ),
+        Instruction::SneByte(x, kk) => (
+            skip_if(next, skipped, move |state| {
+                state.regs[usize::from(x)] != u64::from(kk)
+            }),
+            false,
+        ),
+        Instruction::SeReg(x, y) => (
+            skip_if(next, skipped, move |state| {
+                state.regs[usize::from(x)] == state.regs[usize::from(y)]
+            }),
+            false,
+        ),
+        Instruction::SneReg(x, y) => (
+            skip_if(next, skipped, move |state| {
+                state.regs[usize::from(x)] != state.regs[usize::from(y)]
+            }),
+            false,
+        ),
+        Instruction::SaveRange(x, y) => (
+            then_next(next, move |state| unsafe {
+                fn_extern::ld_i_range(state, x.into(), y.into())
+            }),
+            true,
+        ),
+        Instruction::LoadRange(x, y) => (
+            then_next(next, move |state| unsafe {
+                fn_extern::ld_range_i(state, x.into(), y.into())
+            }),
+            true,
+        ),
+        Instruction::LdByte(x, kk) => (
+            then_next(next, move |state| state.regs[usize::from(x)] = kk.into()),
+            true,
+        ),
+        Instruction::AddByte(x, kk) => (
+            then_next(next, move |state| {
+                let vx = &mut state.regs[usize::from(x)];
+                *vx = (*vx + u64::from(kk)) & 0xff;
+            }),
+            true,
+        ),
+        Instruction::LdReg(x, y) => (
+            then_next(next, move |state| {
+                state.regs[usize::from(x)] = state.regs[usize::from(y)];
+            }),
+            true,
+        ),
+        Instruction::Or(x, y) => (
+            then_next(next, move |state| {
+                let value = state.regs[usize::from(x)] | state.regs[usize::from(y)];
+                logic_result(state, x, value);
+            }),
+            true,
+        ),
+        Instruction::And(x, y) => (
+            then_next(next, move |state| {
+                let value = state.regs[usize::from(x)] & state.regs[usize::from(y)];
+                logic_result(state, x, value);
+            }),
+            true,
+        ),
+        Instruction::Xor(x, y) => (
+            then_next(next, move |state| {
+                let value = state.regs[usize::from(x)] ^ state.regs[usize::from(y)];
+                logic_result(state, x, value);
+            }),
+            true,
+        ),
+        Instruction::AddReg(x, y) => (
+            then_next(next, move |state| {
+                let sum = state.regs[usize::from(x)] + state.regs[usize::from(y)];
+                set_with_flag(state, x, sum, sum > 0xff);
+            }),
+            true,
+        ),
+        Instruction::Sub(x, y) => (
+            then_next(next, move |state| {
+                let (vx, vy) = (state.regs[usize::from(x)], state.regs[usize::from(y)]);
+                set_with_flag(state, x, vx.wrapping_sub(vy), vx >= vy);
+            }),
+            true,
+        ),
+        Instruction::Subn(x, y) => (
+            then_next(next, move |state| {
+                let (vx, vy) = (state.regs[usize::from(x)], state.regs[usize::from(y)]);
+                set_with_flag(state, x, vy.wrapping_sub(vx), vy >= vx);
+            }),
+            true,
+        ),
+        Instruction::Shr(x, y) => (
+            then_next(next, move |state| {
+                let source = state.regs[shift_source(x, y)];
+                set_with_flag(state, x, source >> 1, source & 1 != 0);
+            }),
+            true,
+        ),
+        Instruction::Shl(x, y) => (
+            then_next(next, move |state| {
+                let source = state.regs[shift_source(x, y)];
+                set_with_flag(state, x, source << 1, source & 0x80 != 0);
+            }),
+            true,
+        ),
+        Instruction::LdI(nnn) => (then_next(next, move |state| state.i = nnn.into()), true),
+        Instruction::JpV0(nnn) => {
+            let reg = if quirks.jump_vx {
+                usize::from((nnn >> 8) & 0xf)
+            } else {
+                0
+            };
+            (
+                Box::new(move |state| state.pc = state.regs[reg] + u64::from(nnn)),
+                false,
+            )
+        }
+        Instruction::Rnd(x, kk) => (
+            then_next(next, move |state| unsafe {
+                fn_extern::rnd(state, x.into(), kk.into())
+            }),
+            true,
+        ),
+        Instruction::Drw(x, y, n) => (
+            then_next(next, move |state| unsafe {
+                fn_extern::drw(state, x.into(), y.into(), n.into())
+            }),
+            // the block has to end to be able to wait for the next frame
+            !quirks.display_wait,
+        ),
+        // the helpers add the size of the next instruction to pc if it's skipped
+        Instruction::Skp(x) => (
+            Box::new(move |state| {
+                let pc = state.pc;
+                unsafe { fn_extern::skp(state, x.into()) };
+                state.pc += next - pc;
+            }),
+            false,
+        ),
+        Instruction::Sknp(x) => (
+            Box::new(move |state| {
+                let pc = state.pc;
+                unsafe { fn_extern::sknp(state, x.into()) };
+                state.pc += next - pc;
+            }),
+            false,
+        ),
+        Instruction::Plane(n) => (
+            then_next(next, move |state| state.planes = u64::from(n & 0x3)),
+            true,
+        ),
+        Instruction::Audio => (
+            then_next(next, |state| unsafe { fn_extern::audio(state) }),
+            true,
+        ),
+        Instruction::LdXDt(x) => (
+            then_next(next, move |state| state.regs[usize::from(x)] = state.delay),
+            true,
+        ),
+        // ld_k sets pc itself, it stays here while no key is pressed
+        Instruction::LdK(x) => (
+            Box::new(move |state| unsafe { fn_extern::ld_k(state, x.into()) }),
+            false,
+        ),
+        Instruction::LdDtX(x) => (
+            then_next(next, move |state| state.delay = state.regs[usize::from(x)]),
+            true,
+        ),
+        Instruction::LdSt(x) => (
+            then_next(next, move |state| state.sound = state.regs[usize::from(x)]),
+            true,
+        ),
+        Instruction::AddI(x) => (
+            then_next(next, move |state| {
+                state.i = (state.i + state.regs[usize::from(x)]) & Chip8::ADDR_MASK;
+            }),
+            true,
+        ),
+        Instruction::LdF(x) => (
+            then_next(next, move |state| unsafe {
+                fn_extern::ld_f(state, x.into())
+            }),
+            true,
+        ),
+        Instruction::LdHf(x) => (
+            then_next(next, move |state| unsafe {
+                fn_extern::ld_hf(state, x.into())
+            }),
+            true,
+        ),
+        Instruction::LdB(x) => (
+            then_next(next, move |state| unsafe {
+                fn_extern::ld_b(state, x.into())
+            }),
+            true,
+        ),
+        Instruction::Pitch(x) => (
+            then_next(next, move |state| state.pitch = state.regs[usize::from(x)]),
+            true,
+        ),
+        Instruction::LdIX(x) => (
+            then_next(next, move |state| {
+                unsafe { fn_extern::ld_i_range(state, 0, x.into()) };
+                increment_i(state, x);
+            }),
+            true,
+        ),
+        Instruction::LdXI(x) => (
+            then_next(next, move |state| {
+                unsafe { fn_extern::ld_range_i(state, 0, x.into()) };
+                increment_i(state, x);
+            }),
+            true,
+        ),
+        Instruction::LdRX(x) => (
+            then_next(next, move |state| unsafe {
+                fn_extern::ld_r_x(state, x.into())
+            }),
+            true,
+        ),
+        Instruction::LdXR(x) => (
+            then_next(next, move |state| unsafe {
+                fn_extern::ld_x_r(state, x.into())
+            }),
+            true,
+        ),
+    }
+}
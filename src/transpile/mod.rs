@@ -0,0 +1,368 @@
+//! Translates a ROM into Rust source code with a function for every block which is reachable
+//! from the start address. The blocks are the states of the program, every function sets pc to
+//! the block which runs next. The quirks are resolved while translating, like in the backends.
+
+pub mod runtime;
+
+use crate::cfg::Cfg;
+use crate::chip8::{self, Chip8};
+use crate::instruction::Instruction;
+use crate::quirks::Quirks;
+use crate::{Addr, Result};
+
+use std::fmt::{self, Write};
+
+/// Returns the source code of a program which runs `rom` with the `rip8` crate, `name` is
+/// mentioned in the header.
+pub fn transpile(rom: &[u8], quirks: Quirks, speed: u64, name: &str) -> Result<String> {
+    let chip8 = Chip8::new(rom.to_vec(), quirks)?;
+    let mem = chip8.memory();
+
+    let mut source = String::new();
+    write_program(&mut source, mem, rom, quirks, speed, name)
+        .expect("writing to a String can't fail");
+
+    Ok(source)
+}
+
+fn write_program(
+    out: &mut String,
+    mem: &[u8],
+    rom: &[u8],
+    quirks: Quirks,
+    speed: u64,
+    name: &str,
+) -> fmt::Result {
+    let blocks: Vec<(Addr, Vec<(Addr, Instruction)>)> = Cfg::analyze(mem, rom.len(), &quirks)
+        .compiled_blocks()
+        .collect();
+
+    writeln!(out, "//! Generated by `rip8 transpile` from '{}'.", name)?;
+    writeln!(out)?;
+    writeln!(out, "use rip8::chip8::Chip8State;")?;
+    writeln!(out, "use rip8::quirks::Quirks;")?;
+    writeln!(
+        out,
+        "use rip8::transpile::runtime::{{self, Block, Program}};"
+    )?;
+    writeln!(out)?;
+
+    writeln!(out, "pub const PROGRAM: Program = Program {{")?;
+    writeln!(out, "    rom: &ROM,")?;
+    writeln!(out, "    quirks: Quirks {{")?;
+    writeln!(out, "        shift_vy: {},", quirks.shift_vy)?;
+    writeln!(out, "        increment_i: {},", quirks.increment_i)?;
+    writeln!(out, "        jump_vx: {},", quirks.jump_vx)?;
+    writeln!(out, "        vf_reset: {},", quirks.vf_reset)?;
+    writeln!(out, "        clip_sprites: {},", quirks.clip_sprites)?;
+    writeln!(out, "        display_wait: {},", quirks.display_wait)?;
+    writeln!(out, "        key_release: {},", quirks.key_release)?;
+    writeln!(out, "        stack_depth: {},", quirks.stack_depth)?;
+    writeln!(out, "    }},")?;
+    writeln!(out, "    speed: {},", speed)?;
+    writeln!(out, "    blocks: &BLOCKS,")?;
+    writeln!(out, "}};")?;
+    writeln!(out)?;
+
+    writeln!(out, "const ROM: [u8; {}] = [", rom.len())?;
+    for line in rom.chunks(12) {
+        let bytes: Vec<String> = line.iter().map(|byte| format!("{:#04x},", byte)).collect();
+        writeln!(out, "    {}", bytes.join(" "))?;
+    }
+    writeln!(out, "];")?;
+    writeln!(out)?;
+
+    writeln!(out, "const BLOCKS: [Block; {}] = [", blocks.len())?;
+    for (start, instructions) in &blocks {
+        let end = instructions.last().map_or(*start, |&(addr, _)| {
+            addr + chip8::instruction_size(mem, addr)
+        });
+        writeln!(out, "    Block {{")?;
+        writeln!(out, "        addrs: {:#x}..{:#x},", start, end)?;
+        writeln!(out, "        instructions: {},", instructions.len())?;
+        writeln!(out, "        function: block_{:x},", start)?;
+        writeln!(out, "    }},")?;
+    }
+    writeln!(out, "];")?;
+    writeln!(out)?;
+
+    writeln!(out, "fn main() {{")?;
+    writeln!(
+        out,
+        "    if let Err(err) = rip8::run_program(&PROGRAM, rip8::Options::default()) {{"
+    )?;
+    writeln!(out, "        eprintln!(\"error: {{}}\", err);")?;
+    writeln!(out, "        std::process::exit(1);")?;
+    writeln!(out, "    }}")?;
+    writeln!(out, "}}")?;
+
+    for (start, instructions) in &blocks {
+        writeln!(out)?;
+        writeln!(out, "fn block_{:x}(state: &mut Chip8State) {{", start)?;
+        for &(addr, instruction) in instructions {
+            let next = addr + chip8::instruction_size(mem, addr);
+            let skipped = next + chip8::instruction_size(mem, next);
+
+            writeln!(out, "    // {:#x}: {}", addr, instruction)?;
+            for line in statements(instruction, addr, next, skipped, &quirks) {
+                writeln!(out, "    {}", line)?;
+            }
+        }
+        writeln!(out, "}}")?;
+    }
+
+    Ok(())
+}
+
+/// Returns the lines of Rust code of `instruction` at `addr`. `next` is the address of the
+/// following instruction and `skipped` the one after it. Only the instructions which end a block
+/// set pc.
+fn statements(
+    instruction: Instruction,
+    addr: Addr,
+    next: Addr,
+    skipped: Addr,
+    quirks: &Quirks,
+) -> Vec<String> {
+    let v = |x: u8| format!("state.regs[{:#x}]", x);
+    let skip_if = |condition: String| {
+        vec![format!(
+            "state.pc = if {} {{ {:#x} }} else {{ {:#x} }};",
+            condition, skipped, next
+        )]
+    };
+    let logic = |x: u8, y: u8, operator: &str| {
+        let mut lines = vec![format!("{} {}= {};", v(x), operator, v(y))];
+        if quirks.vf_reset {
+            lines.push(format!("{} = 0;", v(0xf)));
+        }
+        lines
+    };
+    let shift_source = |x: u8, y: u8| v(if quirks.shift_vy { y } else { x });
+    let increment_i = |x: u8| {
+        quirks.increment_i.then(|| {
+            format!(
+                "state.i = (state.i + {}) & {:#x};",
+                u64::from(x) + 1,
+                Chip8::ADDR_MASK
+            )
+        })
+    };
+
+    match instruction {
+        Instruction::Scd(n) => vec![format!("runtime::scd(state, {:#x});", n)],
+        Instruction::Scu(n) => vec![format!("runtime::scu(state, {:#x});", n)],
+        Instruction::Cls => vec!["runtime::cls(state);".into()],
+        Instruction::Ret => vec![format!("runtime::ret(state, {:#x});", addr)],
+        Instruction::Scr => vec!["runtime::scr(state);".into()],
+        Instruction::Scl => vec!["runtime::scl(state);".into()],
+        Instruction::Exit => vec![
+            "runtime::exit(state);".into(),
+            format!("state.pc = {:#x};", next),
+        ],
+        Instruction::Low => vec!["runtime::low(state);".into()],
+        Instruction::High => vec!["runtime::high(state);".into()],
+        Instruction::Sys(_) => vec![
+            "// machine code routines of the COSMAC VIP can't be executed".into(),
+            format!("state.pc = {:#x};", next),
+        ],
+        Instruction::Jp(nnn) => vec![format!("state.pc = {:#x};", nnn)],
+        Instruction::Call(nnn) => vec![format!(
+            "runtime::call(state, {:#x}, {:#x}, {:#x});",
+            addr, nnn, next
+        )],
+        Instruction::SeByte(x, kk) => skip_if(format!("{} == {:#x}", v(x), kk)),
+        Instruction::SneByte(x, kk) => skip_if(format!("{} != {:#x}", v(x), kk)),
+        Instruction::SeReg(x, y) => skip_if(format!("{} == {}", v(x), v(y))),
+        Instruction::SneReg(x, y) => skip_if(format!("{} != {}", v(x), v(y))),
+        Instruction::SaveRange(x, y) => {
+            vec![format!("runtime::ld_i_range(state, {:#x}, {:#x});", x, y)]
+        }
+        Instruction::LoadRange(x, y) => {
+            vec![format!("runtime::ld_range_i(state, {:#x}, {:#x});", x, y)]
+        }
+        Instruction::LdByte(x, kk) => vec![format!("{} = {:#x};", v(x), kk)],
+        Instruction::AddByte(x, kk) => {
+            vec![format!("{} = ({} + {:#x}) & 0xff;", v(x), v(x), kk)]
+        }
+        Instruction::LdReg(x, y) => vec![format!("{} = {};", v(x), v(y))],
+        Instruction::Or(x, y) => logic(x, y, "|"),
+        Instruction::And(x, y) => logic(x, y, "&"),
+        Instruction::Xor(x, y) => logic(x, y, "^"),
+        // Vf is stored last in case Vx is Vf
+        Instruction::AddReg(x, y) => vec![
+            format!("let sum = {} + {};", v(x), v(y)),
+            format!("{} = sum & 0xff;", v(x)),
+            format!("{} = u64::from(sum > 0xff);", v(0xf)),
+        ],
+        Instruction::Sub(x, y) => vec![
+            format!("let (vx, vy) = ({}, {});", v(x), v(y)),
+            format!("{} = vx.wrapping_sub(vy) & 0xff;", v(x)),
+            format!("{} = u64::from(vx >= vy);", v(0xf)),
+        ],
+        Instruction::Subn(x, y) => vec![
+            format!("let (vx, vy) = ({}, {});", v(x), v(y)),
+            format!("{} = vy.wrapping_sub(vx) & 0xff;", v(x)),
+            format!("{} = u64::from(vy >= vx);", v(0xf)),
+        ],
+        Instruction::Shr(x, y) => vec![
+            format!("let source = {};", shift_source(x, y)),
+            format!("{} = source >> 1;", v(x)),
+            format!("{} = source & 1;", v(0xf)),
+        ],
+        Instruction::Shl(x, y) => vec![
+            format!("let source = {};", shift_source(x, y)),
+            format!("{} = (source << 1) & 0xff;", v(x)),
+            format!("{} = source >> 7;", v(0xf)),
+        ],
+        Instruction::LdI(nnn) => vec![format!("state.i = {:#x};", nnn)],
+        Instruction::JpV0(nnn) => {
+            let reg = if quirks.jump_vx {
+                (nnn >> 8) as u8 & 0xf
+            } else {
+                0
+            };
+            vec![format!("state.pc = {} + {:#x};", v(reg), nnn)]
+        }
+        Instruction::Rnd(x, kk) => vec![format!("runtime::rnd(state, {:#x}, {:#x});", x, kk)],
+        Instruction::Drw(x, y, n) => {
+            let mut lines = vec![format!(
+                "runtime::drw(state, {:#x}, {:#x}, {:#x});",
+                x, y, n
+            )];
+            // the block ends to wait for the next frame
+            if quirks.display_wait {
+                lines.push(format!("state.pc = {:#x};", next));
+            }
+            lines
+        }
+        Instruction::Skp(x) => skip_if(format!("runtime::is_pressed(state, {:#x})", x)),
+        Instruction::Sknp(x) => skip_if(format!("!runtime::is_pressed(state, {:#x})", x)),
+        Instruction::Plane(n) => vec![format!("state.planes = {:#x};", n & 0x3)],
+        Instruction::Audio => vec!["runtime::audio(state);".into()],
+        Instruction::LdXDt(x) => vec![format!("{} = state.delay;", v(x))],
+        // pc stays at this instruction while no key is pressed
+        Instruction::LdK(x) => vec![
+            format!("state.pc = {:#x};", addr),
+            format!("runtime::ld_k(state, {:#x});", x),
+        ],
+        Instruction::LdDtX(x) => vec![format!("state.delay = {};", v(x))],
+        Instruction::LdSt(x) => vec![format!("state.sound = {};", v(x))],
+        Instruction::AddI(x) => vec![format!(
+            "state.i = (state.i + {}) & {:#x};",
+            v(x),
+            Chip8::ADDR_MASK
+        )],
+        Instruction::LdF(x) => vec![format!("runtime::ld_f(state, {:#x});", x)],
+        Instruction::LdHf(x) => vec![format!("runtime::ld_hf(state, {:#x});", x)],
+        Instruction::LdB(x) => vec![format!("runtime::ld_b(state, {:#x});", x)],
+        Instruction::Pitch(x) => vec![format!("state.pitch = {};", v(x))],
+        Instruction::LdIX(x) => {
+            std::iter::once(format!("runtime::ld_i_range(state, 0x0, {:#x});", x))
+                .chain(increment_i(x))
+                .collect()
+        }
+        Instruction::LdXI(x) => {
+            std::iter::once(format!("runtime::ld_range_i(state, 0x0, {:#x});", x))
+                .chain(increment_i(x))
+                .collect()
+        }
+        Instruction::LdRX(x) => vec![format!("runtime::ld_r_x(state, {:#x});", x)],
+        Instruction::LdXR(x) => vec![format!("runtime::ld_x_r(state, {:#x});", x)],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rustfmt::skip]
+    const ROM: [u8; 10] = [
+        0x61, 0x05, // 0x200: LD V1, 0x5
+        0x31, 0x05, // 0x202: SE V1, 0x5
+        0x12, 0x00, // 0x204: JP 0x200
+        0xd1, 0x25, // 0x206: DRW V1, V2, 0x5
+        0x12, 0x06, // 0x208: JP 0x206
+    ];
+
+    fn lines(instruction: Instruction, quirks: &Quirks) -> Vec<String> {
+        statements(instruction, 0x300, 0x302, 0x304, quirks)
+    }
+
+    #[test]
+    fn translates_instructions() {
+        let vip = Quirks::VIP;
+        let schip = Quirks::SCHIP;
+
+        assert_eq!(
+            lines(Instruction::LdByte(1, 0x42), &vip),
+            ["state.regs[0x1] = 0x42;"]
+        );
+        assert_eq!(
+            lines(Instruction::SeByte(1, 5), &vip),
+            ["state.pc = if state.regs[0x1] == 0x5 { 0x304 } else { 0x302 };"]
+        );
+        assert_eq!(
+            lines(Instruction::Call(0x400), &vip),
+            ["runtime::call(state, 0x300, 0x400, 0x302);"]
+        );
+        assert_eq!(
+            lines(Instruction::LdK(3), &vip),
+            ["state.pc = 0x300;", "runtime::ld_k(state, 0x3);"]
+        );
+
+        assert_eq!(lines(Instruction::Or(1, 2), &vip).len(), 2);
+        assert_eq!(
+            lines(Instruction::Or(1, 2), &schip),
+            ["state.regs[0x1] |= state.regs[0x2];"]
+        );
+        assert_eq!(
+            lines(Instruction::Shr(1, 2), &vip)[0],
+            "let source = state.regs[0x2];"
+        );
+        assert_eq!(
+            lines(Instruction::Shr(1, 2), &schip)[0],
+            "let source = state.regs[0x1];"
+        );
+        assert_eq!(
+            lines(Instruction::JpV0(0x234), &vip),
+            ["state.pc = state.regs[0x0] + 0x234;"]
+        );
+        assert_eq!(
+            lines(Instruction::JpV0(0x234), &schip),
+            ["state.pc = state.regs[0x2] + 0x234;"]
+        );
+        assert_eq!(
+            lines(Instruction::Drw(1, 2, 5), &vip).last().unwrap(),
+            "state.pc = 0x302;"
+        );
+        assert_eq!(lines(Instruction::Drw(1, 2, 5), &schip).len(), 1);
+        assert_eq!(lines(Instruction::LdIX(3), &vip).len(), 2);
+        assert_eq!(lines(Instruction::LdIX(3), &schip).len(), 1);
+    }
+
+    #[test]
+    fn generates_a_program() {
+        let source = transpile(&ROM, Quirks::VIP, 1000, "test.ch8").unwrap();
+        let file = syn::parse_file(&source).unwrap();
+
+        let functions: Vec<String> = file
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                syn::Item::Fn(function) => Some(function.sig.ident.to_string()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            functions,
+            ["main", "block_200", "block_204", "block_206", "block_208"]
+        );
+
+        assert!(source.contains("const ROM: [u8; 10] = ["));
+        assert!(source.contains("const BLOCKS: [Block; 4] = ["));
+        assert!(source.contains("addrs: 0x200..0x204,"));
+        assert!(source.contains("function: block_208,"));
+        assert!(source.contains("speed: 1000,"));
+    }
+}
@@ -0,0 +1,137 @@
+//! The functions which the Rust code of `rip8 transpile` is built on. The helpers are safe
+//! wrappers of the ones which the compiled code calls.
+
+use crate::chip8::Chip8State;
+use crate::jit::fn_extern;
+use crate::quirks::Quirks;
+use crate::{Addr, Fault};
+
+use std::ops::Range;
+
+/// A ROM which was translated into Rust, run it with [crate::run_program].
+#[derive(Debug, Clone, Copy)]
+pub struct Program {
+    pub rom: &'static [u8],
+    /// The quirks the blocks were translated with.
+    pub quirks: Quirks,
+    pub speed: u64,
+    pub blocks: &'static [Block],
+}
+
+/// A block of a [Program], blocks which aren't part of it are compiled by the JIT when they're
+/// reached.
+#[derive(Debug, Clone)]
+pub struct Block {
+    pub addrs: Range<Addr>,
+    /// The amount of CHIP-8 instructions in this block.
+    pub instructions: u64,
+    /// Executes the instructions and sets pc to the next block.
+    pub function: fn(&mut Chip8State),
+}
+
+/// `00EE` at `addr`, pc stays there if the stack is empty.
+pub fn ret(state: &mut Chip8State, addr: Addr) {
+    if state.sp == 0 {
+        state.pc = addr;
+        state.fault = Fault::StackUnderflow as u64;
+        return;
+    }
+    state.sp -= 1;
+    state.pc = state.stack[state.sp as usize];
+}
+
+/// `2NNN` at `addr` which returns to `next`, pc stays at `addr` if the stack is full.
+pub fn call(state: &mut Chip8State, addr: Addr, nnn: Addr, next: Addr) {
+    if state.sp >= state.quirks.stack_limit() {
+        state.pc = addr;
+        state.fault = Fault::StackOverflow as u64;
+        return;
+    }
+    state.stack[state.sp as usize] = next;
+    state.sp += 1;
+    state.pc = nnn;
+}
+
+/// Returns `true` if the key in Vx is held.
+pub fn is_pressed(state: &Chip8State, x: u8) -> bool {
+    state.keys[(state.regs[usize::from(x)] & 0xf) as usize]
+}
+
+pub fn cls(state: &mut Chip8State) {
+    unsafe { fn_extern::cls(state) }
+}
+
+pub fn scd(state: &mut Chip8State, n: u8) {
+    unsafe { fn_extern::scd(state, n.into()) }
+}
+
+pub fn scu(state: &mut Chip8State, n: u8) {
+    unsafe { fn_extern::scu(state, n.into()) }
+}
+
+pub fn scr(state: &mut Chip8State) {
+    unsafe { fn_extern::scr(state) }
+}
+
+pub fn scl(state: &mut Chip8State) {
+    unsafe { fn_extern::scl(state) }
+}
+
+pub fn exit(state: &mut Chip8State) {
+    unsafe { fn_extern::exit(state) }
+}
+
+pub fn low(state: &mut Chip8State) {
+    unsafe { fn_extern::low(state) }
+}
+
+pub fn high(state: &mut Chip8State) {
+    unsafe { fn_extern::high(state) }
+}
+
+pub fn drw(state: &mut Chip8State, x: u8, y: u8, n: u8) {
+    unsafe { fn_extern::drw(state, x.into(), y.into(), n.into()) }
+}
+
+/// `FX0A`, pc has to be at this instruction. It only moves on once a key got pressed.
+pub fn ld_k(state: &mut Chip8State, x: u8) {
+    unsafe { fn_extern::ld_k(state, x.into()) }
+}
+
+pub fn rnd(state: &mut Chip8State, x: u8, kk: u8) {
+    unsafe { fn_extern::rnd(state, x.into(), kk.into()) }
+}
+
+pub fn ld_f(state: &mut Chip8State, x: u8) {
+    unsafe { fn_extern::ld_f(state, x.into()) }
+}
+
+pub fn ld_hf(state: &mut Chip8State, x: u8) {
+    unsafe { fn_extern::ld_hf(state, x.into()) }
+}
+
+pub fn ld_b(state: &mut Chip8State, x: u8) {
+    unsafe { fn_extern::ld_b(state, x.into()) }
+}
+
+/// Stores the registers from Vx to Vy at I, without changing I.
+pub fn ld_i_range(state: &mut Chip8State, x: u8, y: u8) {
+    unsafe { fn_extern::ld_i_range(state, x.into(), y.into()) }
+}
+
+/// Loads the registers from Vx to Vy from I, without changing I.
+pub fn ld_range_i(state: &mut Chip8State, x: u8, y: u8) {
+    unsafe { fn_extern::ld_range_i(state, x.into(), y.into()) }
+}
+
+pub fn audio(state: &mut Chip8State) {
+    unsafe { fn_extern::audio(state) }
+}
+
+pub fn ld_r_x(state: &mut Chip8State, x: u8) {
+    unsafe { fn_extern::ld_r_x(state, x.into()) }
+}
+
+pub fn ld_x_r(state: &mut Chip8State, x: u8) {
+    unsafe { fn_extern::ld_x_r(state, x.into()) }
+}